@@ -1,7 +1,9 @@
-use egui_wlr_layer::{Anchor, InputRegions, KeyboardInteractivity, Layer, LayerAppOpts};
+use egui_wlr_layer::{
+    Anchor, InputRegions, KeyboardInteractivity, Layer, LayerAppOpts, LayerFrame, LayerSetup,
+};
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut context = egui_wlr_layer::Context::new();
+    let mut context = egui_wlr_layer::Context::new()?;
 
     context.new_layer_app(
         Box::new(DemoApp::default()),
@@ -10,11 +12,22 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
             namespace: Some("egui-demo"),
             output: None,
             input_regions: InputRegions::WindowsOnly,
+            ..Default::default()
         },
-    );
+    )?;
 
     loop {
-        context.blocking_dispatch().unwrap();
+        match context.blocking_dispatch() {
+            Ok(_) => {}
+            // Wait for the compositor to come back after it crashed or restarted
+            Err(egui_wlr_layer::Error::Disconnected(_)) => {
+                while let Err(e) = context.reconnect() {
+                    println!("Failed to reconnect: {e}");
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
 }
 
@@ -24,11 +37,11 @@ struct DemoApp {
 }
 
 impl egui_wlr_layer::App for DemoApp {
-    fn update(&mut self, ctx: &egui::Context) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut LayerFrame) {
         self.demo.ui(ctx);
     }
 
-    fn on_init(&mut self, layer: &smithay_client_toolkit::shell::wlr_layer::LayerSurface) {
+    fn on_init(&mut self, layer: &LayerSetup) {
         layer.set_anchor(Anchor::all());
         layer.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
     }
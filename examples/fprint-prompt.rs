@@ -1,13 +1,9 @@
-use std::{
-    io::{BufRead as _, BufReader},
-    process::{Command, Stdio},
-    time::Duration,
-};
-
 use egui::{Color32, FontId, LayerId, Rect, pos2, text::LayoutJob};
 use egui_wlr_layer::{
-    Anchor, InputRegions, KeyboardInteractivity, Layer, LayerAppOpts, LayerSurface, OutputInfo,
+    Anchor, InputRegions, KeyboardInteractivity, Layer, LayerAppOpts, LayerFrame, LayerSetup,
+    OutputSelector, forward_dbus_signals,
 };
+use zbus::{MatchRule, message::Type};
 
 struct PositionInfo {
     thickness: u32,
@@ -63,86 +59,59 @@ impl PositionInfo {
 }
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // monitor for fprint events
-    let monitor_proc = Command::new("dbus-monitor")
-        .args(["--system", "interface='net.reactivated.Fprint.Device'"])
-        .stdout(Stdio::piped())
-        .spawn()?;
-
-    let monitor_stdout = BufReader::new(monitor_proc.stdout.unwrap());
-
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    std::thread::spawn(move || {
-        for line in monitor_stdout.lines() {
-            let line = line.expect("Failed to read line from dbus-monitor");
-
-            if line.contains("member=VerifyFingerSelected") {
-                // fprint is waiting for a finger touch
-
-                tx.send(true);
-            }
-
-            if line.contains("member=VerifyStatus") {
-                // fprint is no longer waiting for a finger touch
-
-                tx.send(false);
+    let mut context = egui_wlr_layer::Context::new()?;
+
+    // Stays mapped, but only draws something while fprint is waiting for a finger touch
+    let layer_app = context.new_layer_app(Box::new(FprintPromptApp::default()), LayerAppOpts {
+        layer: Layer::Overlay,
+        namespace: Some("fprint-prompt"),
+        output: Some(OutputSelector::by_name("eDP-1")),
+        input_regions: InputRegions::None,
+        ..Default::default()
+    })?;
+
+    let connection = zbus::blocking::Connection::system()?;
+    let rule = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface("net.reactivated.Fprint.Device")?
+        .build();
+    forward_dbus_signals(&layer_app, &connection, rule, |app: &mut FprintPromptApp, message| {
+        match message.header().member().map(|member| member.as_str()) {
+            // fprint is waiting for a finger touch
+            Some("VerifyFingerSelected") => {
+                app.waiting = true;
+                app.shown_at = None;
             }
+            // fprint is no longer waiting for a finger touch
+            Some("VerifyStatus") => app.waiting = false,
+            _ => {}
         }
-
-        panic!("dbus monitor thread exited");
-    });
-
-    let mut context = egui_wlr_layer::Context::new();
-
-    let mut layer_app = None;
+    })?;
 
     loop {
-        match context.poll_dispatch() {
-            Ok(0) => {}
-            Ok(n) => {
-                println!("handled {} events", n);
-            }
-            Err(e) => {
-                eprintln!("Error polling events: {}", e);
-                break Err(e.into());
-            }
-        }
-
-        if let Ok(waiting) = rx.recv_timeout(Duration::from_millis(1)) {
-            if waiting {
-                // fprint is waiting for a finger touch
-                layer_app = Some(context.new_layer_app(
-                    Box::new(FprintPromptApp),
-                    LayerAppOpts {
-                        layer: Layer::Overlay,
-                        namespace: Some("fprint-prompt"),
-                        output: Some(&|info: OutputInfo| {
-                            dbg!(info.name) == Some("eDP-1".to_string())
-                        }),
-                        input_regions: InputRegions::None,
-                    },
-                ));
-            } else {
-                // fprint is no longer waiting for a finger touch
-                if let Some(handle) = layer_app.take() {
-                    handle.exit();
-                }
-            }
-        }
+        context.blocking_dispatch()?;
     }
 }
 
-struct FprintPromptApp;
+#[derive(Default)]
+struct FprintPromptApp {
+    waiting: bool,
+    /// When the prompt was first drawn, to start its animation.
+    shown_at: Option<f64>,
+}
 
 impl egui_wlr_layer::App for FprintPromptApp {
-    fn update(&mut self, ctx: &egui::Context) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut LayerFrame) {
         ctx.style_mut(|s| {
             s.visuals.panel_fill = Color32::TRANSPARENT;
             s.visuals.widgets.noninteractive.fg_stroke = (1., Color32::WHITE).into();
         });
 
-        let time = ctx.input(|i| i.time) as f32;
+        if !self.waiting {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        let time = (now - *self.shown_at.get_or_insert(now)) as f32;
         let wiggle = text_animation(time);
 
         let painter = ctx.layer_painter(LayerId::background());
@@ -180,7 +149,7 @@ impl egui_wlr_layer::App for FprintPromptApp {
         ctx.request_repaint();
     }
 
-    fn on_init(&mut self, layer: &LayerSurface) {
+    fn on_init(&mut self, layer: &LayerSetup) {
         layer.set_anchor(POS.edge | POS.close_to);
         layer.set_size(POS.win_width(), POS.win_height());
         layer.set_keyboard_interactivity(KeyboardInteractivity::None);
@@ -0,0 +1,125 @@
+use std::{collections::HashMap, sync::mpsc, time::Duration};
+
+use egui_wlr_layer::{
+    Anchor, App, CloseReason, Expiry, Layer, LayerAppHandle, LayerAppOpts, LayerFrame, LayerSetup,
+    Notification, NotificationEvent, NotificationServer, NotificationServerOpts, Urgency,
+};
+
+const WIDTH: u32 = 360;
+const HEIGHT: u32 = 90;
+const GAP: u32 = 10;
+
+/// Shows each notification on a layer surface of its own, stacked in the top right corner.
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut context = egui_wlr_layer::Context::new()?;
+
+    let (tx, rx) = mpsc::channel();
+    let server = NotificationServer::new(NotificationServerOpts::default(), move |event| {
+        let _ = tx.send(event);
+    })?;
+
+    let mut shown = HashMap::<_, LayerAppHandle>::new();
+
+    loop {
+        context.poll_dispatch()?;
+
+        let Ok(event) = rx.recv_timeout(Duration::from_millis(10)) else {
+            continue;
+        };
+        shown.retain(|_, handle| !handle.is_closed());
+
+        match event {
+            NotificationEvent::Notify(notification) => {
+                let id = notification.id;
+                let timeout = match notification.expiry {
+                    Expiry::Default if notification.urgency == Urgency::Critical => None,
+                    Expiry::Default => Some(Duration::from_secs(5)),
+                    Expiry::Never => None,
+                    Expiry::After(timeout) => Some(timeout),
+                };
+
+                let handle = match shown.get(&id) {
+                    Some(handle) => {
+                        handle.send(move |app: &mut NotificationApp| {
+                            app.notification = notification;
+                        });
+                        handle.clone()
+                    }
+                    None => {
+                        let app = NotificationApp {
+                            notification,
+                            server: server.clone(),
+                            slot: shown.len() as u32,
+                        };
+                        let handle = context.new_layer_app(Box::new(app), LayerAppOpts {
+                            layer: Layer::Overlay,
+                            namespace: Some("notification"),
+                            ..Default::default()
+                        })?;
+
+                        // Does nothing if it was dismissed or closed by its app before
+                        let server = server.clone();
+                        handle.on_close(move || server.close(id, CloseReason::Expired));
+                        shown.insert(id, handle.clone());
+                        handle
+                    }
+                };
+
+                if let Some(timeout) = timeout {
+                    handle.close_after(timeout);
+                }
+            }
+            NotificationEvent::Closed(id) => {
+                if let Some(handle) = shown.remove(&id) {
+                    handle.exit();
+                }
+            }
+        }
+    }
+}
+
+struct NotificationApp {
+    notification: Notification,
+    server: NotificationServer,
+    /// The position in the stack of notifications.
+    slot: u32,
+}
+
+impl App for NotificationApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut LayerFrame) {
+        let Notification {
+            id, app_name, summary, body, actions, ..
+        } = &self.notification;
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong(summary);
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("✖").clicked() {
+                        self.server.close(*id, CloseReason::Dismissed);
+                        frame.exit();
+                    }
+                    ui.weak(app_name);
+                });
+            });
+            ui.label(body);
+
+            ui.horizontal(|ui| {
+                for (key, label) in actions {
+                    if ui.button(label).clicked() {
+                        self.server.invoke_action(*id, key);
+                        self.server.close(*id, CloseReason::Dismissed);
+                        frame.exit();
+                    }
+                }
+            });
+        });
+    }
+
+    fn on_init(&mut self, layer: &LayerSetup) {
+        layer.set_anchor(Anchor::TOP | Anchor::RIGHT);
+        layer.set_size(WIDTH, HEIGHT);
+        let top = GAP + self.slot * (HEIGHT + GAP);
+        layer.set_margin(top as i32, GAP as i32, 0, 0);
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use egui_wlr_layer::{Anchor, App, LayerAppHandle, LayerAppOpts, LayerFrame, LayerSetup};
+
+/// Runs the context on a thread of its own, while the main thread goes on with other work and
+/// controls the app through its handle.
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (tx, rx) = mpsc::channel::<LayerAppHandle>();
+    let seconds = Arc::new(AtomicU32::new(5));
+    let app = CountdownApp { seconds: seconds.clone() };
+
+    let worker = thread::spawn(move || -> Result<(), egui_wlr_layer::Error> {
+        let mut context = egui_wlr_layer::Context::new()?;
+        let handle = context.new_layer_app(Box::new(app), LayerAppOpts {
+            namespace: Some("worker-thread"),
+            ..Default::default()
+        })?;
+        let _ = tx.send(handle.clone());
+
+        // Exiting through the handle wakes up the dispatch, so this ends once the app is gone
+        while !handle.is_closed() {
+            context.blocking_dispatch()?;
+        }
+
+        Ok(())
+    });
+
+    let handle = rx.recv()?;
+    while seconds.load(Ordering::Relaxed) > 0 {
+        thread::sleep(Duration::from_secs(1));
+        seconds.fetch_sub(1, Ordering::Relaxed);
+        handle.request_repaint();
+    }
+    handle.exit();
+
+    worker.join().expect("worker thread panicked")?;
+    Ok(())
+}
+
+struct CountdownApp {
+    seconds: Arc<AtomicU32>,
+}
+
+impl App for CountdownApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut LayerFrame) {
+        let seconds = self.seconds.load(Ordering::Relaxed);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(format!("Closing in {seconds}…"));
+        });
+    }
+
+    fn on_init(&mut self, layer: &LayerSetup) {
+        layer.set_anchor(Anchor::TOP | Anchor::RIGHT);
+        layer.set_size(300, 80);
+    }
+}
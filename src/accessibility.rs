@@ -0,0 +1,84 @@
+//! Exposing apps to screen readers over AT-SPI, with the `accesskit` feature.
+
+use std::sync::{Arc, Mutex};
+
+use accesskit_unix::Adapter;
+use egui::accesskit::{
+    ActionHandler, ActionRequest, ActivationHandler, DeactivationHandler, Rect, TreeUpdate,
+};
+
+/// An app's accessibility tree, which egui only builds while a screen reader is listening.
+pub(crate) struct Accessibility {
+    adapter: Adapter,
+    app_id: String,
+}
+
+/// Called from AccessKit's thread when screen readers come and go, or act on a widget.
+struct Handler {
+    egui_context: egui::Context,
+    /// The app's input for the next frame.
+    pending_events: Arc<Mutex<Vec<egui::Event>>>,
+}
+
+impl Accessibility {
+    pub fn new(
+        app_id: &str,
+        egui_context: &egui::Context,
+        pending_events: Arc<Mutex<Vec<egui::Event>>>,
+    ) -> Self {
+        let handler = || Handler {
+            egui_context: egui_context.clone(),
+            pending_events: pending_events.clone(),
+        };
+
+        Self {
+            adapter: Adapter::new(handler(), handler(), handler()),
+            app_id: app_id.to_owned(),
+        }
+    }
+
+    /// Pass on the tree of the frame that was just run, which egui sends in full every frame.
+    pub fn update(&mut self, update: &TreeUpdate, size: (u32, u32)) {
+        let bounds = Rect::new(0., 0., f64::from(size.0), f64::from(size.1));
+        // Layer surfaces don't know where they are on the output, so they're placed at its origin
+        self.adapter.set_root_window_bounds(bounds, bounds);
+        self.adapter.update_if_active(|| {
+            let mut update = update.clone();
+            if let Some(tree) = &mut update.tree {
+                tree.app_name = Some(self.app_id.clone());
+                tree.toolkit_name = Some(env!("CARGO_PKG_NAME").to_owned());
+                tree.toolkit_version = Some(env!("CARGO_PKG_VERSION").to_owned());
+            }
+            update
+        });
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.adapter.update_window_focus_state(focused);
+    }
+}
+
+impl ActivationHandler for Handler {
+    fn request_initial_tree(&mut self) -> Option<TreeUpdate> {
+        // The tree is sent with the next frame
+        self.egui_context.enable_accesskit();
+        self.egui_context.request_repaint();
+        None
+    }
+}
+
+impl ActionHandler for Handler {
+    fn do_action(&mut self, request: ActionRequest) {
+        self.pending_events
+            .lock()
+            .unwrap()
+            .push(egui::Event::AccessKitActionRequest(request));
+        self.egui_context.request_repaint();
+    }
+}
+
+impl DeactivationHandler for Handler {
+    fn deactivate_accessibility(&mut self) {
+        self.egui_context.disable_accesskit();
+    }
+}
@@ -0,0 +1,851 @@
+//! Screen capture into egui textures through ext-image-copy-capture or wlr-screencopy, eg. for
+//! color pickers, magnifiers, live thumbnails or blurred backdrops.
+
+use std::{
+    fmt, io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use egui::Rect;
+use egui_wgpu::RenderState;
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::{
+                wl_buffer::WlBuffer,
+                wl_callback::{self, WlCallback},
+                wl_output::WlOutput,
+                wl_shm,
+            },
+        },
+        protocols::ext::{
+            image_capture_source::v1::client::{
+                ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+                ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+            },
+            image_copy_capture::v1::client::{
+                ext_image_copy_capture_frame_v1::{
+                    self, ExtImageCopyCaptureFrameV1, FailureReason,
+                },
+                ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
+                ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+            },
+        },
+        protocols_wlr::screencopy::v1::client::{
+            zwlr_screencopy_frame_v1::{self, Flags, ZwlrScreencopyFrameV1},
+            zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        },
+    },
+    shm::raw::RawPool,
+};
+
+use super::ContextDelegate;
+
+/// A captured frame. Free the texture with [`egui_wgpu::Renderer::free_texture`] once it's no
+/// longer shown.
+#[derive(Debug)]
+pub struct Capture {
+    pub texture: wgpu::Texture,
+    /// The texture, registered with the renderer.
+    pub texture_id: egui::TextureId,
+    /// In pixels.
+    pub size: [u32; 2],
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The compositor couldn't capture the output.
+    Failed,
+    /// None of the buffer formats the compositor offered are supported.
+    UnsupportedFormat,
+    /// The compositor can't capture regions of outputs.
+    UnsupportedRegion,
+    Io(io::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Failed => write!(f, "the compositor failed to capture the output"),
+            CaptureError::UnsupportedFormat => write!(f, "no supported buffer format"),
+            CaptureError::UnsupportedRegion => write!(f, "capturing regions is not supported"),
+            CaptureError::Io(e) => write!(f, "failed to create the buffer: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// Receives the captured frame.
+pub type CaptureCallback = Box<dyn FnOnce(Result<Capture, CaptureError>) + Send>;
+
+/// Receives every frame of a continuous capture. The texture is reused between frames and freed
+/// once the capture ends. After an error, the capture has ended.
+pub type ContinuousCaptureCallback = Box<dyn FnMut(Result<&Capture, CaptureError>) + Send>;
+
+/// Handle to capture the outputs of a [`Context`](crate::Context).
+#[derive(Clone)]
+pub struct Screencopy {
+    ext: Option<ExtCapture>,
+    wlr: Option<ZwlrScreencopyManagerV1>,
+    queue_handle: QueueHandle<ContextDelegate>,
+    wayland_conn: Connection,
+}
+
+#[derive(Clone)]
+struct ExtCapture {
+    manager: ExtImageCopyCaptureManagerV1,
+    sources: ExtOutputImageCaptureSourceManagerV1,
+}
+
+/// A running continuous capture, see [`Screencopy::capture_continuous`].
+#[derive(Clone)]
+pub struct CaptureSession {
+    data: Arc<CaptureData>,
+    wayland_conn: Connection,
+}
+
+impl CaptureSession {
+    /// Stop capturing right away, which frees the texture. The callback won't get any more
+    /// frames.
+    pub fn stop(&self) {
+        self.data.end(Ok(()));
+        let _ = self.wayland_conn.flush();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.data.stopped.load(Ordering::Relaxed)
+    }
+}
+
+impl Screencopy {
+    /// Capture the output, or a region of it in logical coordinates, and register it as a
+    /// texture with the renderer. `callback` is called from the event loop once it's done.
+    ///
+    /// Regions can only be captured through wlr-screencopy.
+    pub fn capture(
+        &self,
+        output: &WlOutput,
+        region: Option<Rect>,
+        overlay_cursor: bool,
+        render_state: &RenderState,
+        callback: impl FnOnce(Result<Capture, CaptureError>) + Send + 'static,
+    ) {
+        let callback = CaptureCallbackKind::Once(Box::new(callback));
+        self.start(output, region, overlay_cursor, render_state, callback);
+    }
+
+    /// Keep capturing the output, or a region of it in logical coordinates. A new frame is only
+    /// captured once the output changed, so this is cheap enough for live thumbnails and
+    /// magnifiers. `callback` is called from the event loop for every frame.
+    ///
+    /// Regions can only be captured through wlr-screencopy.
+    pub fn capture_continuous(
+        &self,
+        output: &WlOutput,
+        region: Option<Rect>,
+        overlay_cursor: bool,
+        render_state: &RenderState,
+        callback: impl FnMut(Result<&Capture, CaptureError>) + Send + 'static,
+    ) -> CaptureSession {
+        let callback = CaptureCallbackKind::Continuous(Box::new(callback));
+        let data = self.start(output, region, overlay_cursor, render_state, callback);
+        CaptureSession {
+            data,
+            wayland_conn: self.wayland_conn.clone(),
+        }
+    }
+
+    fn start(
+        &self,
+        output: &WlOutput,
+        region: Option<Rect>,
+        overlay_cursor: bool,
+        render_state: &RenderState,
+        callback: CaptureCallbackKind,
+    ) -> Arc<CaptureData> {
+        let region = region.map(|region| {
+            let (min, size) = (region.min.round(), region.size().round());
+            [min.x as i32, min.y as i32, size.x as i32, size.y as i32]
+        });
+
+        // Prefer ext-image-copy-capture, unless a region is requested
+        let backend = match (&self.ext, &self.wlr, region) {
+            (Some(_), _, None) => Backend::Ext,
+            (_, Some(_), _) => Backend::Wlr,
+            _ => Backend::Ext,
+        };
+
+        let data = Arc::new(CaptureData {
+            render_state: render_state.clone(),
+            callback: Mutex::new(Some(callback)),
+            state: Mutex::default(),
+            stopped: AtomicBool::new(false),
+            backend,
+            output: output.clone(),
+            region,
+            overlay_cursor,
+        });
+
+        match (backend, &self.ext) {
+            (Backend::Ext, Some(ext)) if region.is_none() => {
+                let source = ext
+                    .sources
+                    .create_source(output, &self.queue_handle, GlobalData);
+                let options = match overlay_cursor {
+                    true => Options::PaintCursors,
+                    false => Options::empty(),
+                };
+                let session =
+                    ext.manager
+                        .create_session(&source, options, &self.queue_handle, data.clone());
+                data.state.lock().unwrap().session = Some((source, session));
+            }
+            // The callback is only ever called from the event loop, so it fails once the
+            // compositor answers
+            (Backend::Ext, _) => {
+                self.wayland_conn
+                    .display()
+                    .sync(&self.queue_handle, data.clone());
+            }
+            (Backend::Wlr, _) => {
+                if let Some(manager) = &self.wlr {
+                    data.capture_wlr(manager, &self.queue_handle);
+                }
+            }
+        }
+
+        let _ = self.wayland_conn.flush();
+        data
+    }
+}
+
+pub(crate) struct ScreencopyState {
+    handle: Screencopy,
+}
+
+impl ScreencopyState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> Result<Self, BindError> {
+        let ext = globals
+            .bind(queue_handle, 1..=1, GlobalData)
+            .and_then(|manager| {
+                Ok(ExtCapture {
+                    manager,
+                    sources: globals.bind(queue_handle, 1..=1, GlobalData)?,
+                })
+            });
+        let wlr = globals.bind(queue_handle, 1..=3, GlobalData);
+        let (ext, wlr) = match (ext, wlr) {
+            (Err(_), Err(e)) => return Err(e),
+            (ext, wlr) => (ext.ok(), wlr.ok()),
+        };
+
+        Ok(Self {
+            handle: Screencopy {
+                ext,
+                wlr,
+                queue_handle: queue_handle.clone(),
+                wayland_conn,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> &Screencopy {
+        &self.handle
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Backend {
+    Ext,
+    Wlr,
+}
+
+enum CaptureCallbackKind {
+    Once(CaptureCallback),
+    Continuous(ContinuousCaptureCallback),
+}
+
+/// The frame that's being captured, destroyed before its buffer when the capture ends.
+enum CaptureFrame {
+    Wlr(ZwlrScreencopyFrameV1),
+    Ext(ExtImageCopyCaptureFrameV1),
+}
+
+struct CaptureData {
+    render_state: RenderState,
+    /// Taken while it's called, so it can stop the capture.
+    callback: Mutex<Option<CaptureCallbackKind>>,
+    state: Mutex<CaptureState>,
+    stopped: AtomicBool,
+    backend: Backend,
+    // For capturing the next frame through wlr-screencopy
+    output: WlOutput,
+    region: Option<[i32; 4]>,
+    overlay_cursor: bool,
+}
+
+#[derive(Default)]
+struct CaptureState {
+    /// The first supported buffer format the compositor offered for the next frame.
+    offered: Option<BufferInfo>,
+    /// The buffer that's copied into, reused between frames while the format stays the same.
+    copy: Option<(BufferInfo, RawPool, WlBuffer)>,
+    frame: Option<CaptureFrame>,
+    y_invert: bool,
+    /// The frame size the compositor sent last, for ext-image-copy-capture.
+    buffer_size: Option<(u32, u32)>,
+    /// Whether any frame was copied yet, to only wait for damage after the first one.
+    copied: bool,
+    /// Whether a frame is being captured through ext-image-copy-capture.
+    capturing: bool,
+    /// The last frame of a continuous capture.
+    capture: Option<Capture>,
+    session: Option<(ExtImageCaptureSourceV1, ExtImageCopyCaptureSessionV1)>,
+    ended: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct BufferInfo {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+fn supported_format(format: WEnum<wl_shm::Format>) -> Option<wl_shm::Format> {
+    match format {
+        WEnum::Value(
+            format @ (wl_shm::Format::Argb8888
+            | wl_shm::Format::Xrgb8888
+            | wl_shm::Format::Abgr8888
+            | wl_shm::Format::Xbgr8888),
+        ) => Some(format),
+        _ => None,
+    }
+}
+
+impl CaptureData {
+    fn capture_wlr(
+        self: &Arc<Self>,
+        manager: &ZwlrScreencopyManagerV1,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) {
+        let overlay_cursor = self.overlay_cursor as i32;
+        let frame = match self.region {
+            Some([x, y, width, height]) => manager.capture_output_region(
+                overlay_cursor,
+                &self.output,
+                x,
+                y,
+                width,
+                height,
+                queue_handle,
+                self.clone(),
+            ),
+            None => {
+                manager.capture_output(overlay_cursor, &self.output, queue_handle, self.clone())
+            }
+        };
+        self.state.lock().unwrap().frame = Some(CaptureFrame::Wlr(frame));
+    }
+
+    /// Hand the copied frame to the callback. One-shot captures end here.
+    fn frame_ready(&self) {
+        if self.stopped.load(Ordering::Relaxed) {
+            self.end(Ok(()));
+            return;
+        }
+        if let Err(e) = self.upload() {
+            self.end(Err(e));
+            return;
+        }
+
+        // Neither lock is held while the callback runs, so it can stop the capture
+        let Some(capture) = self.state.lock().unwrap().capture.take() else {
+            return;
+        };
+        let callback = self.callback.lock().unwrap().take();
+        match callback {
+            Some(CaptureCallbackKind::Once(callback)) => {
+                callback(Ok(capture));
+                self.end(Ok(()));
+            }
+            Some(CaptureCallbackKind::Continuous(mut callback)) => {
+                callback(Ok(&capture));
+                let mut state = self.state.lock().unwrap();
+                if state.ended {
+                    drop(state);
+                    self.free_texture(&capture);
+                } else {
+                    state.capture = Some(capture);
+                    drop(state);
+                    *self.callback.lock().unwrap() =
+                        Some(CaptureCallbackKind::Continuous(callback));
+                }
+            }
+            None => self.free_texture(&capture),
+        }
+    }
+
+    fn free_texture(&self, capture: &Capture) {
+        let mut renderer = self.render_state.renderer.write();
+        renderer.free_texture(&capture.texture_id);
+    }
+
+    /// Destroy everything, and report the error if there is one.
+    fn end(&self, result: Result<(), CaptureError>) {
+        let capture = {
+            let mut state = self.state.lock().unwrap();
+            if state.ended {
+                return;
+            }
+            state.ended = true;
+            match state.frame.take() {
+                Some(CaptureFrame::Wlr(frame)) => frame.destroy(),
+                Some(CaptureFrame::Ext(frame)) => frame.destroy(),
+                None => {}
+            }
+            if let Some((source, session)) = state.session.take() {
+                session.destroy();
+                source.destroy();
+            }
+            if let Some((_, _, buffer)) = state.copy.take() {
+                buffer.destroy();
+            }
+            state.capture.take()
+        };
+        self.stopped.store(true, Ordering::Relaxed);
+
+        if let Some(capture) = capture {
+            self.free_texture(&capture);
+        }
+
+        let callback = self.callback.lock().unwrap().take();
+        match (callback, result) {
+            (Some(CaptureCallbackKind::Once(callback)), Err(e)) => callback(Err(e)),
+            (Some(CaptureCallbackKind::Continuous(mut callback)), Err(e)) => callback(Err(e)),
+            _ => {}
+        }
+    }
+
+    /// Read the copied frame into a texture, reusing the last one if the size didn't change.
+    fn upload(&self) -> Result<(), CaptureError> {
+        let mut state = self.state.lock().unwrap();
+        let y_invert = state.y_invert;
+        let Some((info, pool, _)) = &mut state.copy else {
+            return Err(CaptureError::Failed);
+        };
+        let info = *info;
+
+        let (width, height, stride) =
+            (info.width as usize, info.height as usize, info.stride as usize);
+        let data = &pool.mmap()[..];
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let y = if y_invert { height - 1 - y } else { y };
+            let row = &data[y * stride..][..width * 4];
+            for pixel in row.chunks_exact(4) {
+                // The formats are little endian, so ARGB is stored as BGRA
+                pixels.extend_from_slice(&match info.format {
+                    wl_shm::Format::Argb8888 => [pixel[2], pixel[1], pixel[0], pixel[3]],
+                    wl_shm::Format::Xrgb8888 => [pixel[2], pixel[1], pixel[0], 0xff],
+                    wl_shm::Format::Abgr8888 => [pixel[0], pixel[1], pixel[2], pixel[3]],
+                    _ => [pixel[0], pixel[1], pixel[2], 0xff],
+                });
+            }
+        }
+
+        let device = &self.render_state.device;
+        let size = wgpu::Extent3d {
+            width: info.width,
+            height: info.height,
+            depth_or_array_layers: 1,
+        };
+
+        if state
+            .capture
+            .as_ref()
+            .is_some_and(|capture| capture.size != [info.width, info.height])
+        {
+            let capture = state.capture.take().unwrap();
+            self.free_texture(&capture);
+        }
+
+        let capture = state.capture.get_or_insert_with(|| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("egui_capture_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&Default::default());
+            let texture_id = self.render_state.renderer.write().register_native_texture(
+                device,
+                &view,
+                wgpu::FilterMode::Linear,
+            );
+
+            Capture {
+                texture,
+                texture_id,
+                size: [info.width, info.height],
+            }
+        });
+
+        self.render_state.queue.write_texture(
+            capture.texture.as_image_copy(),
+            &pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(info.width * 4),
+                rows_per_image: Some(info.height),
+            },
+            size,
+        );
+
+        Ok(())
+    }
+}
+
+impl ContextDelegate {
+    /// Get a buffer in the offered format, creating a new one if it changed.
+    fn capture_buffer(
+        &self,
+        qh: &QueueHandle<Self>,
+        data: &CaptureData,
+    ) -> Result<WlBuffer, CaptureError> {
+        let mut state = data.state.lock().unwrap();
+        let Some(info) = state.offered else {
+            return Err(CaptureError::UnsupportedFormat);
+        };
+
+        if let Some((copy_info, _, buffer)) = &state.copy {
+            if *copy_info == info {
+                return Ok(buffer.clone());
+            }
+        }
+        if let Some((_, _, buffer)) = state.copy.take() {
+            buffer.destroy();
+        }
+
+        let mut pool = RawPool::new((info.stride * info.height) as usize, &self.shm)
+            .map_err(|e| CaptureError::Io(io::Error::other(e)))?;
+        let buffer = pool.create_buffer(
+            0,
+            info.width as i32,
+            info.height as i32,
+            info.stride as i32,
+            info.format,
+            GlobalData,
+            qh,
+        );
+
+        state.copy = Some((info, pool, buffer.clone()));
+        Ok(buffer)
+    }
+
+    /// Ask the compositor to copy a wlr-screencopy frame into our buffer. After the first frame
+    /// of a continuous capture, the copy waits until the output is damaged.
+    fn copy_wlr_frame(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        frame: &ZwlrScreencopyFrameV1,
+        data: &CaptureData,
+    ) {
+        let buffer = match self.capture_buffer(qh, data) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                frame.destroy();
+                data.end(Err(e));
+                return;
+            }
+        };
+
+        let copied = std::mem::replace(&mut data.state.lock().unwrap().copied, true);
+        if copied && frame.version() >= 2 {
+            frame.copy_with_damage(&buffer);
+        } else {
+            frame.copy(&buffer);
+        }
+    }
+
+    /// Capture the next frame of an ext-image-copy-capture session. The compositor holds it back
+    /// until the output is damaged.
+    fn capture_ext_frame(&mut self, qh: &QueueHandle<Self>, data: &Arc<CaptureData>) {
+        if data.stopped.load(Ordering::Relaxed) {
+            data.end(Ok(()));
+            return;
+        }
+
+        let buffer = match self.capture_buffer(qh, data) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                data.end(Err(e));
+                return;
+            }
+        };
+
+        let mut state = data.state.lock().unwrap();
+        let Some((_, session)) = &state.session else {
+            return;
+        };
+        let frame = session.create_frame(qh, data.clone());
+        frame.attach_buffer(&buffer);
+        frame.damage_buffer(0, 0, i32::MAX, i32::MAX);
+        frame.capture();
+        state.frame = Some(CaptureFrame::Ext(frame));
+        state.capturing = true;
+    }
+
+    /// Start on the next frame of a continuous capture.
+    fn capture_next_frame(&mut self, qh: &QueueHandle<Self>, data: &Arc<CaptureData>) {
+        if data.state.lock().unwrap().ended {
+            return;
+        }
+        if data.stopped.load(Ordering::Relaxed) {
+            data.end(Ok(()));
+            return;
+        }
+
+        match data.backend {
+            Backend::Ext => self.capture_ext_frame(qh, data),
+            Backend::Wlr => {
+                let manager = self
+                    .screencopy
+                    .as_ref()
+                    .and_then(|screencopy| screencopy.handle.wlr.clone());
+                if let Some(manager) = manager {
+                    data.state.lock().unwrap().offered = None;
+                    data.capture_wlr(&manager, qh);
+                }
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrScreencopyManagerV1, GlobalData, ContextDelegate> for ScreencopyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwlrScreencopyManagerV1,
+        _: <ZwlrScreencopyManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, Arc<CaptureData>, ContextDelegate> for ScreencopyState {
+    fn event(
+        state: &mut ContextDelegate,
+        frame: &ZwlrScreencopyFrameV1,
+        event: <ZwlrScreencopyFrameV1 as Proxy>::Event,
+        data: &Arc<CaptureData>,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer { format, width, height, stride } => {
+                if let Some(format) = supported_format(format) {
+                    let mut capture_state = data.state.lock().unwrap();
+                    if capture_state.offered.is_none() {
+                        capture_state.offered = Some(BufferInfo { format, width, height, stride });
+                    }
+                }
+
+                // Before version 3 there's only one buffer event, and no buffer_done
+                if frame.version() < 3 {
+                    state.copy_wlr_frame(qh, frame, data);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => state.copy_wlr_frame(qh, frame, data),
+            zwlr_screencopy_frame_v1::Event::Flags { flags: WEnum::Value(flags) } => {
+                data.state.lock().unwrap().y_invert = flags.contains(Flags::YInvert);
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                frame.destroy();
+                data.state.lock().unwrap().frame = None;
+                data.frame_ready();
+                state.capture_next_frame(qh, data);
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                frame.destroy();
+                data.state.lock().unwrap().frame = None;
+                data.end(Err(CaptureError::Failed));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureManagerV1, GlobalData, ContextDelegate> for ScreencopyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ExtImageCopyCaptureManagerV1,
+        _: <ExtImageCopyCaptureManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<ExtOutputImageCaptureSourceManagerV1, GlobalData, ContextDelegate>
+    for ScreencopyState
+{
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ExtOutputImageCaptureSourceManagerV1,
+        _: <ExtOutputImageCaptureSourceManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<ExtImageCaptureSourceV1, GlobalData, ContextDelegate> for ScreencopyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ExtImageCaptureSourceV1,
+        _: <ExtImageCaptureSourceV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, Arc<CaptureData>, ContextDelegate> for ScreencopyState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ExtImageCopyCaptureSessionV1,
+        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
+        data: &Arc<CaptureData>,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                let mut capture_state = data.state.lock().unwrap();
+                capture_state.buffer_size = Some((width, height));
+                capture_state.offered = None;
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat { format } => {
+                let mut capture_state = data.state.lock().unwrap();
+                if let (None, Some(format), Some((width, height))) =
+                    (capture_state.offered, supported_format(format), capture_state.buffer_size)
+                {
+                    let stride = width * 4;
+                    capture_state.offered = Some(BufferInfo { format, width, height, stride });
+                }
+            }
+            ext_image_copy_capture_session_v1::Event::Done => {
+                // The constraints changed. A frame that's being captured fails, and is retried then
+                let capturing = data.state.lock().unwrap().capturing;
+                if !capturing {
+                    state.capture_ext_frame(qh, data);
+                }
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => {
+                data.end(Err(CaptureError::Failed));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, Arc<CaptureData>, ContextDelegate> for ScreencopyState {
+    fn event(
+        state: &mut ContextDelegate,
+        frame: &ExtImageCopyCaptureFrameV1,
+        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
+        data: &Arc<CaptureData>,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Ready => {
+                frame.destroy();
+                let mut capture_state = data.state.lock().unwrap();
+                capture_state.frame = None;
+                capture_state.capturing = false;
+                drop(capture_state);
+                data.frame_ready();
+                state.capture_next_frame(qh, data);
+            }
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                frame.destroy();
+                let mut capture_state = data.state.lock().unwrap();
+                capture_state.frame = None;
+                capture_state.capturing = false;
+                drop(capture_state);
+                if reason == WEnum::Value(FailureReason::BufferConstraints) {
+                    state.capture_next_frame(qh, data);
+                } else {
+                    data.end(Err(CaptureError::Failed));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlCallback, Arc<CaptureData>, ContextDelegate> for ScreencopyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WlCallback,
+        event: <WlCallback as Proxy>::Event,
+        data: &Arc<CaptureData>,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            data.end(Err(CaptureError::UnsupportedRegion));
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, GlobalData, ContextDelegate> for ScreencopyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WlBuffer,
+        _: <WlBuffer as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // The buffer is destroyed once the capture ends.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrScreencopyManagerV1: GlobalData] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ZwlrScreencopyFrameV1: Arc<CaptureData>] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ExtImageCopyCaptureManagerV1: GlobalData] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ExtOutputImageCaptureSourceManagerV1: GlobalData] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ExtImageCaptureSourceV1: GlobalData] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ExtImageCopyCaptureSessionV1: Arc<CaptureData>] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [ExtImageCopyCaptureFrameV1: Arc<CaptureData>] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [WlCallback: Arc<CaptureData>] => ScreencopyState);
+delegate_dispatch!(ContextDelegate: [WlBuffer: GlobalData] => ScreencopyState);
@@ -0,0 +1,178 @@
+//! Short-lived surfaces next to an app's own, eg. a volume slider popping out of a tray icon.
+
+use std::mem::take;
+
+use egui::{Rect, Vec2, epaint::Marginf};
+use log::error;
+use smithay_client_toolkit::{
+    compositor::Surface,
+    reexports::{
+        client::{Proxy, QueueHandle, protocol::wl_surface::WlSurface},
+        protocols::xdg::shell::client::xdg_positioner::{Anchor, ConstraintAdjustment, Gravity},
+    },
+    shell::xdg::{XdgPositioner, popup::Popup},
+};
+
+use super::{
+    App, AppOpts, Context, ContextDelegate, InputRegions, LayerAppHandle, OpaqueRegions,
+    ShellSurface, SurfaceFormat,
+};
+
+/// Where a child surface goes, see [`Context::new_child_surface`].
+pub struct ChildSurfaceOpts {
+    /// The part of the parent's surface that the child is placed next to, in points, eg. the
+    /// button that opened it.
+    pub anchor_rect: Rect,
+    /// The size of the child, in points.
+    pub size: Vec2,
+    /// Which side of `anchor_rect` the child goes on. The compositor flips or slides it if it
+    /// doesn't fit on the output there.
+    pub side: Side,
+    /// Take the keyboard and dismiss the child once something else is clicked, like a menu.
+    /// Only works when it's created in response to a click or key press.
+    pub grab: bool,
+}
+
+impl Default for ChildSurfaceOpts {
+    fn default() -> Self {
+        Self {
+            anchor_rect: Rect::ZERO,
+            size: Vec2::new(200., 100.),
+            side: Side::Bottom,
+            grab: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Context {
+    /// Show an app on a surface next to the parent's, which is closed when the compositor
+    /// dismisses it, or with [`LayerAppHandle::exit`]. It starts with the parent's egui style and
+    /// zoom. Returns `None` if the parent is gone, or isn't a layer surface or child surface
+    /// itself.
+    pub fn new_child_surface(
+        &mut self,
+        parent: &LayerAppHandle,
+        app: Box<dyn App>,
+        opts: ChildSurfaceOpts,
+    ) -> Option<LayerAppHandle> {
+        let qh = self.event_queue.handle();
+        self.delegate
+            .new_child_surface(&qh, &parent.wl_surface(), app, opts)
+    }
+}
+
+impl ContextDelegate {
+    pub(crate) fn new_child_surface(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        parent: &WlSurface,
+        app: Box<dyn App>,
+        ChildSurfaceOpts { anchor_rect, size, side, grab }: ChildSurfaceOpts,
+    ) -> Option<LayerAppHandle> {
+        let xdg_shell = self.xdg_shell.as_ref()?;
+        let parent_app = self.apps.get(&parent.id())?;
+        let app_id = parent_app.app_id.clone();
+        let style = parent_app.egui_context.style();
+        let zoom = parent_app.egui_context.zoom_factor();
+
+        let positioner = XdgPositioner::new(xdg_shell).ok()?;
+        let anchor_rect = anchor_rect * zoom;
+        let (min, max) = (anchor_rect.min.round(), anchor_rect.max.round());
+        let size = (size * zoom).round().max(Vec2::splat(1.));
+        let (anchor, gravity) = match side {
+            Side::Top => (Anchor::Top, Gravity::Top),
+            Side::Bottom => (Anchor::Bottom, Gravity::Bottom),
+            Side::Left => (Anchor::Left, Gravity::Left),
+            Side::Right => (Anchor::Right, Gravity::Right),
+        };
+        positioner.set_size(size.x as i32, size.y as i32);
+        positioner.set_anchor_rect(
+            min.x as i32,
+            min.y as i32,
+            ((max.x - min.x) as i32).max(1),
+            ((max.y - min.y) as i32).max(1),
+        );
+        positioner.set_anchor(anchor);
+        positioner.set_gravity(gravity);
+        positioner.set_constraint_adjustment(
+            ConstraintAdjustment::FlipX
+                | ConstraintAdjustment::FlipY
+                | ConstraintAdjustment::SlideX
+                | ConstraintAdjustment::SlideY,
+        );
+
+        let surface = Surface::new(&self.compositor, qh).ok()?;
+        let popup = match &parent_app.shell_surface {
+            ShellSurface::Layer(layer) => {
+                let popup = Popup::from_surface(None, &positioner, qh, surface, xdg_shell).ok()?;
+                layer.get_popup(popup.xdg_popup());
+                popup
+            }
+            ShellSurface::Child(parent) => {
+                Popup::from_surface(Some(parent.xdg_surface()), &positioner, qh, surface, xdg_shell)
+                    .ok()?
+            }
+            ShellSurface::Lock(_) | ShellSurface::Window(_) => return None,
+        };
+
+        if grab {
+            if let Some(seat) = self.seat_state.seats().next() {
+                popup.xdg_popup().grab(&seat, self.last_serial);
+            }
+        }
+
+        // This only blocks if it's the first app, when the GPU adapter and device are requested
+        let result = pollster::block_on(self.new_app(
+            qh,
+            |_| app,
+            None,
+            ShellSurface::Child(popup),
+            AppOpts {
+                app_id,
+                namespace: None,
+                input_regions: InputRegions::Full,
+                opaque_regions: OpaqueRegions::None,
+                surface_format: SurfaceFormat::default(),
+                render_scale: 1.,
+                msaa_samples: 1,
+                dithering: true,
+                popup_space: Marginf::ZERO,
+                viewports: false,
+                image_description: None,
+                url_opener: None,
+                default_size: None,
+                #[cfg(feature = "persistence")]
+                persist_memory: false,
+                desktop_style: false,
+                setup: None,
+            },
+        ));
+        let handle = match result {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to create child surface: {e}");
+                return None;
+            }
+        };
+        handle.egui_context.set_style(style);
+        handle.egui_context.set_zoom_factor(zoom);
+
+        // Children that were closed since are dropped, the rest are closed before the parent
+        if let Some(parent_app) = self.apps.get_mut(&parent.id()) {
+            let mut children = take(&mut parent_app.children);
+            children.retain(|child| self.apps.contains_key(child));
+            children.push(handle.wl_surface().id());
+            self.apps.get_mut(&parent.id()).unwrap().children = children;
+        }
+
+        Some(handle)
+    }
+}
@@ -0,0 +1,454 @@
+//! Clipboard support through `wl_data_device`.
+
+use std::{
+    io::{self, Read as _, Write as _},
+    mem::take,
+    sync::{Arc, Mutex},
+};
+
+use log::warn;
+use smithay_client_toolkit::{
+    data_device_manager::{
+        DataDeviceManagerState, ReadPipe, WritePipe,
+        data_device::{DataDevice, DataDeviceHandler},
+        data_offer::{DataOfferHandler, DragOffer},
+        data_source::{CopyPasteSource, DataSourceHandler},
+    },
+    delegate_data_device, delegate_primary_selection,
+    primary_selection::{
+        PrimarySelectionManagerState,
+        device::{PrimarySelectionDevice, PrimarySelectionDeviceHandler},
+        selection::PrimarySelectionSourceHandler,
+    },
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            protocol::{
+                wl_callback::{self, WlCallback},
+                wl_data_device::WlDataDevice,
+                wl_data_device_manager::DndAction,
+                wl_data_source::WlDataSource,
+                wl_seat::WlSeat,
+                wl_surface::WlSurface,
+            },
+        },
+        protocols::wp::primary_selection::zv1::client::{
+            zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1,
+            zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Mime types used for plain text, in order of preference.
+pub const TEXT_MIME_TYPES: &[&str] =
+    &["text/plain;charset=utf-8", "text/plain", "UTF8_STRING", "STRING", "TEXT"];
+
+/// Produces the clipboard contents for the requested mime type.
+pub type ClipboardData = Box<dyn Fn(&str) -> Option<Vec<u8>> + Send>;
+
+/// Receives the mime type and contents of the clipboard, once the transfer is complete.
+pub type ClipboardCallback = Box<dyn FnOnce(io::Result<(String, Vec<u8>)>) + Send>;
+
+/// Handle to the clipboard of a [`Context`](crate::Context), which works from any thread.
+///
+/// Requests are queued and executed by the event loop, which is woken up for them if it's waiting
+/// for events. Callbacks are called from a background thread when the transfer completes.
+#[derive(Clone)]
+pub struct Clipboard {
+    requests: Arc<Mutex<Vec<ClipboardRequest>>>,
+    wayland_conn: Connection,
+    queue_handle: QueueHandle<ContextDelegate>,
+}
+
+/// The user data of the `wl_display.sync` that wakes the event loop for queued requests.
+struct WakeUp;
+
+enum ClipboardRequest {
+    Offer {
+        mime_types: Vec<String>,
+        data: ClipboardData,
+    },
+    Receive {
+        primary: bool,
+        mime_types: Vec<String>,
+        callback: ClipboardCallback,
+    },
+}
+
+impl Clipboard {
+    /// Take ownership of the clipboard, offering the given mime types. `data` is called each time
+    /// another client pastes, with one of the offered mime types.
+    pub fn offer(
+        &self,
+        mime_types: impl IntoIterator<Item = impl Into<String>>,
+        data: impl Fn(&str) -> Option<Vec<u8>> + Send + 'static,
+    ) {
+        self.push(ClipboardRequest::Offer {
+            mime_types: mime_types.into_iter().map(Into::into).collect(),
+            data: Box::new(data),
+        });
+    }
+
+    /// Put plain text on the clipboard.
+    pub fn offer_text(&self, text: impl Into<String>) {
+        let text = text.into();
+        self.offer(TEXT_MIME_TYPES.iter().copied(), move |_| Some(text.clone().into_bytes()));
+    }
+
+    /// Request the clipboard contents in the first of `mime_types` (in order of preference) that
+    /// is being offered. The callback receives an error if none of them are available.
+    pub fn receive(
+        &self,
+        mime_types: impl IntoIterator<Item = impl Into<String>>,
+        callback: impl FnOnce(io::Result<(String, Vec<u8>)>) + Send + 'static,
+    ) {
+        self.push(ClipboardRequest::Receive {
+            primary: false,
+            mime_types: mime_types.into_iter().map(Into::into).collect(),
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Request the clipboard contents as plain text.
+    pub fn receive_text(&self, callback: impl FnOnce(Option<String>) + Send + 'static) {
+        self.receive(TEXT_MIME_TYPES.iter().copied(), move |res| {
+            callback(res.ok().and_then(|(_, data)| String::from_utf8(data).ok()));
+        });
+    }
+
+    /// Like [`Clipboard::receive`], but for the primary selection (ie. the last selected text).
+    pub fn receive_primary(
+        &self,
+        mime_types: impl IntoIterator<Item = impl Into<String>>,
+        callback: impl FnOnce(io::Result<(String, Vec<u8>)>) + Send + 'static,
+    ) {
+        self.push(ClipboardRequest::Receive {
+            primary: true,
+            mime_types: mime_types.into_iter().map(Into::into).collect(),
+            callback: Box::new(callback),
+        });
+    }
+
+    /// Request the primary selection as plain text.
+    pub fn receive_primary_text(&self, callback: impl FnOnce(Option<String>) + Send + 'static) {
+        self.receive_primary(TEXT_MIME_TYPES.iter().copied(), move |res| {
+            callback(res.ok().and_then(|(_, data)| String::from_utf8(data).ok()));
+        });
+    }
+
+    fn push(&self, request: ClipboardRequest) {
+        let mut requests = self.requests.lock().unwrap();
+        requests.push(request);
+        // The event loop may be blocked waiting for events, the compositor's answer to the sync
+        // wakes it up, and the requests are executed when it's dispatched
+        if requests.len() == 1 {
+            self.wayland_conn.display().sync(&self.queue_handle, WakeUp);
+            let _ = self.wayland_conn.flush();
+        }
+    }
+}
+
+pub(crate) struct ClipboardState {
+    manager: Option<DataDeviceManagerState>,
+    device: Option<DataDevice>,
+    source: Option<(CopyPasteSource, ClipboardData)>,
+    primary_manager: Option<PrimarySelectionManagerState>,
+    primary_device: Option<PrimarySelectionDevice>,
+    handle: Clipboard,
+}
+
+impl ClipboardState {
+    pub fn new(
+        manager: Option<DataDeviceManagerState>,
+        primary_manager: Option<PrimarySelectionManagerState>,
+        wayland_conn: Connection,
+        queue_handle: QueueHandle<ContextDelegate>,
+    ) -> Self {
+        Self {
+            manager,
+            device: None,
+            source: None,
+            primary_manager,
+            primary_device: None,
+            handle: Clipboard {
+                requests: Arc::default(),
+                wayland_conn,
+                queue_handle,
+            },
+        }
+    }
+
+    pub fn handle(&self) -> &Clipboard {
+        &self.handle
+    }
+}
+
+impl ContextDelegate {
+    pub(crate) fn init_data_device(&mut self, qh: &QueueHandle<Self>, seat: &WlSeat) {
+        if let (Some(manager), None) = (&self.clipboard.manager, &self.clipboard.device) {
+            self.clipboard.device = Some(manager.get_data_device(qh, seat));
+        }
+
+        if let (Some(manager), None) =
+            (&self.clipboard.primary_manager, &self.clipboard.primary_device)
+        {
+            self.clipboard.primary_device = Some(manager.get_selection_device(qh, seat));
+        }
+    }
+
+    /// Execute queued clipboard requests.
+    pub(crate) fn handle_clipboard_requests(&mut self, qh: &QueueHandle<Self>) {
+        let requests = take(&mut *self.clipboard.handle.requests.lock().unwrap());
+
+        for request in requests {
+            match request {
+                ClipboardRequest::Offer { mime_types, data } => {
+                    let (Some(manager), Some(device)) =
+                        (&self.clipboard.manager, &self.clipboard.device)
+                    else {
+                        warn!("No data device, can't set clipboard");
+                        continue;
+                    };
+
+                    let source = manager.create_copy_paste_source(qh, mime_types);
+                    source.set_selection(device, self.last_serial);
+                    self.clipboard.source = Some((source, data));
+                }
+                ClipboardRequest::Receive { primary, mime_types, callback } => {
+                    match self.open_selection(primary, &mime_types) {
+                        Ok((mut pipe, mime_type)) => {
+                            std::thread::spawn(move || {
+                                let mut data = Vec::new();
+                                callback(pipe.read_to_end(&mut data).map(|_| (mime_type, data)));
+                            });
+                        }
+                        Err(e) => callback(Err(e)),
+                    }
+                }
+            }
+        }
+    }
+
+    fn open_selection(
+        &self,
+        primary: bool,
+        mime_types: &[String],
+    ) -> io::Result<(ReadPipe, String)> {
+        let find_mime_type = |offered: &[String]| {
+            mime_types
+                .iter()
+                .find(|mime| offered.contains(mime))
+                .cloned()
+        };
+        let empty = || io::Error::new(io::ErrorKind::NotFound, "clipboard is empty");
+        let no_match =
+            || io::Error::new(io::ErrorKind::NotFound, "no matching mime type on the clipboard");
+
+        let res = if primary {
+            let offer = self
+                .clipboard
+                .primary_device
+                .as_ref()
+                .and_then(|device| device.data().selection_offer())
+                .ok_or_else(empty)?;
+
+            let mime_type = offer.with_mime_types(find_mime_type).ok_or_else(no_match)?;
+            (offer.receive(mime_type.clone())?, mime_type)
+        } else {
+            let offer = self
+                .clipboard
+                .device
+                .as_ref()
+                .and_then(|device| device.data().selection_offer())
+                .ok_or_else(empty)?;
+
+            let mime_type = offer.with_mime_types(find_mime_type).ok_or_else(no_match)?;
+            (offer.receive(mime_type.clone()).map_err(io::Error::other)?, mime_type)
+        };
+
+        // The source client won't see the request until we flush, and it may well be us, so the
+        // actual read has to happen on another thread.
+        self.wayland_conn.flush().map_err(io::Error::other)?;
+
+        Ok(res)
+    }
+}
+
+impl DataDeviceHandler for ContextDelegate {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+        _x: f64,
+        _y: f64,
+        _wl_surface: &WlSurface,
+    ) {
+    }
+
+    fn leave(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _data_device: &WlDataDevice) {}
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+        _x: f64,
+        _y: f64,
+    ) {
+    }
+
+    fn selection(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+    ) {
+        // The offer is stored on the data device, and only looked at when receiving.
+    }
+
+    fn drop_performed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _data_device: &WlDataDevice,
+    ) {
+    }
+}
+
+impl DataSourceHandler for ContextDelegate {
+    fn accept_mime(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+        _mime: Option<String>,
+    ) {
+    }
+
+    fn send_request(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        source: &WlDataSource,
+        mime: String,
+        mut fd: WritePipe,
+    ) {
+        let Some((current, data)) = &self.clipboard.source else {
+            return;
+        };
+
+        if current.inner() != source {
+            return;
+        }
+
+        if let Some(bytes) = data(&mime) {
+            std::thread::spawn(move || fd.write_all(&bytes));
+        }
+    }
+
+    fn cancelled(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, source: &WlDataSource) {
+        if self
+            .clipboard
+            .source
+            .as_ref()
+            .is_some_and(|(current, _)| current.inner() == source)
+        {
+            self.clipboard.source = None;
+        }
+    }
+
+    fn dnd_dropped(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _source: &WlDataSource) {
+    }
+
+    fn dnd_finished(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+    ) {
+    }
+
+    fn action(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &WlDataSource,
+        _action: DndAction,
+    ) {
+    }
+}
+
+impl DataOfferHandler for ContextDelegate {
+    fn source_actions(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _offer: &mut DragOffer,
+        _actions: DndAction,
+    ) {
+    }
+
+    fn selected_action(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _offer: &mut DragOffer,
+        _actions: DndAction,
+    ) {
+    }
+}
+
+impl PrimarySelectionDeviceHandler for ContextDelegate {
+    fn selection(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _primary_selection_device: &ZwpPrimarySelectionDeviceV1,
+    ) {
+        // The offer is stored on the device, and only looked at when receiving.
+    }
+}
+
+impl PrimarySelectionSourceHandler for ContextDelegate {
+    fn send_request(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &ZwpPrimarySelectionSourceV1,
+        _mime: String,
+        _write_pipe: WritePipe,
+    ) {
+        // We never offer a primary selection.
+    }
+
+    fn cancelled(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _source: &ZwpPrimarySelectionSourceV1,
+    ) {
+    }
+}
+
+impl Dispatch<WlCallback, WakeUp, ContextDelegate> for ClipboardState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &WlCallback,
+        event: <WlCallback as Proxy>::Event,
+        _: &WakeUp,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.handle_clipboard_requests(qh);
+            let _ = state.wayland_conn.flush();
+        }
+    }
+}
+
+delegate_data_device!(ContextDelegate);
+delegate_primary_selection!(ContextDelegate);
+delegate_dispatch!(ContextDelegate: [WlCallback: WakeUp] => ClipboardState);
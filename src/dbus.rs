@@ -0,0 +1,61 @@
+//! Passing D-Bus signals and method replies to apps, with the `zbus` feature.
+
+use std::{sync::Arc, thread};
+
+use log::warn;
+use zbus::{
+    MatchRule, Message,
+    blocking::{Connection, MessageIterator},
+};
+
+use super::{App, LayerAppHandle};
+
+/// Pass the signals matching `rule` to the app of `handle`, eg. to show a prompt while fprintd
+/// waits for a finger. They're received on a thread of its own, and `on_signal` is called with
+/// the app before its next frame, see [`LayerAppHandle::send`]. The thread stops with the first
+/// signal after the app is gone, or when the connection is closed.
+pub fn forward_dbus_signals<T: App>(
+    handle: &LayerAppHandle,
+    connection: &Connection,
+    rule: MatchRule<'static>,
+    on_signal: impl Fn(&mut T, &Message) + Send + Sync + 'static,
+) -> zbus::Result<()> {
+    // Subscribed before returning, so no signal is missed while the thread starts
+    let signals = MessageIterator::for_match_rule(rule, connection, None)?;
+    let handle = handle.downgrade();
+    let on_signal = Arc::new(on_signal);
+
+    thread::spawn(move || {
+        for message in signals {
+            let Some(handle) = handle.upgrade() else {
+                break;
+            };
+            match message {
+                Ok(message) => {
+                    let on_signal = on_signal.clone();
+                    handle.send(move |app: &mut T| on_signal(app, &message));
+                }
+                Err(e) => warn!("Failed to receive D-Bus signal: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Make a blocking D-Bus call on a thread of its own, eg. through a proxy, and pass the reply to
+/// the app of `handle` before its next frame. Nothing is passed if the app is gone by then.
+pub fn spawn_dbus_call<T: App, R: Send + 'static>(
+    handle: &LayerAppHandle,
+    call: impl FnOnce() -> zbus::Result<R> + Send + 'static,
+    on_reply: impl FnOnce(&mut T, zbus::Result<R>) + Send + 'static,
+) {
+    let handle = handle.downgrade();
+
+    thread::spawn(move || {
+        let reply = call();
+        if let Some(handle) = handle.upgrade() {
+            handle.send(move |app: &mut T| on_reply(app, reply));
+        }
+    });
+}
@@ -0,0 +1,518 @@
+//! Importing dmabufs as textures, without copying them through the CPU.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::fd::{FromRawFd, IntoRawFd, OwnedFd},
+};
+
+use ash::vk;
+use wayland_client::Connection;
+use wgpu::{
+    TextureFormat,
+    hal::{self, api::Vulkan},
+};
+
+const DRM_FORMAT_ARGB8888: u32 = fourcc(b"AR24");
+const DRM_FORMAT_XRGB8888: u32 = fourcc(b"XR24");
+const DRM_FORMAT_ABGR8888: u32 = fourcc(b"AB24");
+const DRM_FORMAT_XBGR8888: u32 = fourcc(b"XB24");
+const DRM_FORMAT_ABGR2101010: u32 = fourcc(b"AB30");
+const DRM_FORMAT_ABGR16161616F: u32 = fourcc(b"AB4H");
+
+/// The Vulkan extensions that are needed on top of wgpu's own.
+const EXTENSIONS: [&CStr; 5] = [
+    ash::khr::external_memory_fd::NAME,
+    ash::ext::external_memory_dma_buf::NAME,
+    ash::ext::image_drm_format_modifier::NAME,
+    ash::khr::image_format_list::NAME,
+    ash::ext::queue_family_foreign::NAME,
+];
+
+const fn fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+/// A buffer shared through `linux-dmabuf`, eg. by PipeWire, v4l2 or another process.
+#[derive(Debug)]
+pub struct Dmabuf {
+    pub width: u32,
+    pub height: u32,
+    /// The DRM fourcc format code, only single-plane RGB formats are supported.
+    pub format: u32,
+    /// The DRM format modifier, which describes the buffer's tiling.
+    pub modifier: u64,
+    pub plane: DmabufPlane,
+}
+
+#[derive(Debug)]
+pub struct DmabufPlane {
+    pub fd: OwnedFd,
+    pub offset: u32,
+    pub stride: u32,
+}
+
+#[derive(Debug)]
+pub enum DmabufError {
+    /// No Vulkan adapter was found.
+    NoAdapter,
+    /// The device isn't a Vulkan device.
+    NotVulkan,
+    /// The adapter doesn't support one of the needed Vulkan extensions.
+    MissingExtension(&'static CStr),
+    /// None of the adapter's queue families can render and present to the compositor.
+    NoQueueFamily,
+    /// The buffer's DRM format isn't supported.
+    UnsupportedFormat(u32),
+    /// None of the device's memory types can hold the buffer.
+    NoMemoryType,
+    RequestDevice(wgpu::RequestDeviceError),
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for DmabufError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DmabufError::NoAdapter => write!(f, "no Vulkan adapter found"),
+            DmabufError::NotVulkan => write!(f, "the device doesn't use Vulkan"),
+            DmabufError::MissingExtension(name) => {
+                write!(f, "missing Vulkan extension {}", name.to_string_lossy())
+            }
+            DmabufError::NoQueueFamily => {
+                write!(f, "no queue family can present to the compositor")
+            }
+            DmabufError::UnsupportedFormat(format) => {
+                let code = format.to_le_bytes();
+                write!(f, "unsupported DRM format {}", String::from_utf8_lossy(&code))
+            }
+            DmabufError::NoMemoryType => write!(f, "no memory type can hold the buffer"),
+            DmabufError::RequestDevice(e) => write!(f, "failed to request device: {e}"),
+            DmabufError::Vulkan(e) => write!(f, "Vulkan error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DmabufError {}
+
+impl From<vk::Result> for DmabufError {
+    fn from(e: vk::Result) -> Self {
+        DmabufError::Vulkan(e)
+    }
+}
+
+/// Open a device on the adapter with the Vulkan extensions that are needed to import dmabufs. Its
+/// queue is one that can present to the compositor on `wayland_conn`.
+pub fn request_device(
+    adapter: &wgpu::Adapter,
+    wayland_conn: &Connection,
+) -> Result<(wgpu::Device, wgpu::Queue), DmabufError> {
+    let features = wgpu::Features::empty();
+    let memory_hints = wgpu::MemoryHints::default();
+
+    let open_device = unsafe {
+        adapter.as_hal::<Vulkan, _, _>(|hal_adapter| -> Result<_, DmabufError> {
+            let hal_adapter = hal_adapter.ok_or(DmabufError::NotVulkan)?;
+            let capabilities = hal_adapter.physical_device_capabilities();
+
+            let mut extensions = hal_adapter.required_device_extensions(features);
+            for extension in EXTENSIONS {
+                if !capabilities.supports_extension(extension) {
+                    return Err(DmabufError::MissingExtension(extension));
+                }
+                if !extensions.contains(&extension) {
+                    extensions.push(extension);
+                }
+            }
+
+            let mut device_features = hal_adapter.physical_device_features(&extensions, features);
+
+            // wgpu always uses the first queue family, which can't necessarily present
+            let shared_instance = hal_adapter.shared_instance();
+            let raw_instance = shared_instance.raw_instance();
+            let physical_device = hal_adapter.raw_physical_device();
+            if !shared_instance
+                .extensions()
+                .contains(&ash::khr::wayland_surface::NAME)
+            {
+                return Err(DmabufError::MissingExtension(ash::khr::wayland_surface::NAME));
+            }
+            let wayland_surface =
+                ash::khr::wayland_surface::Instance::new(shared_instance.entry(), raw_instance);
+            let display = &mut *(wayland_conn.backend().display_ptr() as *mut vk::wl_display);
+            let families =
+                raw_instance.get_physical_device_queue_family_properties(physical_device);
+            let family_index = (0..)
+                .zip(&families)
+                .find(|(index, family)| {
+                    family
+                        .queue_flags
+                        .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+                        && wayland_surface.get_physical_device_wayland_presentation_support(
+                            physical_device,
+                            *index,
+                            display,
+                        )
+                })
+                .map(|(index, _)| index)
+                .ok_or(DmabufError::NoQueueFamily)?;
+            let queue_infos = [vk::DeviceQueueCreateInfo::default()
+                .queue_family_index(family_index)
+                .queue_priorities(&[1.0])];
+            let extension_names = extensions
+                .iter()
+                .map(|name| name.as_ptr())
+                .collect::<Vec<_>>();
+            let create_info = device_features.add_to_device_create(
+                vk::DeviceCreateInfo::default()
+                    .queue_create_infos(&queue_infos)
+                    .enabled_extension_names(&extension_names),
+            );
+
+            let raw_device = raw_instance.create_device(physical_device, &create_info, None)?;
+
+            hal_adapter
+                .device_from_raw(
+                    raw_device,
+                    None,
+                    &extensions,
+                    features,
+                    &memory_hints,
+                    family_index,
+                    0,
+                )
+                .map_err(|_| DmabufError::Vulkan(vk::Result::ERROR_INITIALIZATION_FAILED))
+        })?
+    };
+
+    unsafe {
+        adapter.create_device_from_hal(
+            open_device,
+            &wgpu::DeviceDescriptor {
+                label: Some("egui_dmabuf_device"),
+                required_features: features,
+                required_limits: adapter.limits(),
+                memory_hints,
+            },
+            None,
+        )
+    }
+    .map_err(DmabufError::RequestDevice)
+}
+
+/// Import a dmabuf as a texture that can be sampled. The device needs to be opened with
+/// [`request_device`].
+///
+/// The buffer's contents are copied into the texture, and the buffer is handed back to its
+/// producer once this returns, so it can be reused right away. There's no implicit
+/// synchronization with the producer though: it has to be done writing the buffer before it's
+/// imported, eg. after waiting for its fence. The copy is submitted to the device's queue outside
+/// of wgpu and waited for, so this mustn't be called while another thread submits to `queue`,
+/// eg. call it from the context's thread.
+pub fn import_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    dmabuf: Dmabuf,
+) -> Result<wgpu::Texture, DmabufError> {
+    let (format, vk_format) = match dmabuf.format {
+        // The X formats' padding is read as alpha, which producers usually fill with 0xff
+        DRM_FORMAT_ARGB8888 | DRM_FORMAT_XRGB8888 => {
+            (TextureFormat::Bgra8Unorm, vk::Format::B8G8R8A8_UNORM)
+        }
+        DRM_FORMAT_ABGR8888 | DRM_FORMAT_XBGR8888 => {
+            (TextureFormat::Rgba8Unorm, vk::Format::R8G8B8A8_UNORM)
+        }
+        DRM_FORMAT_ABGR2101010 => {
+            (TextureFormat::Rgb10a2Unorm, vk::Format::A2B10G10R10_UNORM_PACK32)
+        }
+        DRM_FORMAT_ABGR16161616F => (TextureFormat::Rgba16Float, vk::Format::R16G16B16A16_SFLOAT),
+        format => return Err(DmabufError::UnsupportedFormat(format)),
+    };
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("egui_dmabuf_texture"),
+        size: wgpu::Extent3d {
+            width: dmabuf.width,
+            height: dmabuf.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    // wgpu tracks the texture's layout, and would discard its contents when it first uses a
+    // texture it doesn't know to be initialized. Clearing it in a render pass leaves it in the
+    // color attachment layout, which the copy goes back to.
+    let mut encoder = device.create_command_encoder(&Default::default());
+    drop(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("egui_dmabuf_init"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: &texture.create_view(&Default::default()),
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        ..Default::default()
+    }));
+    queue.submit([encoder.finish()]);
+
+    let texture_image = unsafe {
+        texture.as_hal::<Vulkan, _, _>(|texture| texture.map(|texture| texture.raw_handle()))
+    }
+    .ok_or(DmabufError::NotVulkan)?;
+
+    unsafe {
+        device.as_hal::<Vulkan, _, _>(|hal_device| -> Result<_, DmabufError> {
+            let hal_device = hal_device.ok_or(DmabufError::NotVulkan)?;
+            let raw_device = hal_device.raw_device();
+            let raw_instance = hal_device.shared_instance().raw_instance();
+
+            let extent = vk::Extent3D {
+                width: dmabuf.width,
+                height: dmabuf.height,
+                depth: 1,
+            };
+            let plane_layouts = [vk::SubresourceLayout {
+                offset: dmabuf.plane.offset as u64,
+                size: 0,
+                row_pitch: dmabuf.plane.stride as u64,
+                array_pitch: 0,
+                depth_pitch: 0,
+            }];
+            let mut modifier_info = vk::ImageDrmFormatModifierExplicitCreateInfoEXT::default()
+                .drm_format_modifier(dmabuf.modifier)
+                .plane_layouts(&plane_layouts);
+            let mut external_info = vk::ExternalMemoryImageCreateInfo::default()
+                .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT);
+
+            // Its contents are defined by the producer, the layout is only the Vulkan side's
+            let image = raw_device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .format(vk_format)
+                    .extent(extent)
+                    .mip_levels(1)
+                    .array_layers(1)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::DRM_FORMAT_MODIFIER_EXT)
+                    .usage(vk::ImageUsageFlags::TRANSFER_SRC)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .push_next(&mut external_info)
+                    .push_next(&mut modifier_info),
+                None,
+            )?;
+
+            let memory = import_memory(raw_instance, raw_device, image, dmabuf.plane.fd)
+                .and_then(|memory| match raw_device.bind_image_memory(image, memory, 0) {
+                    Ok(()) => Ok(memory),
+                    Err(e) => {
+                        raw_device.free_memory(memory, None);
+                        Err(e.into())
+                    }
+                })
+                .inspect_err(|_| raw_device.destroy_image(image, None))?;
+
+            let result = copy_image(hal_device, image, texture_image, extent);
+            raw_device.destroy_image(image, None);
+            raw_device.free_memory(memory, None);
+            result
+        })?;
+    }
+
+    Ok(texture)
+}
+
+/// Copy the dmabuf's image into the texture's, in a submission of its own that's waited for. The
+/// buffer is acquired from its producer and released back to it afterwards, and the texture is
+/// left in the layout that wgpu knows it in.
+unsafe fn copy_image(
+    hal_device: &hal::vulkan::Device,
+    image: vk::Image,
+    texture_image: vk::Image,
+    extent: vk::Extent3D,
+) -> Result<(), DmabufError> {
+    let device = hal_device.raw_device();
+    let family_index = hal_device.queue_family_index();
+    let range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let layers = vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+
+    let acquire = [
+        vk::ImageMemoryBarrier::default()
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::GENERAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_FOREIGN_EXT)
+            .dst_queue_family_index(family_index)
+            .image(image)
+            .subresource_range(range),
+        vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(texture_image)
+            .subresource_range(range),
+    ];
+    let release = [
+        vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL)
+            .src_queue_family_index(family_index)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_FOREIGN_EXT)
+            .image(image)
+            .subresource_range(range),
+        vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            )
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(texture_image)
+            .subresource_range(range),
+    ];
+
+    let pool = unsafe {
+        device.create_command_pool(
+            &vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(family_index),
+            None,
+        )
+    }?;
+    let result = (|| unsafe {
+        let command_buffer = device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::default()
+                .command_pool(pool)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .command_buffer_count(1),
+        )?[0];
+        device.begin_command_buffer(
+            command_buffer,
+            &vk::CommandBufferBeginInfo::default()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+        )?;
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &acquire,
+        );
+        device.cmd_copy_image(
+            command_buffer,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            texture_image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[vk::ImageCopy {
+                src_subresource: layers,
+                src_offset: vk::Offset3D::default(),
+                dst_subresource: layers,
+                dst_offset: vk::Offset3D::default(),
+                extent,
+            }],
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &release,
+        );
+        device.end_command_buffer(command_buffer)?;
+
+        let fence = device.create_fence(&Default::default(), None)?;
+        let command_buffers = [command_buffer];
+        let result = device
+            .queue_submit(
+                hal_device.raw_queue(),
+                &[vk::SubmitInfo::default().command_buffers(&command_buffers)],
+                fence,
+            )
+            .and_then(|()| device.wait_for_fences(&[fence], true, u64::MAX));
+        device.destroy_fence(fence, None);
+        result
+    })();
+    unsafe { device.destroy_command_pool(pool, None) };
+    result.map_err(Into::into)
+}
+
+/// Import the dmabuf's memory for the image. On success, Vulkan takes ownership of the fd.
+unsafe fn import_memory(
+    instance: &ash::Instance,
+    device: &ash::Device,
+    image: vk::Image,
+    fd: OwnedFd,
+) -> Result<vk::DeviceMemory, DmabufError> {
+    let fd = fd.into_raw_fd();
+    let close_fd = || drop(unsafe { OwnedFd::from_raw_fd(fd) });
+
+    let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+    let mut fd_properties = vk::MemoryFdPropertiesKHR::default();
+    let external_memory_fd = ash::khr::external_memory_fd::Device::new(instance, device);
+    if let Err(e) = unsafe {
+        external_memory_fd.get_memory_fd_properties(
+            vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT,
+            fd,
+            &mut fd_properties,
+        )
+    } {
+        close_fd();
+        return Err(e.into());
+    }
+
+    let memory_types = requirements.memory_type_bits & fd_properties.memory_type_bits;
+    if memory_types == 0 {
+        close_fd();
+        return Err(DmabufError::NoMemoryType);
+    }
+
+    let mut import_info = vk::ImportMemoryFdInfoKHR::default()
+        .handle_type(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+        .fd(fd);
+    let mut dedicated_info = vk::MemoryDedicatedAllocateInfo::default().image(image);
+
+    unsafe {
+        device.allocate_memory(
+            &vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_types.trailing_zeros())
+                .push_next(&mut import_info)
+                .push_next(&mut dedicated_info),
+            None,
+        )
+    }
+    .map_err(|e| {
+        close_fd();
+        e.into()
+    })
+}
@@ -0,0 +1,156 @@
+use std::{fmt, io};
+
+use wayland_backend::client::WaylandError;
+use wayland_client::{ConnectError, DispatchError, globals::GlobalError};
+
+#[cfg(feature = "dmabuf")]
+use super::DmabufError;
+
+/// Errors returned while setting up the context and apps, or while running the event loop.
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting to the compositor failed, eg. because `WAYLAND_DISPLAY` isn't set.
+    Connect(ConnectError),
+    /// Getting the compositor's globals failed.
+    Globals(GlobalError),
+    /// The compositor doesn't support a protocol that's needed, eg. `zwlr_layer_shell_v1`.
+    MissingProtocol(&'static str),
+    /// No GPU adapter or device was found that can render to the app's surface.
+    RenderState(egui_wgpu::WgpuError),
+    /// Creating the wgpu surface for an app failed.
+    CreateSurface(wgpu::CreateSurfaceError),
+    /// Setting up a device that can import dmabufs failed.
+    #[cfg(feature = "dmabuf")]
+    Dmabuf(DmabufError),
+    /// Dispatching Wayland events failed.
+    Dispatch(DispatchError),
+    /// The connection to the compositor was lost, eg. because it crashed or restarted. The apps
+    /// can be shown again with [`Context::reconnect`](crate::Context::reconnect) once it's back.
+    Disconnected(io::Error),
+    /// Rendering a frame failed in a way that can't be recovered from, eg. running out of memory.
+    Surface(wgpu::SurfaceError),
+    /// The layer surface settings made in [`App::on_init`](crate::App::on_init) are invalid.
+    Geometry(GeometryError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connect(e) => write!(f, "failed to connect to the compositor: {e}"),
+            Error::Globals(e) => write!(f, "failed to get the compositor's globals: {e}"),
+            Error::MissingProtocol(name) => write!(f, "{name} is not available"),
+            Error::RenderState(e) => write!(f, "failed to set up rendering: {e}"),
+            Error::CreateSurface(e) => write!(f, "failed to create wgpu surface: {e}"),
+            #[cfg(feature = "dmabuf")]
+            Error::Dmabuf(e) => write!(f, "failed to set up dmabuf import: {e}"),
+            Error::Dispatch(e) => write!(f, "failed to dispatch events: {e}"),
+            Error::Disconnected(e) => write!(f, "lost the connection to the compositor: {e}"),
+            Error::Surface(e) => write!(f, "failed to render frame: {e}"),
+            Error::Geometry(e) => write!(f, "invalid layer surface settings: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connect(e) => Some(e),
+            Error::Globals(e) => Some(e),
+            Error::MissingProtocol(_) => None,
+            Error::RenderState(e) => Some(e),
+            Error::CreateSurface(e) => Some(e),
+            #[cfg(feature = "dmabuf")]
+            Error::Dmabuf(e) => Some(e),
+            Error::Dispatch(e) => Some(e),
+            Error::Disconnected(e) => Some(e),
+            Error::Surface(e) => Some(e),
+            Error::Geometry(e) => Some(e),
+        }
+    }
+}
+
+/// Layer surface settings that the compositor would reject, or that would hide the surface. The
+/// settings are checked together with the ones made earlier through the handle or frame, and in
+/// [`App::on_init`](crate::App::on_init).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryError {
+    /// A width of 0 stretches the surface between its anchors, so it needs both the left and the
+    /// right one.
+    ZeroWidthNotAnchored,
+    /// A height of 0 stretches the surface between its anchors, so it needs both the top and the
+    /// bottom one.
+    ZeroHeightNotAnchored,
+    /// A negative margin on an anchored edge is at least as large as the surface, which moves it
+    /// off the output.
+    MarginHidesSurface,
+}
+
+impl fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryError::ZeroWidthNotAnchored => {
+                write!(f, "a width of 0 needs the surface to be anchored left and right")
+            }
+            GeometryError::ZeroHeightNotAnchored => {
+                write!(f, "a height of 0 needs the surface to be anchored to the top and bottom")
+            }
+            GeometryError::MarginHidesSurface => {
+                write!(f, "a negative margin moves the surface off the output")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+impl From<ConnectError> for Error {
+    fn from(e: ConnectError) -> Self {
+        Error::Connect(e)
+    }
+}
+
+impl From<GlobalError> for Error {
+    fn from(e: GlobalError) -> Self {
+        Error::Globals(e)
+    }
+}
+
+impl From<egui_wgpu::WgpuError> for Error {
+    fn from(e: egui_wgpu::WgpuError) -> Self {
+        Error::RenderState(e)
+    }
+}
+
+impl From<wgpu::CreateSurfaceError> for Error {
+    fn from(e: wgpu::CreateSurfaceError) -> Self {
+        Error::CreateSurface(e)
+    }
+}
+
+#[cfg(feature = "dmabuf")]
+impl From<DmabufError> for Error {
+    fn from(e: DmabufError) -> Self {
+        Error::Dmabuf(e)
+    }
+}
+
+impl From<DispatchError> for Error {
+    fn from(e: DispatchError) -> Self {
+        match e {
+            DispatchError::Backend(WaylandError::Io(e)) => Error::Disconnected(e),
+            e => Error::Dispatch(e),
+        }
+    }
+}
+
+impl From<GeometryError> for Error {
+    fn from(e: GeometryError) -> Self {
+        Error::Geometry(e)
+    }
+}
+
+impl From<wgpu::SurfaceError> for Error {
+    fn from(e: wgpu::SurfaceError) -> Self {
+        Error::Surface(e)
+    }
+}
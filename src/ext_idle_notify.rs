@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_seat::WlSeat,
+        },
+        protocols::ext::idle_notify::v1::client::{
+            ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+            ext_idle_notifier_v1::ExtIdleNotifierV1,
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Idle notifier, to tell apps when the user is away.
+#[derive(Debug)]
+pub struct IdleNotifyState {
+    notifier: ExtIdleNotifierV1,
+}
+
+impl IdleNotifyState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let notifier = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self { notifier })
+    }
+
+    /// Get notified once the seat had no input for `timeout`, and again when it's used after that.
+    pub fn get_notification(
+        &self,
+        timeout: Duration,
+        seat: &WlSeat,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> ExtIdleNotificationV1 {
+        let timeout = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        self.notifier
+            .get_idle_notification(timeout, seat, queue_handle, GlobalData)
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, GlobalData, ContextDelegate> for IdleNotifyState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ExtIdleNotifierV1,
+        _: <ExtIdleNotifierV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<ExtIdleNotificationV1, GlobalData, ContextDelegate> for IdleNotifyState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ExtIdleNotificationV1,
+        event: <ExtIdleNotificationV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            ext_idle_notification_v1::Event::Idled => state.idle_changed(true),
+            ext_idle_notification_v1::Event::Resumed => state.idle_changed(false),
+            _ => {}
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ExtIdleNotifierV1: GlobalData] => IdleNotifyState);
+delegate_dispatch!(ContextDelegate: [ExtIdleNotificationV1: GlobalData] => IdleNotifyState);
@@ -0,0 +1,189 @@
+//! Controlling an app's surface while it's running.
+
+use std::{
+    cell::Cell,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use egui_wgpu::RenderState;
+use smithay_client_toolkit::{
+    output::OutputInfo,
+    shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerSurface},
+};
+
+use super::{GeometryError, InitGeometry, LayerGeometry, ShellSurface};
+
+/// The app's new layer surface, passed to [`App::on_init`](crate::App::on_init). The size, anchor
+/// and margin are checked once `on_init` returns, and the app is closed if they're invalid, see
+/// [`GeometryError`].
+pub struct LayerSetup<'a> {
+    layer: &'a LayerSurface,
+    pub(crate) init: Cell<InitGeometry>,
+}
+
+impl<'a> LayerSetup<'a> {
+    pub(crate) fn new(layer: &'a LayerSurface) -> Self {
+        Self { layer, init: Cell::default() }
+    }
+
+    /// The layer surface, for settings that aren't covered here. Settings made on it directly
+    /// aren't checked.
+    pub fn layer_surface(&self) -> &LayerSurface {
+        self.layer
+    }
+
+    /// The size, in logical pixels. 0 stretches the surface between its anchors.
+    pub fn set_size(&self, width: u32, height: u32) {
+        self.layer.set_size(width, height);
+        self.record(|init| init.size = Some((width, height)));
+    }
+
+    pub fn set_anchor(&self, anchor: Anchor) {
+        self.layer.set_anchor(anchor);
+        self.record(|init| init.anchor = Some(anchor));
+    }
+
+    pub fn set_margin(&self, top: i32, right: i32, bottom: i32, left: i32) {
+        self.layer.set_margin(top, right, bottom, left);
+        self.record(|init| init.margin = Some((top, right, bottom, left)));
+    }
+
+    pub fn set_exclusive_zone(&self, zone: i32) {
+        self.layer.set_exclusive_zone(zone);
+    }
+
+    pub fn set_keyboard_interactivity(&self, interactivity: KeyboardInteractivity) {
+        self.layer.set_keyboard_interactivity(interactivity);
+    }
+
+    pub fn set_layer(&self, layer: Layer) {
+        self.layer.set_layer(layer);
+    }
+
+    fn record(&self, update: impl FnOnce(&mut InitGeometry)) {
+        let mut init = self.init.get();
+        update(&mut init);
+        self.init.set(init);
+    }
+}
+
+/// The app's surface, passed to [`App::update`](crate::App::update). The layer surface settings
+/// take effect with the frame after this one.
+pub struct LayerFrame<'a> {
+    pub(crate) shell_surface: &'a ShellSurface,
+    pub(crate) egui_context: &'a egui::Context,
+    pub(crate) render_state: &'a RenderState,
+    pub(crate) output_info: &'a Mutex<Vec<OutputInfo>>,
+    pub(crate) exit: &'a AtomicBool,
+    pub(crate) hidden: &'a AtomicBool,
+    pub(crate) geometry: &'a Mutex<LayerGeometry>,
+    pub(crate) size: (u32, u32),
+    pub(crate) scale: f32,
+    pub(crate) input_times: &'a [Duration],
+}
+
+impl LayerFrame<'_> {
+    /// The surface's size, in logical pixels.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// The surface's scale factor, integer or fractional.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// When the input events of this frame happened, on `CLOCK_MONOTONIC`, oldest first. Empty
+    /// if the compositor doesn't send precise input timestamps. There's one for every Wayland
+    /// input event, which can turn into several egui events, eg. a key press and its text.
+    ///
+    /// These aren't passed on to egui: its time, which it also uses for pointer velocity and
+    /// flings, is when the frame started, so velocity is only as precise as the frame rate. Apps
+    /// that need precise velocity, eg. for drawing, can track the pointer with these instead.
+    pub fn input_times(&self) -> &[Duration] {
+        self.input_times
+    }
+
+    /// The outputs the surface is on.
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.output_info.lock().unwrap().clone()
+    }
+
+    /// The layer surface, for settings that aren't covered here. `None` for lock surfaces,
+    /// windows and child surfaces.
+    pub fn layer_surface(&self) -> Option<&LayerSurface> {
+        match self.shell_surface {
+            ShellSurface::Layer(layer) => Some(layer),
+            _ => None,
+        }
+    }
+
+    /// Ask for a new size, in logical pixels. 0 stretches the surface between its anchors.
+    /// Invalid settings are rejected, see [`GeometryError`].
+    pub fn set_size(&self, width: u32, height: u32) -> Result<(), GeometryError> {
+        if let Some(layer) = self.layer_surface() {
+            let mut geometry = self.geometry.lock().unwrap();
+            geometry.update(|geometry| geometry.size = Some((width, height)))?;
+            layer.set_size(width, height);
+            self.egui_context.request_repaint();
+        }
+        Ok(())
+    }
+
+    pub fn set_anchor(&self, anchor: Anchor) -> Result<(), GeometryError> {
+        if let Some(layer) = self.layer_surface() {
+            let mut geometry = self.geometry.lock().unwrap();
+            geometry.update(|geometry| geometry.anchor = Some(anchor))?;
+            layer.set_anchor(anchor);
+            self.egui_context.request_repaint();
+        }
+        Ok(())
+    }
+
+    pub fn set_margin(
+        &self,
+        top: i32,
+        right: i32,
+        bottom: i32,
+        left: i32,
+    ) -> Result<(), GeometryError> {
+        if let Some(layer) = self.layer_surface() {
+            let mut geometry = self.geometry.lock().unwrap();
+            geometry.update(|geometry| geometry.margin = Some((top, right, bottom, left)))?;
+            layer.set_margin(top, right, bottom, left);
+            self.egui_context.request_repaint();
+        }
+        Ok(())
+    }
+
+    pub fn set_exclusive_zone(&self, zone: i32) {
+        if let Some(layer) = self.layer_surface() {
+            layer.set_exclusive_zone(zone);
+            self.egui_context.request_repaint();
+        }
+    }
+
+    /// Unmap the layer surface after this frame, which also gives up its exclusive zone. The app
+    /// isn't updated while it's hidden, show it again with
+    /// [`LayerAppHandle::show`](crate::LayerAppHandle::show).
+    pub fn hide(&self) {
+        if self.layer_surface().is_some() {
+            self.hidden.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Close the app after this frame.
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+        self.egui_context.request_repaint();
+    }
+
+    /// The app's wgpu device, queue and egui renderer.
+    pub fn render_state(&self) -> &RenderState {
+        self.render_state
+    }
+}
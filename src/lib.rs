@@ -1,37 +1,76 @@
 use std::{
+    any::{Any, type_name},
     collections::{HashMap, HashSet},
+    future::Future,
     io::ErrorKind,
-    mem::take,
+    mem::{replace, take},
+    ops::RangeInclusive,
+    pin::Pin,
+    process::Command,
     ptr::NonNull,
     sync::{
-        Arc,
+        Arc, Mutex, Weak,
         atomic::{AtomicBool, Ordering},
     },
-    time::Instant,
+    task::{Poll, Waker},
+    time::{Duration, Instant},
 };
 
 use egui::{
-    AreaState, PointerButton, Pos2, TouchDeviceId, TouchId, TouchPhase, ViewportId, ViewportInfo,
+    AreaState, ClippedPrimitive, Color32, CursorIcon, Key, OpenUrl, OutputCommand, PointerButton,
+    Pos2, Rect, TouchDeviceId, TouchId, TouchPhase, Vec2, ViewportId, ViewportIdMap, ViewportInfo,
+    ViewportOutput,
+    epaint::{ClippedShape, Marginf, Primitive},
+    mutex::RwLock,
 };
-use egui_wgpu::{ScreenDescriptor, WgpuConfiguration, wgpu::TextureFormat};
+pub use egui_wgpu::{
+    RenderState, WgpuConfiguration, WgpuSetup, WgpuSetupCreateNew, WgpuSetupExisting,
+};
+use egui_wgpu::{Renderer, ScreenDescriptor};
+use log::{debug, error, trace, warn};
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState, Region},
+    data_device_manager::DataDeviceManagerState,
     delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
-    delegate_registry, delegate_seat, delegate_touch,
+    delegate_registry, delegate_seat, delegate_session_lock, delegate_shm, delegate_subcompositor,
+    delegate_touch, delegate_xdg_popup,
+    globals::{GlobalData, ProvidesBoundGlobal},
     output::{OutputHandler, OutputState},
-    reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1,
+    primary_selection::PrimarySelectionManagerState,
+    reexports::{
+        protocols::{
+            ext::idle_notify::v1::client::ext_idle_notification_v1::ExtIdleNotificationV1,
+            wp::{
+                alpha_modifier::v1::client::wp_alpha_modifier_surface_v1::WpAlphaModifierSurfaceV1,
+                color_management::v1::client::wp_color_management_surface_v1::WpColorManagementSurfaceV1,
+                content_type::v1::client::wp_content_type_v1::WpContentTypeV1,
+                fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1,
+                viewporter::client::wp_viewport::WpViewport,
+            },
+        },
+        protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+    },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
         Capability, SeatHandler, SeatState,
         keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers},
-        pointer::{PointerEvent, PointerEventKind, PointerHandler},
+        pointer::{
+            BTN_BACK, BTN_EXTRA, BTN_FORWARD, BTN_MIDDLE, BTN_RIGHT, BTN_SIDE, PointerEvent,
+            PointerEventKind, PointerHandler,
+        },
         touch::TouchHandler,
     },
+    session_lock::{
+        SessionLock, SessionLockHandler, SessionLockState, SessionLockSurface,
+        SessionLockSurfaceConfigure,
+    },
     shell::{
         WaylandSurface,
-        wlr_layer::{LayerShell, LayerShellHandler, LayerSurfaceConfigure},
+        wlr_layer::{LayerShell, LayerShellHandler, LayerSurfaceConfigure, SurfaceKind},
+        xdg::popup::{Popup, PopupConfigure, PopupHandler},
     },
+    shm::{Shm, ShmHandler},
 };
 pub use smithay_client_toolkit::{
     output::OutputInfo,
@@ -39,471 +78,4657 @@ pub use smithay_client_toolkit::{
 };
 use wayland_backend::client::{ObjectId, WaylandError};
 use wayland_client::{
-    Connection, DispatchError, EventQueue, Proxy as _, QueueHandle,
-    globals::registry_queue_init,
+    Connection, DispatchError, EventQueue, Proxy, QueueHandle,
+    globals::{BindError, GlobalList, registry_queue_init},
     protocol::{
         wl_keyboard,
         wl_output::{self},
-        wl_pointer, wl_seat, wl_surface, wl_touch,
+        wl_pointer, wl_seat,
+        wl_subcompositor::WlSubcompositor,
+        wl_surface, wl_touch,
     },
 };
 use wgpu::{
-    CompositeAlphaMode,
+    CompositeAlphaMode, SurfaceCapabilities, TextureFormat,
     rwh::{RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle},
 };
 
-use self::{wp_fractional_scaling::FractionalScalingManager, wp_viewporter::ViewporterState};
+#[cfg(feature = "accesskit")]
+use self::accessibility::Accessibility;
+#[cfg(feature = "zbus")]
+pub use self::dbus::{forward_dbus_signals, spawn_dbus_call};
+#[cfg(feature = "dmabuf")]
+pub use self::dmabuf::{Dmabuf, DmabufError, DmabufPlane, import_texture, request_device};
+#[cfg(feature = "notifications")]
+pub use self::notifications::{
+    CloseReason, Expiry, Notification, NotificationCallback, NotificationEvent, NotificationId,
+    NotificationServer, NotificationServerOpts, Urgency,
+};
+#[cfg(feature = "persistence")]
+use self::persistence::MemoryStorage;
+#[cfg(feature = "persistence")]
+pub use self::persistence::Storage;
+use self::{
+    capture::ScreencopyState,
+    clipboard::ClipboardState,
+    ext_idle_notify::IdleNotifyState,
+    loop_waker::LoopWaker,
+    output_management::OutputManagementState,
+    output_power::OutputPowerState,
+    popup::{PopupShell, PopupSurface, XdgShellState},
+    repaint_timer::{RepaintScheduler, RepaintTimer, RequestFrame},
+    subsurface::Subsurfaces,
+    system_theme::SystemTheme,
+    toplevels::ToplevelsState,
+    viewport::{ViewportShell, ViewportSurface},
+    virtual_pointer::VirtualPointerState,
+    window::WindowSurface,
+    wp_alpha_modifier::AlphaModifierState,
+    wp_color_management::ColorManagerState,
+    wp_content_type::ContentTypeState,
+    wp_fractional_scaling::FractionalScalingManager,
+    wp_idle_inhibit::{IdleInhibitState, IdleInhibitor},
+    wp_input_timestamps::InputTimestampsState,
+    wp_presentation_time::PresentationTimeState,
+    wp_single_pixel_buffer::SinglePixelBufferState,
+    wp_viewporter::ViewporterState,
+};
+pub use self::{
+    capture::{
+        Capture, CaptureCallback, CaptureError, CaptureSession, ContinuousCaptureCallback,
+        Screencopy,
+    },
+    child_surface::{ChildSurfaceOpts, Side},
+    clipboard::{Clipboard, ClipboardCallback, ClipboardData, TEXT_MIME_TYPES},
+    error::{Error, GeometryError},
+    frame::{LayerFrame, LayerSetup},
+    output_management::{
+        OutputConfigCallback, OutputConfigResult, OutputHead, OutputHeadConfig, OutputHeadId,
+        OutputHeadsCallback, OutputManagement, OutputMode, OutputModeId,
+    },
+    output_power::OutputPower,
+    output_selector::OutputSelector,
+    raw_events::{ProtocolHandler, RawEvents},
+    spawner::Spawner,
+    subsurface::Subsurface,
+    system_theme::Appearance,
+    toplevels::{Toplevel, ToplevelCallback, ToplevelEvent, ToplevelId, ToplevelState, Toplevels},
+    virtual_pointer::VirtualPointer,
+    wp_color_management::ImageDescription,
+    wp_content_type::ContentType,
+    wp_presentation_time::{Presentation, PresentationFeedback},
+    wp_single_pixel_buffer::SolidLayer,
+};
 
+#[cfg(feature = "accesskit")]
+mod accessibility;
+mod capture;
+mod child_surface;
+mod clipboard;
+#[cfg(feature = "zbus")]
+mod dbus;
+#[cfg(feature = "dmabuf")]
+mod dmabuf;
+mod error;
+mod ext_idle_notify;
+mod frame;
 mod keysyms;
+mod loop_waker;
+#[cfg(feature = "notifications")]
+mod notifications;
+mod output_management;
+mod output_power;
+mod output_selector;
+#[cfg(feature = "persistence")]
+mod persistence;
+mod popup;
+mod raw_events;
+mod repaint_timer;
+mod screenshot;
+mod spawner;
+mod subsurface;
+mod system_theme;
+mod toplevels;
+mod viewport;
+mod virtual_pointer;
+mod window;
+mod wp_alpha_modifier;
+mod wp_color_management;
+mod wp_content_type;
 mod wp_fractional_scaling;
+mod wp_idle_inhibit;
+mod wp_input_timestamps;
+mod wp_presentation_time;
+mod wp_single_pixel_buffer;
 mod wp_viewporter;
 
 const DEFAULT_WIDTH: u32 = 1920;
 const DEFAULT_HEIGHT: u32 = 1080;
 
+/// The connection to the compositor, along with the apps that are shown through it.
+///
+/// It can be created and dispatched on any thread, eg. a worker thread of a larger application,
+/// but has to stay on the thread it was created on: it isn't `Send`, since apps don't have to be.
+/// Other threads control the apps through their [`LayerAppHandle`], which is `Send` and `Sync`.
+/// It wakes up a [`Context::blocking_dispatch`] on the context's thread to apply what was asked
+/// for, eg. a repaint, a new size or exiting the app.
+///
+/// The connection can be shared with other toolkits in the same process, eg. winit or another
+/// SCTK based component, see [`Context::with_connection`].
 pub struct Context {
     event_queue: EventQueue<ContextDelegate>,
+    globals: GlobalList,
+    spawner: Spawner,
     delegate: ContextDelegate,
 }
 
-struct ContextDelegate {
+/// The state that the context's Wayland events are dispatched to, see [`RawEvents`].
+pub struct ContextDelegate {
     wayland_conn: Connection,
     compositor: CompositorState,
-    layer_shell: LayerShell,
-    fractional_scaling: FractionalScalingManager,
-    viewporter: ViewporterState,
+    /// `None` on compositors like GNOME, then layer apps can fall back to windows.
+    layer_shell: Option<LayerShell>,
+    subcompositor: Option<WlSubcompositor>,
+    xdg_shell: Option<XdgShellState>,
+    session_lock_state: SessionLockState,
+    /// The session lock that's currently held, if any.
+    session_lock: Option<ActiveSessionLock>,
+    /// See [`Context::new_layer_app_per_output`].
+    output_apps: Vec<ActiveOutputApp>,
+    fractional_scaling: Option<FractionalScalingManager>,
+    viewporter: Option<ViewporterState>,
+    single_pixel_buffer: Option<SinglePixelBufferState>,
+    alpha_modifier: Option<AlphaModifierState>,
+    content_type: Option<ContentTypeState>,
+    presentation_time: Option<PresentationTimeState>,
+    idle_inhibit: Option<IdleInhibitState>,
+    idle_notify: Option<IdleNotifyState>,
+    /// See [`Context::set_idle_timeout`].
+    idle_timeout: Option<Duration>,
+    idle_notification: Option<(wl_seat::WlSeat, ExtIdleNotificationV1)>,
+    color_manager: Option<ColorManagerState>,
+    clipboard: ClipboardState,
+    toplevels: Option<ToplevelsState>,
+    output_management: Option<OutputManagementState>,
+    output_power: Option<OutputPowerState>,
+    shm: Shm,
+    screencopy: Option<ScreencopyState>,
+    virtual_pointer: Option<VirtualPointerState>,
+    input_timestamps: Option<InputTimestampsState>,
     registry_state: RegistryState,
     seat_state: SeatState,
     output_state: OutputState,
     wgpu_instance: wgpu::Instance,
+    wgpu_configuration: WgpuConfiguration,
+    /// Created along with the first app, once there's a surface to pick an adapter for.
+    render_state: Option<egui_wgpu::RenderState>,
     keyboard: Option<wl_keyboard::WlKeyboard>,
     pointer: Option<wl_pointer::WlPointer>,
     touch: Option<wl_touch::WlTouch>,
     touches: HashMap<i32, TouchState>,
+    /// Serial of the last input event, needed to set the clipboard.
+    last_serial: u32,
+    /// Set when drawing fails, returned from the next dispatch.
+    surface_error: Option<wgpu::SurfaceError>,
     apps: HashMap<ObjectId, LayerApp>,
+    /// Apps whose output was removed, they're shown again once it's added back.
+    lost_apps: Vec<LayerApp>,
+    solid_layers: HashMap<ObjectId, Weak<SolidLayer>>,
+    system_theme: SystemTheme,
+    repaint_timer: RepaintTimer,
+    waker: LoopWaker,
 }
 
 struct TouchState {
-    surface_id: ObjectId,
+    surface: wl_surface::WlSurface,
     last_position: Pos2,
 }
 
 impl ContextDelegate {
-    fn scale_factor_changed(
-        &mut self,
-        qh: &QueueHandle<Self>,
-        surface: &wl_surface::WlSurface,
-        new_factor: f32,
-    ) {
-        if let Some(app) = self.apps.get_mut(&surface.id()) {
-            if app.scale == new_factor {
-                // No change
-                return;
-            }
+    /// Cover the output with a new lock surface, if the session is locked.
+    fn add_lock_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let Some(session_lock) = &mut self.session_lock else {
+            return;
+        };
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
 
-            println!("Scale factor changed to {new_factor}");
+        let app = (session_lock.new_app)(&info, session_lock.handle.clone());
+        let app_id = app.app_id();
+        let new_app = |_: &CreationContext<'_>| app;
+        let lock_surface = session_lock.handle.session_lock.create_lock_surface(
+            self.compositor.create_surface(qh),
+            &output,
+            qh,
+        );
+        session_lock
+            .surfaces
+            .push((output, lock_surface.wl_surface().id()));
+
+        let SessionLockOpts {
+            opaque_regions,
+            surface_format,
+            render_scale,
+            msaa_samples,
+            dithering,
+            image_description,
+        } = session_lock.opts;
+
+        // This only blocks if it's the first app, when the GPU adapter and device are requested
+        let result = pollster::block_on(self.new_app(
+            qh,
+            new_app,
+            Some(info),
+            ShellSurface::Lock(lock_surface),
+            AppOpts {
+                app_id,
+                namespace: None,
+                input_regions: InputRegions::Full,
+                opaque_regions,
+                surface_format,
+                render_scale,
+                msaa_samples,
+                dithering,
+                popup_space: Marginf::ZERO,
+                viewports: false,
+                image_description,
+                // There's nothing to open URLs in while the session is locked
+                url_opener: Some(Box::new(|_| {})),
+                default_size: None,
+                #[cfg(feature = "persistence")]
+                persist_memory: false,
+                desktop_style: false,
+                setup: None,
+            },
+        ));
+        if let Err(e) = result {
+            error!("Failed to create lock app: {e}");
+        }
+    }
 
-            let viewport = self.viewporter.get_viewport(app.layer.wl_surface(), qh);
-            viewport.set_destination(app.width as i32, app.height as i32);
+    fn end_session_lock_if_unlocked(&mut self) {
+        let unlocked = self.session_lock.as_ref().is_some_and(|session_lock| {
+            session_lock.handle.unlocked.load(Ordering::Relaxed)
+                && session_lock.handle.session_lock.is_locked()
+        });
 
-            app.scale = new_factor;
-            app.draw(&self.compositor);
+        if unlocked {
+            self.end_session_lock();
         }
     }
 
-    fn key_event(&mut self, event: KeyEvent, pressed: bool) {
-        if let Some(app) = self.apps.values_mut().find(|app| app.keyboard_focus) {
-            if let Some(c) = event.utf8 {
-                if !c.is_empty() && c.chars().all(|c| !c.is_control()) {
-                    app.events.push(egui::Event::Text(c));
-                }
+    /// Close the lock surfaces, once the session is unlocked or the lock failed.
+    fn end_session_lock(&mut self) {
+        let Some(session_lock) = self.session_lock.take() else {
+            return;
+        };
+
+        for (_, id) in session_lock.surfaces {
+            if let Some(mut app) = self.remove_app(&id) {
+                app.app.on_exit();
             }
+        }
+    }
 
-            let Some(key) = keysyms::wl_to_egui(event.keysym) else {
-                println!(
-                    "Unknown keysym: name: {:?}, char: {:?}",
-                    event.keysym.name(),
-                    event.keysym.key_char(),
-                );
-                return;
-            };
+    /// Get idle notifications for the seat, if there's a timeout.
+    fn watch_idle(&mut self, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        let (Some(idle_notify), Some(timeout)) = (&self.idle_notify, self.idle_timeout) else {
+            return;
+        };
 
-            app.events.push(egui::Event::Key {
-                key,
-                physical_key: None,
-                pressed,
-                repeat: false,
-                modifiers: app.modifiers,
-            });
-            app.egui_context.request_repaint();
-        } else {
-            println!("No app with keyboard focus");
+        let notification = idle_notify.get_notification(timeout, &seat, qh);
+        self.idle_notification = Some((seat, notification));
+    }
+
+    /// Commit what an app asked for outside of drawing, from the event loop's thread, see
+    /// [`LoopWaker`]. Also closes the app once its close timer ran out, whether it's drawn or not.
+    fn wake_app(&mut self, qh: &QueueHandle<Self>, surface: &wl_surface::WlSurface) {
+        let id = surface.id();
+        let Some(app) = self.apps.get_mut(&id) else {
+            return;
+        };
+        if app.exit.load(Ordering::Relaxed) || app.close_timer_expired() {
+            if let Some(mut app) = self.remove_app(&id) {
+                app.app.on_exit();
+            }
+            return;
         }
+        app.arm_close_timer();
+        if app.unmapped || !app.needs_new_layer_surface() {
+            app.commit_pending(qh);
+            return;
+        }
+        self.replace_layer_surface(qh, &id);
     }
-}
 
-/// Whether/how to use input regions, can be used to let mouse and touch inputs fall through the
-/// layer surface.
-#[derive(Debug, Clone, Copy, Default)]
-pub enum InputRegions {
-    /// The entire surface takes mouse inputs.
-    #[default]
-    Full,
-    /// The background layer doesn't take inputs, only windows and popups do. This means that eg.
-    /// CentralPanels and SidePanels will not take inputs, because they're typically drawn on the
-    /// background layer.
-    WindowsOnly,
-    /// The layer surface doesn't take any mouse or touch inputs at all.
-    None,
-    // TODO: add more options (select layers, custom behaviour)
-}
+    /// Resume drawing the apps that stopped because their outputs were off.
+    fn output_powered_on(&mut self, qh: &QueueHandle<Self>, output: &wl_output::WlOutput) {
+        for app in self.apps.values_mut() {
+            if app.powered_off && app.outputs.contains(output) {
+                app.powered_off = false;
+                app.frame_requested.store(true, Ordering::SeqCst);
+                app.commit_pending(qh);
+            }
+        }
+    }
 
-pub struct LayerAppOpts<'a> {
-    pub layer: Layer,
-    pub namespace: Option<&'a str>,
-    pub output: Option<&'a dyn Fn(OutputInfo) -> bool>,
-    pub input_regions: InputRegions,
-}
+    fn presentation_feedback(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        feedback: PresentationFeedback,
+    ) {
+        let Some(app) = self.apps.get_mut(&surface.id()) else {
+            return;
+        };
 
-impl Default for LayerAppOpts<'_> {
-    fn default() -> Self {
-        Self {
-            layer: Layer::Top,
-            namespace: Default::default(),
-            output: Default::default(),
-            input_regions: InputRegions::Full,
+        if let PresentationFeedback::Presented(presentation) = feedback {
+            app.frame_stats.lock().unwrap().latency = presentation.latency;
+        }
+        app.app.on_presentation(&feedback);
+    }
+
+    /// The user closed an app's window, so exit the app.
+    fn close_window(&mut self, surface: &wl_surface::WlSurface) {
+        if let Some(app) = self.apps.get(&surface.id()) {
+            app.exit.store(true, Ordering::Relaxed);
+            app.egui_context.request_repaint();
         }
     }
-}
 
-// pub type OutputSelector = Box<dyn Fn(OutputInfo) -> bool>;
+    fn idle_changed(&mut self, idle: bool) {
+        for app in self.apps.values_mut() {
+            app.app.on_idle(idle);
+            app.egui_context.request_repaint();
+        }
+    }
 
-impl Context {
-    pub fn new() -> Self {
-        // All Wayland apps start by connecting the compositor (server).
-        // TODO: reuse between instancces?
-        let wayland_conn = Connection::connect_to_env().unwrap();
+    /// Handle the compositor telling an app's surface what size it should be.
+    fn configure_app(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        new_size: (u32, u32),
+        qh: &QueueHandle<Self>,
+    ) {
+        if let Some(app) = self.apps.get_mut(&surface.id()) {
+            let old_size = (app.width, app.height);
+            // The compositor leaves the size up to the app on axes without a size or two anchors
+            let (width, height) = app.default_size();
+            app.width = if new_size.0 > 0 { new_size.0 } else { width };
+            app.height = if new_size.1 > 0 { new_size.1 } else { height };
+            if app.first_configure || (app.width, app.height) != old_size {
+                app.app.on_configure(app.width, app.height);
+            }
 
-        // Enumerate the list of globals to get the protocols the server implements.
-        let (globals, event_queue) = registry_queue_init(&wayland_conn).unwrap();
+            if let Some(viewport) = &app.viewport {
+                viewport.set_destination(app.width as i32, app.height as i32);
+            }
 
-        let wgpu_instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        });
+            // let surface_format = app
+            //     .wgpu_surface
+            //     .get_supported_formats(&app.egui_render_state.adapter)[0];
 
-        let qh = event_queue.handle();
+            // let mut surface_config = wgpu::SurfaceConfiguration {
+            //     usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            //     format: TextureFormat::Bgra8UnormSrgb,
+            //     width: app.width,
+            //     height: app.height,
+            //     present_mode: wgpu::PresentMode::Fifo,
+            //     alpha_mode: CompositeAlphaMode::Auto,
+            //     desired_maximum_frame_latency
+            // };
+            // surface.configure(&device, &surface_config);
+
+            if app.first_configure {
+                // The color manager's supported features are known by now, they're sent right
+                // after binding it.
+                if let Some(description) = app.image_description {
+                    app.color_surface = self.color_manager.as_ref().and_then(|color_manager| {
+                        color_manager.set_image_description(
+                            app.shell_surface.wl_surface(),
+                            description,
+                            qh,
+                        )
+                    });
+
+                    if app.color_surface.is_none() {
+                        warn!("Image description {description:?} is not supported");
+                    }
+                }
+            }
 
-        let compositor =
-            CompositorState::bind(&globals, &qh).expect("wl_compositor is not available");
+            // Initiate the first draw, or the first one after being shown again. The configure is
+            // already acked, so a new size is drawn right away too, instead of with the next frame
+            // callback: the compositor may show the old buffer stretched or cut off until then, eg.
+            // when another surface's exclusive zone changed.
+            let resized = (app.width, app.height) != old_size;
+            if app.first_configure || app.unmapped || resized {
+                app.first_configure = false;
+                app.unmapped = false;
 
-        let layer_shell = LayerShell::bind(&globals, &qh).expect("layer shell is not available");
+                if let Err(e) = app.draw(&self.compositor, qh) {
+                    self.surface_error = Some(e);
+                }
+            }
+        }
+    }
 
-        let fractional_scaling = FractionalScalingManager::bind(&globals, &qh).unwrap();
-        let viewporter = ViewporterState::bind(&globals, &qh).unwrap();
+    /// Create the app of `self.output_apps[index]` on the output, if it wants one there.
+    fn add_output_app(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        index: usize,
+        output: wl_output::WlOutput,
+    ) {
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
+        let output_app = &mut self.output_apps[index];
+        if output_app.handle.exit.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some((app, opts)) = (output_app.new_app)(&info) else {
+            return;
+        };
 
-        Context {
-            event_queue,
-            delegate: ContextDelegate {
-                wayland_conn,
-                compositor,
-                layer_shell,
-                fractional_scaling,
-                viewporter,
-                registry_state: RegistryState::new(&globals),
-                seat_state: SeatState::new(&globals, &qh),
-                output_state: OutputState::new(&globals, &qh),
-                wgpu_instance,
-                keyboard: None,
-                pointer: None,
-                touch: None,
-                touches: HashMap::new(),
-                apps: HashMap::new(),
-            },
+        let handle = output_app.handle.clone();
+        let app_id = app.app_id();
+        // This only blocks if it's the first app, when the GPU adapter and device are requested
+        let result = self.new_layer_app(qh, app_id, |_| app, opts, Some(&output));
+        match pollster::block_on(result) {
+            Ok(app_handle) => handle.apps.lock().unwrap().push((output, app_handle)),
+            Err(e) => error!("Failed to create app for output: {e}"),
         }
     }
 
-    pub fn new_layer_app(
+    /// Create a layer surface for an app, on `output` or one the compositor picks. `app_id` is
+    /// the id the app is created with, see [`CreationContext::app_id`].
+    async fn new_layer_app(
         &mut self,
-        mut app: Box<dyn App>,
+        qh: &QueueHandle<Self>,
+        app_id: String,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App>,
         LayerAppOpts {
             layer,
             namespace,
-            output,
+            output: _,
             input_regions,
-        }: LayerAppOpts,
-    ) -> LayerAppHandle {
-        let qh = self.event_queue.handle();
+            opaque_regions,
+            surface_format,
+            render_scale,
+            msaa_samples,
+            dithering,
+            popup_space,
+            viewports,
+            image_description,
+            url_opener,
+            window_fallback,
+            default_size,
+            #[cfg(feature = "persistence")]
+            persist_memory,
+            desktop_style,
+            setup,
+        }: LayerAppOpts<'_>,
+        output: Option<&wl_output::WlOutput>,
+    ) -> Result<LayerAppHandle, Error> {
+        let namespace = namespace.map_or_else(|| app_id.clone(), str::to_owned);
 
         // A layer surface is created from a surface.
-        let wl_surface = self.delegate.compositor.create_surface(&qh);
-
-        let output = output.and_then(|selector| {
-            self.delegate
-                .output_state
-                .outputs()
-                .filter_map(|output| {
-                    self.delegate
-                        .output_state
-                        .info(&output)
-                        .map(|info| (info, output))
-                })
-                .find_map(|(info, output)| selector(info).then_some(output))
-        });
-
-        // And then we create the layer shell.
-        let layer = self.delegate.layer_shell.create_layer_surface(
-            &qh,
-            wl_surface,
-            layer,
-            namespace,
-            output.as_ref(),
-        );
+        let wl_surface = self.compositor.create_surface(qh);
+
+        let shell_surface = match (&self.layer_shell, &self.xdg_shell) {
+            (Some(layer_shell), _) => {
+                // And then we create the layer shell.
+                let layer = layer_shell.create_layer_surface(
+                    qh,
+                    wl_surface,
+                    layer,
+                    Some(namespace.clone()),
+                    output,
+                );
 
-        app.on_init(&layer);
+                ShellSurface::Layer(layer)
+            }
+            // There's no layer surface to set up in `on_init`, so anchors, exclusive zones and
+            // keyboard interactivity don't apply. The window is resized by the user instead.
+            (None, Some(xdg_shell)) if window_fallback => {
+                ShellSurface::Window(WindowSurface::new(xdg_shell, wl_surface, &namespace, qh))
+            }
+            (None, _) => {
+                wl_surface.destroy();
+                return Err(Error::MissingProtocol("zwlr_layer_shell_v1"));
+            }
+        };
 
         match input_regions {
-            InputRegions::Full => layer.set_input_region(None),
-            InputRegions::WindowsOnly | InputRegions::None => {
-                if let Ok(region) = Region::new(&self.delegate.compositor) {
+            InputRegions::Full => shell_surface.set_input_region(None),
+            InputRegions::WindowsOnly | InputRegions::Custom | InputRegions::None => {
+                if let Ok(region) = Region::new(&self.compositor) {
                     region.add(0, 0, 0, 0);
-                    layer.set_input_region(Some(region.wl_region()));
+                    shell_surface.set_input_region(Some(region.wl_region()));
                 }
             }
         }
 
-        let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
-            NonNull::new(self.delegate.wayland_conn.backend().display_ptr() as *mut _).unwrap(),
-        ));
-        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
-            NonNull::new(layer.wl_surface().id().as_ptr() as *mut _).unwrap(),
-        ));
-
-        let wgpu_surface = unsafe {
-            self.delegate
-                .wgpu_instance
-                .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
-                    raw_display_handle,
-                    raw_window_handle,
-                })
-                .expect("Failed to create wgpu surface")
+        // Popups are only positioned relative to layer surfaces
+        let popup_space = match shell_surface {
+            ShellSurface::Window(_) => Marginf::ZERO,
+            _ => popup_space,
         };
 
-        // // TODO: make this function async instead of block on these?
+        let output = output.and_then(|output| self.output_state.info(output));
+        let handle = self
+            .new_app(qh, new_app, output, shell_surface, AppOpts {
+                app_id,
+                namespace: Some(namespace),
+                input_regions,
+                opaque_regions,
+                surface_format,
+                render_scale,
+                msaa_samples,
+                dithering,
+                popup_space,
+                viewports,
+                image_description,
+                url_opener,
+                default_size,
+                #[cfg(feature = "persistence")]
+                persist_memory,
+                desktop_style,
+                setup,
+            })
+            .await?;
+        // So setting the same layer again doesn't do anything
+        handle.geometry.lock().unwrap().layer = Some(layer);
+
+        Ok(handle)
+    }
+
+    /// Set up rendering and input for an app on the given surface, which is the same for all
+    /// shell roles.
+    async fn new_app(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App>,
+        output: Option<OutputInfo>,
+        shell_surface: ShellSurface,
+        AppOpts {
+            app_id,
+            namespace,
+            input_regions,
+            opaque_regions,
+            surface_format,
+            render_scale,
+            msaa_samples,
+            dithering,
+            popup_space,
+            viewports,
+            image_description,
+            url_opener,
+            default_size,
+            #[cfg(feature = "persistence")]
+            persist_memory,
+            desktop_style,
+            setup,
+        }: AppOpts,
+    ) -> Result<LayerAppHandle, Error> {
+        if let OpaqueRegions::Full = opaque_regions {
+            if let Ok(region) = Region::new(&self.compositor) {
+                region.add(0, 0, i32::MAX, i32::MAX);
+                shell_surface
+                    .wl_surface()
+                    .set_opaque_region(Some(region.wl_region()));
+            }
+        }
+
+        let wgpu_surface = self.create_wgpu_surface(shell_surface.wl_surface())?;
+
         let egui_context = egui::Context::default();
 
         let frame_requested = Arc::new(AtomicBool::new(true));
+        let drawing = Arc::new(AtomicBool::new(false));
 
-        {
-            let surface = layer.wl_surface().clone();
-            let qh = qh.clone();
-            let frame_requested = frame_requested.clone();
-            egui_context.set_request_repaint_callback(move |_info| {
-                // TODO: handle info.delay
-                if !frame_requested.load(Ordering::Relaxed) {
-                    surface.frame(&qh, surface.clone());
-                    frame_requested.store(true, Ordering::Relaxed);
-                } else {
-                    println!("dropped");
-                }
-            });
+        self.set_repaint_callback(
+            &egui_context,
+            shell_surface.wl_surface(),
+            frame_requested.clone(),
+            drawing.clone(),
+        );
+
+        // The adapter, device and queue are shared between all apps
+        let shared_render_state = match &self.render_state {
+            Some(render_state) => render_state.clone(),
+            None => {
+                let render_state = egui_wgpu::RenderState::create(
+                    &self.wgpu_configuration,
+                    &self.wgpu_instance,
+                    Some(&wgpu_surface),
+                    None,
+                    msaa_samples,
+                    dithering,
+                )
+                .await?;
+                self.render_state = Some(render_state.clone());
+                render_state
+            }
+        };
+
+        let capabilities = wgpu_surface.get_capabilities(&shared_render_state.adapter);
+
+        let format = surface_format
+            .select(&capabilities.formats)
+            .unwrap_or(shared_render_state.target_format);
+
+        // Each app gets its own renderer though, as it holds the textures of a single egui context
+        // and its shaders depend on the target format
+        let egui_render_state = egui_wgpu::RenderState {
+            target_format: format,
+            renderer: Arc::new(RwLock::new(Renderer::new(
+                &shared_render_state.device,
+                format,
+                None,
+                msaa_samples,
+                dithering,
+            ))),
+            ..shared_render_state
+        };
+
+        // The outputs the surface is on aren't known until it's shown
+        let default_size = default_size.or_else(|| output.as_ref().and_then(output_size));
+        #[cfg(feature = "persistence")]
+        let mut memory_storage = persist_memory
+            .then(|| MemoryStorage::new(&app_id, output.as_ref()))
+            .flatten();
+        #[cfg(feature = "persistence")]
+        if let Some(memory_storage) = &mut memory_storage {
+            memory_storage.load(&egui_context);
+        }
+        if let Some(setup) = setup {
+            setup(&egui_context);
+        }
+        let mut app = new_app(&CreationContext {
+            egui_ctx: &egui_context,
+            render_state: &egui_render_state,
+            output,
+            app_id: &app_id,
+            #[cfg(feature = "persistence")]
+            storage: memory_storage
+                .as_ref()
+                .map(|memory_storage| &memory_storage.storage),
+        });
+        // Apps that are created from the creation context only have their own id now
+        let app_id = app.app_id();
+        if let ShellSurface::Window(window) = &shell_surface {
+            window.set_app_id(&app_id);
+        }
+        let mut geometry = LayerGeometry::default();
+        if let ShellSurface::Layer(layer) = &shell_surface {
+            let setup = LayerSetup::new(layer);
+            app.on_init(&setup);
+            geometry.init = setup.init.get();
+            if let Err(e) = geometry.validate() {
+                app.on_exit();
+                return Err(e.into());
+            }
         }
+        app.on_render_state(&egui_render_state);
 
-        let msaa_samples = 1;
-        let dithering = true;
-        let egui_render_state = pollster::block_on(egui_wgpu::RenderState::create(
-            &WgpuConfiguration::default(),
-            &self.delegate.wgpu_instance,
-            Some(&wgpu_surface),
-            None,
-            msaa_samples,
-            dithering,
-        ))
-        .expect("Failed to create egui render state");
+        let surface_config = surface_configuration(&capabilities, egui_render_state.target_format);
 
         // In order for the layer surface to be mapped, we need to perform an initial commit with no attached\
         // buffer. For more info, see WaylandSurface::commit
         //
         // The compositor will respond with an initial configure that we can then use to present to the layer
         // surface with the correct options.
-        layer.commit();
+        shell_surface.commit();
 
-        let fractional_scale = self
-            .delegate
-            .fractional_scaling
-            .fractional_scaling(layer.wl_surface(), &qh);
+        let (viewport, fractional_scale) = self.scaling(shell_surface.wl_surface(), qh);
+        let render_scale = if viewport.is_some() { render_scale } else { 1. };
 
         let exit = Arc::new(AtomicBool::new(false));
+        let hidden = Arc::new(AtomicBool::new(false));
+        let geometry = Arc::new(Mutex::new(geometry));
+        let properties = Arc::new(Mutex::new(SurfaceProperties::default()));
+        let subsurfaces = Subsurfaces::default();
+        let closed = Arc::new(AtomicBool::new(false));
+        self.system_theme.add_app(&egui_context, closed.clone());
+        let close_listeners = Arc::new(Mutex::new(CloseListeners::default()));
+        let close_timer = Arc::new(Mutex::new(None));
+        let new_input_regions = Arc::new(Mutex::new(None));
+        let frame_stats = Arc::new(Mutex::new(FrameStats::default()));
+        let render_state = egui_render_state.clone();
+        let offscreen_requests = Arc::new(Mutex::new(Vec::new()));
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let buffer_transform = Arc::new(Mutex::new(wl_output::Transform::Normal));
+        let output_info = Arc::new(Mutex::new(Vec::new()));
+        let pending_events = Arc::new(Mutex::new(Vec::new()));
+        #[cfg(feature = "accesskit")]
+        let accessibility = Accessibility::new(&app_id, &egui_context, pending_events.clone());
+        let app_surface = Arc::new(Mutex::new(self.app_surface(&shell_surface, qh)));
+        let popup_shell = self
+            .popup_shell(qh)
+            .filter(|_| popup_space != Marginf::ZERO);
+        let viewport_shell = self.viewport_shell(qh).filter(|_| viewports);
+        // Otherwise egui shows them inside the app's surface
+        egui_context.set_embed_viewports(viewport_shell.is_none());
+        let idle_inhibitor = self.idle_inhibit.clone().map(|idle_inhibit| {
+            Arc::new(IdleInhibitor::new(
+                idle_inhibit,
+                shell_surface.wl_surface().clone(),
+                qh.clone(),
+            ))
+        });
 
-        self.delegate.apps.insert(
-            layer.wl_surface().id(),
-            LayerApp {
-                app,
-                wgpu_surface,
-                egui_context: egui_context.clone(),
-                egui_render_state,
-                layer,
-                fractional_scale,
-
-                frame_requested,
-                start: Instant::now(),
-                events: Vec::new(),
-                modifiers: egui::Modifiers::default(),
-                input_regions,
-                exit: exit.clone(),
-                first_configure: true,
-                width: DEFAULT_WIDTH,
-                height: DEFAULT_HEIGHT,
-                scale: 1.,
-                shift: None,
-                keyboard_focus: false,
+        let id = shell_surface.wl_surface().id();
+        self.apps.insert(id.clone(), LayerApp {
+            app,
+            app_id: app_id.clone(),
+            namespace,
+            wgpu_surface,
+            popups: Vec::new(),
+            dismissed_popups: HashSet::new(),
+            children: Vec::new(),
+            popup_space: if popup_shell.is_some() {
+                popup_space
+            } else {
+                Marginf::ZERO
             },
-        );
-
-        LayerAppHandle { egui_context, exit }
-    }
+            popup_shell,
+            viewport_surfaces: Vec::new(),
+            viewport_shell,
+            egui_context: egui_context.clone(),
+            egui_render_state,
+            surface_config,
+            surface_configured: false,
+            previous_shapes: Vec::new(),
+            msaa_samples,
+            msaa_texture: None,
+            shell_surface,
+            fractional_scale,
+            viewport,
+            render_scale,
+            image_description,
+            color_surface: None,
+
+            frame_requested,
+            drawing,
+            frame_pending: false,
+            start: Instant::now(),
+            time: 0.,
+            input_times: Vec::new(),
+            events: Vec::new(),
+            pending_events: pending_events.clone(),
+            clipboard: self.clipboard.handle().clone(),
+            cursor_icon: CursorIcon::Default,
+            middle_paste: false,
+            middle_pastes: Arc::new(Mutex::new(Vec::new())),
+            modifiers: egui::Modifiers::default(),
+            input_regions,
+            new_input_regions: new_input_regions.clone(),
+            mouse_passthrough: false,
+            input_rects: None,
+            opaque_regions,
+            url_opener: url_opener.unwrap_or_else(|| Box::new(xdg_open)),
+            exit: exit.clone(),
+            hidden: hidden.clone(),
+            unmapped: false,
+            geometry: geometry.clone(),
+            properties: properties.clone(),
+            subsurfaces: subsurfaces.clone(),
+            closed: closed.clone(),
+            close_listeners: close_listeners.clone(),
+            close_timer: close_timer.clone(),
+            frame_stats: frame_stats.clone(),
+            last_present: None,
+            presentation_time: self.presentation_time.clone(),
+            offscreen_requests: offscreen_requests.clone(),
+            messages: messages.clone(),
+            transform: wl_output::Transform::Normal,
+            buffer_transform: buffer_transform.clone(),
+            idle_inhibitor: idle_inhibitor.clone(),
+            app_surface: app_surface.clone(),
+            target: None,
+            first_configure: true,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            scale: 1.,
+            shift: None,
+            keyboard_focus: false,
+            keyboard_viewport: ViewportId::ROOT,
+            system_theme: self.system_theme.clone(),
+            appearance: Appearance::default(),
+            desktop_style,
+            outputs: Vec::new(),
+            output_info: output_info.clone(),
+            powered_off: false,
+            default_size,
+            handle: None,
+            #[cfg(feature = "accesskit")]
+            accessibility,
+            #[cfg(feature = "persistence")]
+            memory_storage,
+        });
 
-    pub fn poll_dispatch(&mut self) -> Result<usize, DispatchError> {
-        let dispatched = self.event_queue.dispatch_pending(&mut self.delegate)?;
-        if dispatched > 0 {
-            return Ok(dispatched);
+        let handle = Arc::new_cyclic(|weak| LayerAppHandle {
+            app_id,
+            egui_context,
+            exit,
+            hidden,
+            geometry,
+            properties,
+            subsurfaces,
+            closed,
+            close_listeners,
+            close_timer,
+            new_input_regions,
+            frame_stats,
+            render_state,
+            offscreen_requests,
+            messages,
+            buffer_transform,
+            output_info,
+            surface: app_surface,
+            idle_inhibitor,
+            weak: weak.clone(),
+        });
+        if let Some(app) = self.apps.get_mut(&id) {
+            app.handle = Some(handle.clone());
         }
 
-        self.delegate.wayland_conn.flush()?;
-
-        if let Some(guard) = self.delegate.wayland_conn.prepare_read() {
-            match guard.read() {
-                Ok(_) => self.event_queue.dispatch_pending(&mut self.delegate),
-                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(0),
-                Err(e) => Err(e.into()),
-            }
-        } else {
-            Ok(0)
-        }
+        Ok(LayerAppHandle::clone(&handle))
     }
 
-    pub fn blocking_dispatch(&mut self) -> Result<usize, DispatchError> {
-        self.event_queue.blocking_dispatch(&mut self.delegate)
+    fn popup_shell(&self, qh: &QueueHandle<Self>) -> Option<PopupShell> {
+        Some(PopupShell {
+            xdg_shell: self.xdg_shell.clone()?,
+            compositor: self.compositor.clone(),
+            viewporter: self.viewporter.clone()?,
+            wgpu_instance: self.wgpu_instance.clone(),
+            wayland_conn: self.wayland_conn.clone(),
+            queue_handle: qh.clone(),
+        })
     }
-}
 
-impl Default for Context {
-    fn default() -> Self {
-        Self::new()
+    fn viewport_shell(&self, qh: &QueueHandle<Self>) -> Option<ViewportShell> {
+        // Layer surfaces have no parent, so viewports need nothing but the layer shell
+        Some(ViewportShell {
+            layer_shell: ProvidesBoundGlobal::<_, 1>::bound_global(self.layer_shell.as_ref()?)
+                .ok()?,
+            compositor: self.compositor.clone(),
+            viewporter: self.viewporter.clone()?,
+            wgpu_instance: self.wgpu_instance.clone(),
+            wayland_conn: self.wayland_conn.clone(),
+            queue_handle: qh.clone(),
+        })
     }
-}
-
-#[allow(unused_variables)]
-pub trait App {
-    fn update(&mut self, ctx: &egui::Context);
 
-    fn on_init(&mut self, layer: &LayerSurface) {}
+    fn create_wgpu_surface(
+        &self,
+        surface: &wl_surface::WlSurface,
+    ) -> Result<wgpu::Surface<'static>, wgpu::CreateSurfaceError> {
+        let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+            NonNull::new(self.wayland_conn.backend().display_ptr() as *mut _).unwrap(),
+        ));
+        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(
+            NonNull::new(surface.id().as_ptr() as *mut _).unwrap(),
+        ));
+
+        unsafe {
+            self.wgpu_instance
+                .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+                    raw_display_handle,
+                    raw_window_handle,
+                })
+        }
+    }
+
+    /// Request a frame callback on `surface` whenever egui wants a repaint.
+    fn set_repaint_callback(
+        &self,
+        egui_context: &egui::Context,
+        surface: &wl_surface::WlSurface,
+        frame_requested: Arc<AtomicBool>,
+        drawing: Arc<AtomicBool>,
+    ) {
+        let surface = surface.clone();
+        let waker = self.waker.clone();
+        let request_frame: RequestFrame = Arc::new(move || {
+            if frame_requested.swap(true, Ordering::SeqCst) {
+                trace!("A frame is already requested");
+            } else if !drawing.load(Ordering::SeqCst) {
+                // The frame request only takes effect with a commit, which has to happen on the
+                // event loop's thread while it may be blocked waiting for events. Otherwise it's
+                // committed along with the frame that's being drawn.
+                waker.wake(&surface);
+            }
+        });
+        let repaint_scheduler = self.repaint_timer.scheduler();
+        egui_context.set_request_repaint_callback(move |info| {
+            if info.delay.is_zero() {
+                request_frame();
+            } else if info.delay != Duration::MAX {
+                repaint_scheduler.request_frame_after(info.delay, &request_frame);
+            }
+        });
+    }
+
+    fn scaling(
+        &self,
+        surface: &wl_surface::WlSurface,
+        qh: &QueueHandle<Self>,
+    ) -> (Option<WpViewport>, Option<WpFractionalScaleV1>) {
+        // Used to map the buffer, which can be a different size because of scaling, to the surface.
+        let viewport = self
+            .viewporter
+            .as_ref()
+            .map(|viewporter| viewporter.get_viewport(surface, qh));
+
+        // Fractional buffer sizes need the viewport, without it only integer scales from
+        // `wl_surface` are used, set with `set_buffer_scale`
+        let fractional_scale = self
+            .fractional_scaling
+            .as_ref()
+            .filter(|_| viewport.is_some())
+            .map(|fractional_scaling| fractional_scaling.fractional_scaling(surface, qh));
+
+        (viewport, fractional_scale)
+    }
+
+    fn app_surface(&self, shell_surface: &ShellSurface, qh: &QueueHandle<Self>) -> AppSurface {
+        let surface = shell_surface.wl_surface().clone();
+        AppSurface {
+            layer_surface: match shell_surface {
+                ShellSurface::Layer(layer) => match layer.kind() {
+                    SurfaceKind::Wlr(layer_surface) => Some(layer_surface.clone()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            alpha_surface: self
+                .alpha_modifier
+                .as_ref()
+                .map(|alpha_modifier| alpha_modifier.get_surface(&surface, qh)),
+            content_type_surface: self
+                .content_type
+                .as_ref()
+                .map(|content_type| content_type.get_surface_content_type(&surface, qh)),
+            wake: {
+                let waker = self.waker.clone();
+                let surface = surface.clone();
+                Arc::new(move || waker.wake(&surface))
+            },
+            surface,
+            wayland_conn: self.wayland_conn.clone(),
+            waker: self.waker.clone(),
+            repaint_scheduler: self.repaint_timer.scheduler(),
+        }
+    }
+
+    /// Take the apps on a removed output out of the event loop until it's added back.
+    fn output_lost(&mut self, output: &wl_output::WlOutput) {
+        let lost: Vec<_> = self
+            .apps
+            .iter()
+            .filter(|(_, app)| {
+                app.target
+                    .as_ref()
+                    .is_some_and(|target| target.output.as_ref() == Some(output))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in lost {
+            self.lose_app(&id);
+        }
+    }
+
+    /// Remove an app, after closing its child surfaces.
+    fn remove_app(&mut self, id: &ObjectId) -> Option<LayerApp> {
+        self.close_children(id);
+        self.apps.remove(id)
+    }
+
+    /// Close an app's child surfaces, the newest first, since xdg-shell only lets the topmost
+    /// popup be destroyed.
+    fn close_children(&mut self, id: &ObjectId) {
+        let Some(app) = self.apps.get_mut(id) else {
+            return;
+        };
+        for child in take(&mut app.children).iter().rev() {
+            if let Some(mut child) = self.remove_app(child) {
+                child.app.on_exit();
+            }
+        }
+    }
+
+    fn lose_app(&mut self, id: &ObjectId) {
+        let Some(mut app) = self.remove_app(id) else {
+            return;
+        };
+        debug!("Output of app is gone, waiting for it to come back");
+        app.popups.clear();
+        app.dismissed_popups.clear();
+        app.app.on_output_lost();
+        self.lost_apps.push(app);
+    }
+
+    fn output_infos(&self) -> Vec<OutputInfo> {
+        self.output_state
+            .outputs()
+            .filter_map(|output| self.output_state.info(&output))
+            .collect()
+    }
+
+    /// The first output that `selector` picks.
+    fn select_output(&self, selector: &OutputSelector) -> Option<wl_output::WlOutput> {
+        let outputs = self.output_infos();
+        self.output_state.outputs().find(|output| {
+            self.output_state
+                .info(output)
+                .is_some_and(|info| selector.matches(&info, &outputs))
+        })
+    }
+
+    /// Move apps to `output` if their selector picks it, once it's added or its info changed:
+    /// the ones whose output was removed, and the ones on an output the compositor picked because
+    /// none matched before.
+    fn output_found(&mut self, qh: &QueueHandle<Self>, output: &wl_output::WlOutput) {
+        self.lost_apps.retain_mut(|app| {
+            if app.exit.load(Ordering::Relaxed) {
+                app.app.on_exit();
+                return false;
+            }
+            true
+        });
+
+        let Some(info) = self.output_state.info(output) else {
+            return;
+        };
+        let outputs = self.output_infos();
+        let matches = |target: &OutputTarget| target.selector.matches(&info, &outputs);
+
+        let mut index = 0;
+        while index < self.lost_apps.len() {
+            if self.lost_apps[index].target.as_ref().is_some_and(matches) {
+                let app = self.lost_apps.remove(index);
+                self.move_app(qh, app, output);
+            } else {
+                index += 1;
+            }
+        }
+
+        let unmatched: Vec<_> = self
+            .apps
+            .iter()
+            .filter(|(_, app)| {
+                app.target
+                    .as_ref()
+                    .is_some_and(|target| target.output.is_none() && matches(target))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in unmatched {
+            let Some(mut app) = self.remove_app(&id) else {
+                continue;
+            };
+            debug!("Moving app to the output it was meant for");
+            app.popups.clear();
+            app.dismissed_popups.clear();
+            self.move_app(qh, app, output);
+        }
+    }
+
+    /// Show an app on a new layer surface on `output`, that's set up by `on_init` like the first
+    /// one.
+    fn move_app(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        mut app: LayerApp,
+        output: &wl_output::WlOutput,
+    ) {
+        let target = app.target.as_mut().unwrap();
+        target.output = Some(output.clone());
+        self.new_layer_surface(qh, app, Some(output));
+    }
+
+    /// Show an app on a new layer surface, to move it to the output that was picked through its
+    /// handle, or to change its layer when the compositor can't change it on the existing one.
+    fn replace_layer_surface(&mut self, qh: &QueueHandle<Self>, id: &ObjectId) {
+        let Some(mut app) = self.remove_app(id) else {
+            return;
+        };
+        debug!("Creating a new layer surface for the app");
+        app.popups.clear();
+        app.dismissed_popups.clear();
+        let selector = app.geometry.lock().unwrap().output.take();
+        let output = match selector {
+            // If none matches, the compositor picks one until a matching one is added
+            Some(selector) => {
+                let output = self.select_output(&selector);
+                app.target = Some(OutputTarget { output: output.clone(), selector });
+                output
+            }
+            // Apps without a target go wherever the compositor put them
+            None => match &app.target {
+                Some(target) => target.output.clone(),
+                None => app.outputs.first().cloned(),
+            },
+        };
+        self.new_layer_surface(qh, app, output.as_ref());
+    }
+
+    /// Show an app of a previous connection on a new layer surface, see [`Context::reconnect`].
+    /// `lost` apps keep waiting for their output if it isn't there.
+    fn reconnect_app(&mut self, qh: &QueueHandle<Self>, mut app: LayerApp, lost: bool) {
+        app.popups.clear();
+        app.dismissed_popups.clear();
+        app.viewport_surfaces.clear();
+        if app.popup_shell.is_some() {
+            app.popup_shell = self.popup_shell(qh);
+            if app.popup_shell.is_none() {
+                app.popup_space = Marginf::ZERO;
+            }
+        }
+        if app.viewport_shell.is_some() {
+            app.viewport_shell = self.viewport_shell(qh);
+            app.egui_context
+                .set_embed_viewports(app.viewport_shell.is_none());
+        }
+        app.presentation_time = self.presentation_time.clone();
+        app.clipboard = self.clipboard.handle().clone();
+        if let Some(idle_inhibitor) = &app.idle_inhibitor {
+            idle_inhibitor.reconnect(self.idle_inhibit.clone(), qh.clone());
+        }
+        // The seat is new, so nothing is held or focused
+        app.keyboard_focus = false;
+        app.keyboard_viewport = ViewportId::ROOT;
+        app.modifiers = egui::Modifiers::default();
+        app.shift = None;
+        app.middle_paste = false;
+
+        let output = match &mut app.target {
+            Some(target) => {
+                target.output = self.select_output(&target.selector);
+                target.output.clone()
+            }
+            None => None,
+        };
+        if lost && output.is_none() {
+            self.lost_apps.push(app);
+            return;
+        }
+        self.new_layer_surface(qh, app, output.as_ref());
+    }
+
+    /// Give an app a new layer surface in place of its current one, with the layer and settings
+    /// from its handle.
+    fn new_layer_surface(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        mut app: LayerApp,
+        output: Option<&wl_output::WlOutput>,
+    ) {
+        let Some(layer_shell) = &self.layer_shell else {
+            app.app.on_exit();
+            return;
+        };
+        let wl_surface = self.compositor.create_surface(qh);
+        let mut geometry = app.geometry.lock().unwrap();
+        let layer = layer_shell.create_layer_surface(
+            qh,
+            wl_surface,
+            geometry.layer.unwrap_or(Layer::Top),
+            app.namespace.clone(),
+            output,
+        );
+        geometry.new_layer = false;
+        drop(geometry);
+        let setup = LayerSetup::new(&layer);
+        app.app.on_init(&setup);
+        let mut geometry = app.geometry.lock().unwrap();
+        geometry.init = setup.init.get();
+        if let Err(e) = geometry.validate() {
+            error!("Invalid layer surface settings in on_init, closing the app: {e}");
+            drop(geometry);
+            app.app.on_exit();
+            return;
+        }
+        geometry.apply(&layer);
+        drop(geometry);
+        let shell_surface = ShellSurface::Layer(layer);
+        let surface = shell_surface.wl_surface().clone();
+
+        match app.input_regions {
+            InputRegions::Full => shell_surface.set_input_region(None),
+            InputRegions::WindowsOnly | InputRegions::Custom | InputRegions::None => {
+                if let Ok(region) = Region::new(&self.compositor) {
+                    region.add(0, 0, 0, 0);
+                    shell_surface.set_input_region(Some(region.wl_region()));
+                }
+            }
+        }
+        if let OpaqueRegions::Full = app.opaque_regions {
+            if let Ok(region) = Region::new(&self.compositor) {
+                region.add(0, 0, i32::MAX, i32::MAX);
+                surface.set_opaque_region(Some(region.wl_region()));
+            }
+        }
+        shell_surface.commit();
+
+        // The wgpu surface has to go before the old wl_surface, which is destroyed along with
+        // the old layer surface
+        app.wgpu_surface = match self.create_wgpu_surface(&surface) {
+            Ok(wgpu_surface) => wgpu_surface,
+            Err(e) => {
+                error!("Failed to create wgpu surface, closing the app: {e}");
+                app.app.on_exit();
+                return;
+            }
+        };
+        if let Some(viewport) = app.viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = app.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        if let Some(color_surface) = app.color_surface.take() {
+            color_surface.destroy();
+        }
+        (app.viewport, app.fractional_scale) = self.scaling(&surface, qh);
+
+        let old_surface =
+            replace(&mut *app.app_surface.lock().unwrap(), self.app_surface(&shell_surface, qh));
+        old_surface.destroy();
+        // Set again on the new surface's objects with the first frame
+        app.properties.lock().unwrap().changed = true;
+        // The timer of a previous connection is gone
+        app.arm_close_timer();
+        app.shell_surface = shell_surface;
+        if let Some(subcompositor) = &self.subcompositor {
+            app.subsurfaces.reparent(subcompositor, &surface, qh);
+        }
+        if let Some(idle_inhibitor) = &app.idle_inhibitor {
+            idle_inhibitor.set_surface(surface.clone());
+        }
+        app.frame_requested.store(true, Ordering::Relaxed);
+        app.frame_pending = false;
+        self.set_repaint_callback(
+            &app.egui_context,
+            &surface,
+            app.frame_requested.clone(),
+            app.drawing.clone(),
+        );
+
+        app.surface_configured = false;
+        app.msaa_texture = None;
+        app.previous_shapes.clear();
+        app.input_rects = None;
+        app.first_configure = true;
+        app.scale = 1.;
+        app.outputs.clear();
+        app.outputs_changed(&self.output_state);
+        app.powered_off = false;
+
+        self.apps.insert(surface.id(), app);
+    }
+
+    fn scale_factor_changed(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: f32,
+    ) {
+        if let Some(app) = self.apps.get_mut(&surface.id()) {
+            if app.scale == new_factor {
+                // No change
+                return;
+            }
+
+            debug!("Scale factor changed to {new_factor}");
+
+            match &app.viewport {
+                Some(viewport) => viewport.set_destination(app.width as i32, app.height as i32),
+                None => surface.set_buffer_scale(new_factor as i32),
+            }
+
+            app.scale = new_factor;
+            app.app.on_scale_changed(new_factor);
+            if let Err(e) = app.draw(&self.compositor, qh) {
+                self.surface_error = Some(e);
+            }
+        }
+    }
+
+    /// The precise timestamp of the input event that's being handled, if the compositor sends
+    /// those.
+    fn input_time(&mut self) -> Option<Duration> {
+        self.input_timestamps
+            .as_mut()
+            .and_then(|input_timestamps| input_timestamps.take())
+    }
+
+    fn key_event(&mut self, event: KeyEvent, pressed: bool) {
+        let input_time = self.input_time();
+        let clipboard = self.clipboard.handle();
+
+        if let Some(app) = self.apps.values_mut().find(|app| app.keyboard_focus) {
+            app.input_received(input_time);
+            let viewport = app.keyboard_viewport;
+            if let Some(c) = event.utf8 {
+                if !c.is_empty() && c.chars().all(|c| !c.is_control()) {
+                    app.viewport_events(viewport).push(egui::Event::Text(c));
+                }
+            }
+
+            let Some(key) = keysyms::wl_to_egui(event.keysym) else {
+                // Not the name or character, which would put what's typed, eg. passwords, in the log
+                trace!("Unknown keysym: {:#x}", event.keysym.raw());
+                return;
+            };
+
+            if pressed && app.modifiers.command {
+                match key {
+                    Key::C => {
+                        app.viewport_events(viewport).push(egui::Event::Copy);
+                        app.egui_context.request_repaint();
+                        return;
+                    }
+                    Key::X => {
+                        app.viewport_events(viewport).push(egui::Event::Cut);
+                        app.egui_context.request_repaint();
+                        return;
+                    }
+                    Key::V => {
+                        let pending_events = app.pending_events.clone();
+                        let egui_context = app.egui_context.clone();
+                        clipboard.receive_text(move |text| {
+                            if let Some(text) = text {
+                                pending_events
+                                    .lock()
+                                    .unwrap()
+                                    .push(egui::Event::Paste(text));
+                                egui_context.request_repaint();
+                            }
+                        });
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+
+            let modifiers = app.modifiers;
+            app.viewport_events(viewport).push(egui::Event::Key {
+                key,
+                physical_key: None,
+                pressed,
+                repeat: false,
+                modifiers,
+            });
+            app.egui_context.request_repaint();
+        } else {
+            debug!("No app with keyboard focus");
+        }
+    }
+}
+
+/// Whether/how to use input regions, can be used to let mouse and touch inputs fall through the
+/// layer surface.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum InputRegions {
+    /// The entire surface takes mouse inputs.
+    #[default]
+    Full,
+    /// The background layer doesn't take inputs, only windows and popups do. This means that eg.
+    /// CentralPanels and SidePanels will not take inputs, because they're typically drawn on the
+    /// background layer.
+    WindowsOnly,
+    /// The layer surface doesn't take any mouse or touch inputs at all.
+    None,
+    /// Only the rectangles that [`App::input_region`] returns take inputs, eg. the visible part
+    /// of a pill-shaped bar.
+    Custom,
+}
+
+/// Which parts of the surface to tell the compositor are opaque, so it can skip drawing whatever
+/// is beneath them.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum OpaqueRegions {
+    /// Nothing is marked as opaque.
+    #[default]
+    None,
+    /// The entire surface is opaque, eg. a bar with a solid background.
+    Full,
+    /// Rectangles that are filled with an opaque color, like the backgrounds of panels and
+    /// windows, are marked as opaque every frame.
+    Detect,
+}
+
+/// Which kind of texture format to render into.
+///
+/// If the surface doesn't support the requested kind, the format egui prefers is used instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SurfaceFormat {
+    /// 8 bits per channel, with egui blending in gamma space. This matches how egui is designed to
+    /// look.
+    #[default]
+    Unorm8,
+    /// 8 bits per channel sRGB, so that blending and texture sampling happen in linear space and
+    /// the compositor gets correctly encoded colors.
+    Srgb8,
+    /// 10 bits per color channel, to avoid banding in gradients. Note that this leaves only 2 bits
+    /// for alpha, so it's mostly useful for opaque surfaces.
+    Rgb10a2,
+    /// 16 bit floating point per channel, for HDR content. See [`ImageDescription`].
+    Rgba16Float,
+}
+
+impl SurfaceFormat {
+    fn candidates(self) -> &'static [TextureFormat] {
+        match self {
+            SurfaceFormat::Unorm8 => &[TextureFormat::Bgra8Unorm, TextureFormat::Rgba8Unorm],
+            SurfaceFormat::Srgb8 => &[TextureFormat::Bgra8UnormSrgb, TextureFormat::Rgba8UnormSrgb],
+            SurfaceFormat::Rgb10a2 => &[TextureFormat::Rgb10a2Unorm],
+            SurfaceFormat::Rgba16Float => &[TextureFormat::Rgba16Float],
+        }
+    }
+
+    fn select(self, supported: &[TextureFormat]) -> Option<TextureFormat> {
+        self.candidates()
+            .iter()
+            .copied()
+            .find(|format| supported.contains(format))
+    }
+}
+
+/// Called when egui wants to open a URL, eg. when a hyperlink is clicked.
+pub type UrlOpener = Box<dyn Fn(&OpenUrl)>;
+
+/// Sets up an app's egui context before its first frame, see [`LayerAppOpts::setup`].
+pub type ContextSetup = Box<dyn FnOnce(&egui::Context)>;
+
+/// Receives a frame that was rendered into a texture, see [`LayerAppHandle::render_to_texture`].
+pub type TextureCallback = Box<dyn FnOnce(wgpu::Texture) + Send>;
+
+/// Passed to an app before its next frame, see [`LayerAppHandle::send`].
+type AppMessage = Box<dyn FnOnce(&mut dyn App) + Send>;
+
+/// Creates the app that's shown on an output while the session is locked, see
+/// [`Context::lock_session`].
+type LockAppFactory = Box<dyn FnMut(&OutputInfo, SessionLockHandle) -> Box<dyn App>>;
+
+/// The name of the running binary, for apps that don't pick an id of their own.
+fn default_app_id() -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|path| Some(path.file_stem()?.to_str()?.to_owned()))
+        .unwrap_or_else(|| "egui".to_owned())
+}
+
+/// Opens the URL with `xdg-open`. This is the default [`UrlOpener`].
+pub fn xdg_open(open_url: &OpenUrl) {
+    match Command::new("xdg-open").arg(&open_url.url).spawn() {
+        Ok(mut child) => {
+            // reap the child so it doesn't linger as a zombie
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => warn!("Failed to run xdg-open: {e}"),
+    }
+}
+
+pub struct LayerAppOpts<'a> {
+    pub layer: Layer,
+    /// The layer namespace, [`App::app_id`] if not set.
+    pub namespace: Option<&'a str>,
+    /// Pick the output to show the surface on, or let the compositor pick one if not set. The
+    /// info includes the logical position, size, name and description from xdg-output, for
+    /// compositors that only send those there.
+    ///
+    /// It's checked again when outputs are added or change, so the surface moves to a matching
+    /// output that shows up later, and comes back after its output was unplugged, see
+    /// [`App::on_output_lost`].
+    pub output: Option<OutputSelector>,
+    pub input_regions: InputRegions,
+    pub opaque_regions: OpaqueRegions,
+    pub surface_format: SurfaceFormat,
+    /// Resolution to render at relative to the surface's size, the compositor stretches the result
+    /// to fill the surface. Eg. `0.5` renders at half resolution, which is plenty for blurry
+    /// backdrops. Ignored if the compositor doesn't support viewports.
+    pub render_scale: f32,
+    /// Number of samples for multisample anti-aliasing, 1 disables it.
+    pub msaa_samples: u32,
+    /// Whether to dither colors, which avoids banding in gradients.
+    pub dithering: bool,
+    /// How far egui's menus, combo boxes and tooltips may extend past each side of the surface, in
+    /// points. Those are shown on popup surfaces, eg. below a bar that's too thin to fit them.
+    /// Panels still only cover the surface itself.
+    pub popup_space: Marginf,
+    /// Show egui's deferred viewports on layer surfaces of their own, so UIs that open extra
+    /// windows with [`egui::Context::show_viewport_deferred`] work like they do in eframe. The
+    /// viewports are placed at their builder's position on the output, or centered. Immediate
+    /// viewports are always shown inside the app's surface, like with this off.
+    pub viewports: bool,
+    /// Tell the compositor which color space the surface's contents are in, if it supports the
+    /// color management protocol.
+    pub image_description: Option<ImageDescription>,
+    /// How to open URLs, uses [`xdg_open`] if not set.
+    pub url_opener: Option<UrlOpener>,
+    /// Show the app in an ordinary window if the compositor doesn't support layer shell, like
+    /// GNOME, eg. for development. [`App::on_init`] isn't called then, as there's no layer surface
+    /// to set up, and the namespace is used as the window's title.
+    pub window_fallback: bool,
+    /// The size in logical pixels when the compositor leaves it up to the app, ie. on axes that
+    /// have no size set in [`App::on_init`] and aren't anchored on both sides. If not set, it's
+    /// the size of the app's output.
+    pub default_size: Option<(u32, u32)>,
+    /// Save egui's memory, eg. the positions of windows, and the app's [`Storage`] when the app
+    /// exits and every 30 seconds, and restore them the next time it's created. They're kept per
+    /// app id and output, in `$XDG_DATA_HOME/<app id>/`.
+    #[cfg(feature = "persistence")]
+    pub persist_memory: bool,
+    /// Use the desktop's accent color and contrast preference in egui's default visuals, with
+    /// the `portal` feature. This replaces the visuals whenever they change, so it's best left
+    /// off if the app sets its own.
+    pub desktop_style: bool,
+    /// Called with the app's egui context before the app is created and its first frame, eg. to
+    /// install fonts or set the style, so the first frame already has them.
+    pub setup: Option<ContextSetup>,
+}
+
+impl Default for LayerAppOpts<'_> {
+    fn default() -> Self {
+        Self {
+            layer: Layer::Top,
+            namespace: Default::default(),
+            output: Default::default(),
+            input_regions: InputRegions::Full,
+            opaque_regions: OpaqueRegions::None,
+            surface_format: SurfaceFormat::default(),
+            render_scale: 1.,
+            msaa_samples: 1,
+            dithering: true,
+            popup_space: Marginf::ZERO,
+            viewports: false,
+            image_description: None,
+            url_opener: Default::default(),
+            window_fallback: false,
+            default_size: None,
+            #[cfg(feature = "persistence")]
+            persist_memory: false,
+            desktop_style: false,
+            setup: None,
+        }
+    }
+}
+
+pub struct SolidLayerOpts<'a> {
+    pub layer: Layer,
+    pub namespace: Option<&'a str>,
+    /// Pick the output to show the surface on, see [`LayerAppOpts::output`]. It's only checked
+    /// when the layer is created.
+    pub output: Option<OutputSelector>,
+    /// Premultiplied color to fill the surface with.
+    pub color: Color32,
+    /// Whether the layer catches pointer and touch input, eg. to block clicks on the windows
+    /// beneath it.
+    pub catch_input: bool,
+}
+
+impl Default for SolidLayerOpts<'_> {
+    fn default() -> Self {
+        Self {
+            layer: Layer::Top,
+            namespace: Default::default(),
+            output: Default::default(),
+            color: Color32::from_black_alpha(128),
+            catch_input: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SessionLockOpts {
+    pub opaque_regions: OpaqueRegions,
+    pub surface_format: SurfaceFormat,
+    /// See [`LayerAppOpts::render_scale`].
+    pub render_scale: f32,
+    /// Number of samples for multisample anti-aliasing, 1 disables it.
+    pub msaa_samples: u32,
+    /// Whether to dither colors, which avoids banding in gradients.
+    pub dithering: bool,
+    pub image_description: Option<ImageDescription>,
+}
+
+impl Default for SessionLockOpts {
+    fn default() -> Self {
+        Self {
+            opaque_regions: OpaqueRegions::None,
+            surface_format: SurfaceFormat::default(),
+            render_scale: 1.,
+            msaa_samples: 1,
+            dithering: true,
+            image_description: None,
+        }
+    }
+}
+
+/// A handle to a locked session, which can be cloned and shared with the lock apps.
+#[derive(Clone)]
+pub struct SessionLockHandle {
+    session_lock: SessionLock,
+    unlocked: Arc<AtomicBool>,
+    wayland_conn: Connection,
+}
+
+impl SessionLockHandle {
+    /// Whether the compositor has locked the session, which it does once every output is covered.
+    pub fn is_locked(&self) -> bool {
+        self.session_lock.is_locked()
+    }
+
+    /// Unlock the session and close the lock surfaces.
+    pub fn unlock(&self) {
+        self.unlocked.store(true, Ordering::Relaxed);
+        self.session_lock.unlock();
+        let _ = self.wayland_conn.flush();
+    }
+}
+
+/// The session lock that's held, along with what's needed to cover outputs that are added.
+struct ActiveSessionLock {
+    handle: SessionLockHandle,
+    new_app: LockAppFactory,
+    opts: SessionLockOpts,
+    /// The lock surface of each output.
+    surfaces: Vec<(wl_output::WlOutput, ObjectId)>,
+}
+
+type OutputAppFactory =
+    Box<dyn FnMut(&OutputInfo) -> Option<(Box<dyn App>, LayerAppOpts<'static>)>>;
+
+/// A handle to the layer apps that [`Context::new_layer_app_per_output`] creates, which can be
+/// cloned and sent to other threads.
+#[derive(Clone)]
+pub struct MultiOutputApp {
+    apps: Arc<Mutex<Vec<(wl_output::WlOutput, LayerAppHandle)>>>,
+    exit: Arc<AtomicBool>,
+}
+
+impl MultiOutputApp {
+    /// Call `f` with the handle of the app on each output.
+    pub fn for_each(&self, mut f: impl FnMut(&wl_output::WlOutput, &LayerAppHandle)) {
+        for (output, handle) in self.apps.lock().unwrap().iter() {
+            f(output, handle);
+        }
+    }
+
+    /// Exit the apps on all outputs, and stop creating them for new outputs.
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+        for (_, handle) in self.apps.lock().unwrap().drain(..) {
+            handle.exit();
+        }
+    }
+}
+
+/// Layer apps that are created for every output, along with what's needed for new outputs.
+struct ActiveOutputApp {
+    handle: MultiOutputApp,
+    new_app: OutputAppFactory,
+}
+
+impl Context {
+    /// Connect to the compositor. Fails if there's no compositor, or it doesn't support the
+    /// protocols that are needed for anything at all.
+    pub fn new() -> Result<Self, Error> {
+        Self::with_wgpu_configuration(WgpuConfiguration::default())
+    }
+
+    /// Create a context that sets up wgpu with the given configuration, eg. to pick a backend or
+    /// prefer a low-power adapter.
+    pub fn with_wgpu_configuration(wgpu_configuration: WgpuConfiguration) -> Result<Self, Error> {
+        let wgpu_instance = pollster::block_on(wgpu_configuration.wgpu_setup.new_instance());
+        let wayland_conn = Connection::connect_to_env()?;
+        Self::connect(wayland_conn, wgpu_configuration, wgpu_instance, SystemTheme::new())
+    }
+
+    /// Create a context on a connection that's already open, eg. the one of winit or another
+    /// SCTK based component, so a normal window and layer surfaces can be shown by one process
+    /// without connecting to the compositor twice, or one to a mock compositor in tests. Toolkits
+    /// that only give out the `wl_display`, eg. through raw-window-handle, can be wrapped with
+    /// [`Backend::from_foreign_display`].
+    ///
+    /// The wgpu instance can be shared with the rest of the application too, so its adapters and
+    /// surfaces can be used with the context's, otherwise a new one is created from
+    /// `wgpu_configuration`.
+    ///
+    /// The context binds the globals again and gets an event queue of its own, so neither side
+    /// sees the other's objects or events. Its events are read from the socket by whoever reads
+    /// it first, so when the other toolkit runs the event loop, [`Context::poll_dispatch`] has to
+    /// be called from there, eg. in winit's `about_to_wait`, and when the socket becomes readable.
+    /// [`Context::reconnect`] opens a connection of its own.
+    ///
+    /// [`Backend::from_foreign_display`]: wayland_backend::client::Backend::from_foreign_display
+    pub fn with_connection(
+        wayland_conn: Connection,
+        wgpu_instance: Option<wgpu::Instance>,
+        wgpu_configuration: WgpuConfiguration,
+    ) -> Result<Self, Error> {
+        let wgpu_instance = wgpu_instance
+            .unwrap_or_else(|| pollster::block_on(wgpu_configuration.wgpu_setup.new_instance()));
+        Self::connect(wayland_conn, wgpu_configuration, wgpu_instance, SystemTheme::new())
+    }
+
+    fn connect(
+        wayland_conn: Connection,
+        wgpu_configuration: WgpuConfiguration,
+        wgpu_instance: wgpu::Instance,
+        system_theme: SystemTheme,
+    ) -> Result<Self, Error> {
+        // Enumerate the list of globals to get the protocols the server implements.
+        let (globals, event_queue) = registry_queue_init(&wayland_conn)?;
+
+        let qh = event_queue.handle();
+
+        let compositor = CompositorState::bind(&globals, &qh)
+            .map_err(|_| Error::MissingProtocol("wl_compositor"))?;
+
+        let layer_shell = LayerShell::bind(&globals, &qh).ok();
+        let subcompositor = globals.bind(&qh, 1..=1, GlobalData).ok();
+        let xdg_shell = XdgShellState::bind(&globals, &qh).ok();
+        let session_lock_state = SessionLockState::new(&globals, &qh);
+
+        let fractional_scaling = FractionalScalingManager::bind(&globals, &qh).ok();
+        let viewporter = ViewporterState::bind(&globals, &qh).ok();
+        let data_device_manager = DataDeviceManagerState::bind(&globals, &qh).ok();
+        let primary_selection_manager = PrimarySelectionManagerState::bind(&globals, &qh).ok();
+        let waker = LoopWaker::new(wayland_conn.clone(), qh.clone());
+        let clipboard = ClipboardState::new(
+            data_device_manager,
+            primary_selection_manager,
+            wayland_conn.clone(),
+            qh.clone(),
+        );
+        let color_manager = ColorManagerState::bind(&globals, &qh).ok();
+        let single_pixel_buffer = SinglePixelBufferState::bind(&globals, &qh).ok();
+        let alpha_modifier = AlphaModifierState::bind(&globals, &qh).ok();
+        let content_type = ContentTypeState::bind(&globals, &qh).ok();
+        let presentation_time = PresentationTimeState::bind(&globals, &qh).ok();
+        let idle_inhibit = IdleInhibitState::bind(&globals, &qh).ok();
+        let idle_notify = IdleNotifyState::bind(&globals, &qh).ok();
+        let seat_state = SeatState::new(&globals, &qh);
+        let toplevels = ToplevelsState::bind(&globals, &qh, wayland_conn.clone()).ok();
+        if let Some(toplevels) = &toplevels {
+            toplevels.set_seat(seat_state.seats().next());
+        }
+        let output_management =
+            OutputManagementState::bind(&globals, &qh, wayland_conn.clone()).ok();
+        let output_power = OutputPowerState::bind(&globals, &qh, wayland_conn.clone()).ok();
+        let shm = Shm::bind(&globals, &qh).map_err(|_| Error::MissingProtocol("wl_shm"))?;
+        let screencopy = ScreencopyState::bind(&globals, &qh, wayland_conn.clone()).ok();
+        let virtual_pointer = VirtualPointerState::bind(&globals, &qh).ok();
+        let input_timestamps = InputTimestampsState::bind(&globals, &qh).ok();
+
+        let mut context = Context {
+            event_queue,
+            delegate: ContextDelegate {
+                wayland_conn,
+                compositor,
+                layer_shell,
+                subcompositor,
+                xdg_shell,
+                session_lock_state,
+                session_lock: None,
+                output_apps: Vec::new(),
+                fractional_scaling,
+                viewporter,
+                single_pixel_buffer,
+                alpha_modifier,
+                content_type,
+                presentation_time,
+                idle_inhibit,
+                idle_notify,
+                idle_timeout: None,
+                idle_notification: None,
+                color_manager,
+                clipboard,
+                toplevels,
+                output_management,
+                output_power,
+                shm,
+                screencopy,
+                virtual_pointer,
+                input_timestamps,
+                registry_state: RegistryState::new(&globals),
+                seat_state,
+                output_state: OutputState::new(&globals, &qh),
+                wgpu_instance,
+                wgpu_configuration,
+                render_state: None,
+                keyboard: None,
+                pointer: None,
+                touch: None,
+                touches: HashMap::new(),
+                last_serial: 0,
+                surface_error: None,
+                apps: HashMap::new(),
+                lost_apps: Vec::new(),
+                solid_layers: HashMap::new(),
+                system_theme,
+                repaint_timer: RepaintTimer::new(),
+                waker,
+            },
+            globals,
+            spawner: Spawner::default(),
+        };
+
+        // The outputs were only just bound, wait for their info so that output selectors of apps
+        // that are created right away can see all of them
+        context.event_queue.roundtrip(&mut context.delegate)?;
+
+        Ok(context)
+    }
+
+    /// Connect to the compositor again after [`Error::Disconnected`], eg. once it restarted, and
+    /// show the layer apps on new surfaces. They're set up by [`App::on_init`] like the first
+    /// time, and keep their egui state, handles and the GPU device. Apps created with
+    /// [`Context::new_layer_app_per_output`] are created again for the new outputs.
+    ///
+    /// Everything else that belonged to the old connection is closed: lock apps, child surfaces,
+    /// windows, solid layers and subsurfaces, and the handles from [`Context::toplevels`],
+    /// [`Context::output_management`], [`Context::output_power`] and [`Context::screencopy`]
+    /// stop working. Fails if the compositor isn't back yet, then the context stays as it was and
+    /// this can be tried again.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        let mut context = Self::connect(
+            Connection::connect_to_env()?,
+            self.delegate.wgpu_configuration.clone(),
+            self.delegate.wgpu_instance.clone(),
+            self.delegate.system_theme.clone(),
+        )?;
+        context.delegate.render_state = self.delegate.render_state.clone();
+        context.spawner = self.spawner.clone();
+        context.set_idle_timeout(self.delegate.idle_timeout);
+        let qh = context.event_queue.handle();
+
+        // Apps per output are created again by their factory, for the outputs there are now
+        let output_apps = take(&mut self.delegate.output_apps);
+        let mut output_app_closed = Vec::new();
+        for output_app in &output_apps {
+            for (_, handle) in output_app.handle.apps.lock().unwrap().drain(..) {
+                output_app_closed.push(handle.closed);
+            }
+        }
+
+        self.delegate.session_lock = None;
+        let apps = self.delegate.apps.drain().map(|(_, app)| (app, false));
+        let lost_apps = self.delegate.lost_apps.drain(..).map(|app| (app, true));
+        for (mut app, lost) in apps.chain(lost_apps).collect::<Vec<_>>() {
+            let per_output = output_app_closed
+                .iter()
+                .any(|closed| Arc::ptr_eq(closed, &app.closed));
+            match app.shell_surface {
+                ShellSurface::Layer(_) if !per_output && !app.exit.load(Ordering::Relaxed) => {
+                    // The subsurfaces' surfaces belong to the old connection
+                    app.subsurfaces.clear();
+                    context.delegate.reconnect_app(&qh, app, lost);
+                }
+                _ => app.app.on_exit(),
+            }
+        }
+
+        context.delegate.output_apps = output_apps;
+        let outputs: Vec<_> = context.delegate.output_state.outputs().collect();
+        for index in 0..context.delegate.output_apps.len() {
+            for output in &outputs {
+                context.delegate.add_output_app(&qh, index, output.clone());
+            }
+        }
+
+        *self = context;
+        let _ = self.delegate.wayland_conn.flush();
+
+        Ok(())
+    }
+
+    /// Create a context that renders with existing wgpu handles, so that the device, textures and
+    /// pipelines can be shared with the rest of the application.
+    pub fn with_wgpu(
+        instance: wgpu::Instance,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+    ) -> Result<Self, Error> {
+        Self::with_wgpu_configuration(WgpuConfiguration {
+            wgpu_setup: WgpuSetup::Existing(WgpuSetupExisting { instance, adapter, device, queue }),
+            ..Default::default()
+        })
+    }
+
+    /// Create a context whose device can import dmabufs, see [`LayerAppHandle::import_dmabuf`].
+    #[cfg(feature = "dmabuf")]
+    pub fn with_dmabuf_import() -> Result<Self, Error> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+            .ok_or(DmabufError::NoAdapter)?;
+        let wayland_conn = Connection::connect_to_env()?;
+        let (device, queue) = request_device(&adapter, &wayland_conn)?;
+
+        let wgpu_configuration = WgpuConfiguration {
+            wgpu_setup: WgpuSetup::Existing(WgpuSetupExisting {
+                instance: instance.clone(),
+                adapter,
+                device,
+                queue,
+            }),
+            ..Default::default()
+        };
+        Self::connect(wayland_conn, wgpu_configuration, instance, SystemTheme::new())
+    }
+
+    /// Show an app on a new layer surface. Fails if the compositor doesn't support layer shell
+    /// and there's no window fallback, or rendering can't be set up.
+    pub fn new_layer_app(
+        &mut self,
+        app: Box<dyn App>,
+        opts: LayerAppOpts,
+    ) -> Result<LayerAppHandle, Error> {
+        pollster::block_on(self.new_layer_app_async(app, opts))
+    }
+
+    /// Like [`Context::new_layer_app`], but awaits the GPU adapter and device instead of blocking
+    /// on them, eg. on an async executor. They're only requested for the first app. The context is
+    /// borrowed until then, so apps are still created one after the other.
+    pub async fn new_layer_app_async(
+        &mut self,
+        app: Box<dyn App>,
+        opts: LayerAppOpts<'_>,
+    ) -> Result<LayerAppHandle, Error> {
+        let app_id = app.app_id();
+        self.new_layer_app_as(app_id, |_| app, opts).await
+    }
+
+    /// Like [`Context::new_layer_app`], but creates the app once its egui context and render
+    /// state exist, eg. to install fonts or load textures before the first frame.
+    pub fn new_layer_app_with(
+        &mut self,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App>,
+        opts: LayerAppOpts,
+    ) -> Result<LayerAppHandle, Error> {
+        pollster::block_on(self.new_layer_app_with_async(new_app, opts))
+    }
+
+    /// Like [`Context::new_layer_app_with`], but awaits the GPU adapter and device instead of
+    /// blocking on them, see [`Context::new_layer_app_async`].
+    pub async fn new_layer_app_with_async(
+        &mut self,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App>,
+        opts: LayerAppOpts<'_>,
+    ) -> Result<LayerAppHandle, Error> {
+        self.new_layer_app_as(default_app_id(), new_app, opts).await
+    }
+
+    /// Show an app that's created with `app_id`, see [`CreationContext::app_id`].
+    async fn new_layer_app_as(
+        &mut self,
+        app_id: String,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App>,
+        mut opts: LayerAppOpts<'_>,
+    ) -> Result<LayerAppHandle, Error> {
+        let qh = self.event_queue.handle();
+
+        let selector = opts.output.take();
+        let output = selector
+            .as_ref()
+            .and_then(|selector| self.delegate.select_output(selector));
+
+        let handle = self
+            .delegate
+            .new_layer_app(&qh, app_id, new_app, opts, output.as_ref())
+            .await?;
+
+        // Layer surfaces are closed along with their output, and the output that's wanted may
+        // not be there yet, so keep the selector around for outputs that are added later
+        if let Some(selector) = selector {
+            let id = handle.surface.lock().unwrap().surface.id();
+            if let Some(app) = self.delegate.apps.get_mut(&id) {
+                if let ShellSurface::Layer(_) = app.shell_surface {
+                    app.target = Some(OutputTarget { output, selector });
+                }
+            }
+        }
+
+        Ok(handle)
+    }
+
+    /// Close an app right away, instead of with its next frame like [`LayerAppHandle::exit`].
+    /// Its `on_exit` is called, and its surface and GPU resources are gone once this returns.
+    /// Returns `false` if the app was already gone.
+    pub fn remove_layer_app(&mut self, handle: &LayerAppHandle) -> bool {
+        let id = handle.surface.lock().unwrap().surface.id();
+        let mut app = match self.delegate.remove_app(&id) {
+            Some(app) => app,
+            // It may be waiting for its output to come back
+            None => {
+                let Some(index) = self
+                    .delegate
+                    .lost_apps
+                    .iter()
+                    .position(|app| Arc::ptr_eq(&app.closed, &handle.closed))
+                else {
+                    return false;
+                };
+                self.delegate.lost_apps.remove(index)
+            }
+        };
+
+        app.app.on_exit();
+        drop(app);
+        let _ = self.delegate.wayland_conn.flush();
+        true
+    }
+
+    /// Create a layer app on every output that `new_app` returns one for, and keep doing so for
+    /// outputs that are added later, eg. for bars and wallpapers. The app on an output exits when
+    /// the output is removed. The `output` selector of the returned options is ignored.
+    ///
+    /// To mirror a single app on all of them, return a clone of an `Arc<Mutex<impl App>>` each
+    /// time.
+    pub fn new_layer_app_per_output(
+        &mut self,
+        new_app: impl FnMut(&OutputInfo) -> Option<(Box<dyn App>, LayerAppOpts<'static>)> + 'static,
+    ) -> MultiOutputApp {
+        let qh = self.event_queue.handle();
+        let handle = MultiOutputApp {
+            apps: Arc::default(),
+            exit: Arc::new(AtomicBool::new(false)),
+        };
+
+        self.delegate.output_apps.push(ActiveOutputApp {
+            handle: handle.clone(),
+            new_app: Box::new(new_app),
+        });
+
+        let index = self.delegate.output_apps.len() - 1;
+        for output in self.delegate.output_state.outputs().collect::<Vec<_>>() {
+            self.delegate.add_output_app(&qh, index, output);
+        }
+
+        handle
+    }
+
+    /// Lock the session, eg. for a lockscreen. `new_app` is called to create an app for every
+    /// output, including ones that are added while the session is locked. The session stays
+    /// locked until it's unlocked through the handle, which the apps get as well.
+    /// [`App::on_init`] isn't called for lock apps, since they have no layer surface.
+    ///
+    /// Returns `None` if the compositor doesn't support locking, or the session is already locked
+    /// by this context.
+    pub fn lock_session(
+        &mut self,
+        new_app: impl FnMut(&OutputInfo, SessionLockHandle) -> Box<dyn App> + 'static,
+        opts: SessionLockOpts,
+    ) -> Option<SessionLockHandle> {
+        if self.delegate.session_lock.is_some() {
+            return None;
+        }
+
+        let qh = self.event_queue.handle();
+        let session_lock = self.delegate.session_lock_state.lock(&qh).ok()?;
+        let handle = SessionLockHandle {
+            session_lock,
+            unlocked: Arc::new(AtomicBool::new(false)),
+            wayland_conn: self.delegate.wayland_conn.clone(),
+        };
+
+        self.delegate.session_lock = Some(ActiveSessionLock {
+            handle: handle.clone(),
+            new_app: Box::new(new_app),
+            opts,
+            surfaces: Vec::new(),
+        });
+
+        for output in self.delegate.output_state.outputs().collect::<Vec<_>>() {
+            self.delegate.add_lock_surface(&qh, output);
+        }
+
+        Some(handle)
+    }
+
+    /// Tell apps through [`App::on_idle`] when there's been no input for `timeout`, and when
+    /// there's input again. `None` stops the notifications.
+    ///
+    /// Returns `false` if the compositor doesn't support this.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> bool {
+        if self.delegate.idle_notify.is_none() {
+            return false;
+        }
+
+        let qh = self.event_queue.handle();
+        self.delegate.idle_timeout = timeout;
+        if let Some((_, notification)) = self.delegate.idle_notification.take() {
+            notification.destroy();
+        }
+        if let Some(seat) = self.delegate.seat_state.seats().next() {
+            self.delegate.watch_idle(&qh, seat);
+        }
+
+        true
+    }
+
+    /// Create a layer surface that covers the whole output with a single color, eg. to dim the
+    /// screen. Egui content can be shown on top of it with an app on the same layer that's created
+    /// afterwards. Returns `None` if the compositor doesn't support layer shell, single pixel
+    /// buffers or viewports.
+    pub fn new_solid_layer(&mut self, opts: SolidLayerOpts<'_>) -> Option<Arc<SolidLayer>> {
+        let SolidLayerOpts {
+            layer,
+            namespace,
+            output,
+            color,
+            catch_input,
+        } = opts;
+
+        let layer_shell = self.delegate.layer_shell.as_ref()?;
+        let single_pixel_buffer = self.delegate.single_pixel_buffer.clone()?;
+        let viewporter = self.delegate.viewporter.clone()?;
+        let qh = self.event_queue.handle();
+
+        let wl_surface = self.delegate.compositor.create_surface(&qh);
+        let output = output
+            .as_ref()
+            .and_then(|selector| self.delegate.select_output(selector));
+
+        let layer =
+            layer_shell.create_layer_surface(&qh, wl_surface, layer, namespace, output.as_ref());
+        layer.set_anchor(Anchor::all());
+        layer.set_exclusive_zone(-1);
+
+        if !catch_input {
+            if let Ok(region) = Region::new(&self.delegate.compositor) {
+                layer.set_input_region(Some(region.wl_region()));
+            }
+        }
+
+        if color.is_opaque() {
+            if let Ok(region) = Region::new(&self.delegate.compositor) {
+                region.add(0, 0, i32::MAX, i32::MAX);
+                layer
+                    .wl_surface()
+                    .set_opaque_region(Some(region.wl_region()));
+            }
+        }
+
+        // The buffer is attached once the compositor sends the size
+        layer.commit();
+
+        let viewport = viewporter.get_viewport(layer.wl_surface(), &qh);
+
+        let solid_layer = SolidLayer::new(
+            layer,
+            viewport,
+            single_pixel_buffer,
+            color,
+            qh,
+            self.delegate.wayland_conn.clone(),
+        );
+        self.delegate
+            .solid_layers
+            .insert(solid_layer.layer().wl_surface().id(), Arc::downgrade(&solid_layer));
+
+        Some(solid_layer)
+    }
+
+    /// Create a subsurface of the app's surface, for embedding content that's rendered by
+    /// something else. Returns `None` if the compositor doesn't support subsurfaces.
+    pub fn new_subsurface(&self, app: &LayerAppHandle) -> Option<Subsurface> {
+        let subcompositor = self.delegate.subcompositor.as_ref()?;
+        let qh = self.event_queue.handle();
+        let parent = app.surface.lock().unwrap().surface.clone();
+        let surface = self.delegate.compositor.create_surface(&qh);
+
+        Some(app.subsurfaces.create(
+            subcompositor,
+            surface,
+            parent,
+            &qh,
+            app.egui_context.clone(),
+            self.delegate.wayland_conn.clone(),
+        ))
+    }
+
+    /// Call `f` with the app of `handle` if it's still running and a `T`, or an `Arc<Mutex<T>>`
+    /// that's shared between surfaces, eg. to change its state between dispatches without going
+    /// through a channel. A repaint is requested afterwards.
+    pub fn with_app_mut<T: App, R>(
+        &mut self,
+        handle: &LayerAppHandle,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Option<R> {
+        let id = handle.surface.lock().unwrap().surface.id();
+        let app = match self.delegate.apps.get_mut(&id) {
+            Some(app) => app,
+            // Waiting for its output to come back
+            None => self
+                .delegate
+                .lost_apps
+                .iter_mut()
+                .find(|app| app.app_surface.lock().unwrap().surface.id() == id)?,
+        };
+
+        let result = with_app_as(&mut *app.app, f)?;
+        app.egui_context.request_repaint();
+        Some(result)
+    }
+
+    /// Get a handle to the clipboard.
+    pub fn clipboard(&self) -> Clipboard {
+        self.delegate.clipboard.handle().clone()
+    }
+
+    /// Get a handle to the list of other clients' windows, eg. for a taskbar. Returns `None` if
+    /// the compositor doesn't support this.
+    pub fn toplevels(&self) -> Option<Toplevels> {
+        self.delegate
+            .toplevels
+            .as_ref()
+            .map(|toplevels| toplevels.handle().clone())
+    }
+
+    /// Get a handle to the output configuration, eg. for display settings. Returns `None` if the
+    /// compositor doesn't support this.
+    pub fn output_management(&self) -> Option<OutputManagement> {
+        self.delegate
+            .output_management
+            .as_ref()
+            .map(|output_management| output_management.handle().clone())
+    }
+
+    /// Get a handle to turn outputs on and off. Returns `None` if the compositor doesn't support
+    /// this.
+    pub fn output_power(&self) -> Option<OutputPower> {
+        self.delegate
+            .output_power
+            .as_ref()
+            .map(|output_power| output_power.handle().clone())
+    }
+
+    /// Get a handle to capture outputs into textures. Returns `None` if the compositor doesn't
+    /// support this.
+    pub fn screencopy(&self) -> Option<Screencopy> {
+        self.delegate
+            .screencopy
+            .as_ref()
+            .map(|screencopy| screencopy.handle().clone())
+    }
+
+    /// Create a pointer to inject motion, clicks and scrolling into the seat. Returns `None` if
+    /// the compositor doesn't support this.
+    pub fn new_virtual_pointer(&self) -> Option<VirtualPointer> {
+        let virtual_pointer = self.delegate.virtual_pointer.as_ref()?;
+        let seat = self.delegate.seat_state.seats().next();
+        Some(virtual_pointer.create_pointer(
+            seat.as_ref(),
+            &self.event_queue.handle(),
+            self.delegate.wayland_conn.clone(),
+        ))
+    }
+
+    /// The queue that the context's events are dispatched on, to create objects of protocols this
+    /// crate doesn't support with a [`ProtocolHandler`], eg. [`RawEvents`].
+    ///
+    /// This is advanced API: the crate doesn't know about the objects created on it, so they
+    /// have to be destroyed by whoever created them.
+    pub fn queue_handle(&self) -> QueueHandle<ContextDelegate> {
+        self.event_queue.handle()
+    }
+
+    /// The globals the compositor announced when the context was created, eg. to check whether it
+    /// supports a protocol before binding it with [`Context::bind_global`].
+    ///
+    /// This is advanced API: binding a global that the crate uses itself gives another object,
+    /// whose requests can conflict with the ones the crate makes.
+    pub fn globals(&self) -> &GlobalList {
+        &self.globals
+    }
+
+    /// Bind a global of a protocol this crate doesn't support, whose events are passed to
+    /// `handler`.
+    pub fn bind_global<I: Proxy + 'static>(
+        &self,
+        version: RangeInclusive<u32>,
+        handler: impl ProtocolHandler<I>,
+    ) -> Result<I, BindError> {
+        self.globals
+            .bind(&self.event_queue.handle(), version, handler)
+    }
+
+    pub fn poll_dispatch(&mut self) -> Result<usize, Error> {
+        let dispatched = self.poll_dispatch_events()?;
+        self.delegate.end_session_lock_if_unlocked();
+        self.spawn_queued_apps();
+
+        match self.delegate.surface_error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(dispatched),
+        }
+    }
+
+    pub fn blocking_dispatch(&mut self) -> Result<usize, Error> {
+        self.delegate
+            .handle_clipboard_requests(&self.event_queue.handle());
+
+        let dispatched = self.event_queue.blocking_dispatch(&mut self.delegate)?;
+        self.delegate.end_session_lock_if_unlocked();
+        self.spawn_queued_apps();
+
+        match self.delegate.surface_error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(dispatched),
+        }
+    }
+
+    fn poll_dispatch_events(&mut self) -> Result<usize, DispatchError> {
+        self.delegate
+            .handle_clipboard_requests(&self.event_queue.handle());
+
+        let dispatched = self.event_queue.dispatch_pending(&mut self.delegate)?;
+        if dispatched > 0 {
+            return Ok(dispatched);
+        }
+
+        self.delegate.wayland_conn.flush()?;
+
+        if let Some(guard) = self.delegate.wayland_conn.prepare_read() {
+            match guard.read() {
+                Ok(_) => self.event_queue.dispatch_pending(&mut self.delegate),
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+                Err(e) => Err(e.into()),
+            }
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+/// What an app can be set up with before its first frame, see [`Context::new_layer_app_with`].
+pub struct CreationContext<'a> {
+    pub egui_ctx: &'a egui::Context,
+    /// The app's render state, to set up custom pipelines or register native textures.
+    pub render_state: &'a RenderState,
+    /// The output the app is shown on, `None` if the compositor picks it.
+    pub output: Option<OutputInfo>,
+    /// The id the app's state is persisted under, [`App::app_id`] of an app that's passed to
+    /// [`Context::new_layer_app`]. Apps that are created here don't have one yet, so it's the
+    /// binary's name, and their own [`App::app_id`] is used once they're created.
+    pub app_id: &'a str,
+    /// The values the app saved in its last run, see [`App::save`]. `None` unless
+    /// [`LayerAppOpts::persist_memory`] is set.
+    #[cfg(feature = "persistence")]
+    pub storage: Option<&'a Storage>,
+}
+
+#[allow(unused_variables)]
+pub trait App: Any {
+    /// Called for every frame, `frame` controls the app's surface.
+    fn update(&mut self, ctx: &egui::Context, frame: &mut LayerFrame);
+
+    /// Identifies the app, eg. to persist its state or in logs, and as the window's app id. It's
+    /// the layer namespace too, unless [`LayerAppOpts::namespace`] is set. Defaults to the
+    /// binary's name.
+    fn app_id(&self) -> String {
+        default_app_id()
+    }
+
+    /// Called with the new layer surface before its first commit, to set it up. Not called for
+    /// windows and lock surfaces.
+    fn on_init(&mut self, layer: &LayerSetup) {}
+    /// Called when the compositor gives the surface a new size, in logical pixels, before it's
+    /// drawn at that size.
+    fn on_configure(&mut self, width: u32, height: u32) {}
+    /// Called when the surface's scale factor changes, integer or fractional, before it's drawn
+    /// at that scale. Eg. to load raster images at the right resolution.
+    fn on_scale_changed(&mut self, scale: f32) {}
+    /// Called when the surface gets or loses keyboard focus, eg. to close a launcher when the
+    /// user clicks elsewhere.
+    fn on_focus_changed(&mut self, focused: bool) {}
+    /// Called when the pointer enters or leaves the surface, eg. to restyle a bar while it's
+    /// hovered. Its popups don't count.
+    fn on_hover_changed(&mut self, hovered: bool) {}
+    /// Called after every frame with egui's platform output, eg. to handle IME or AccessKit
+    /// updates. The cursor icon, copied text and opened URLs are already handled.
+    fn on_platform_output(&mut self, platform_output: &egui::PlatformOutput) {}
+    /// The parts of the surface that take mouse and touch inputs, in points, with
+    /// [`InputRegions::Custom`]. Called after every frame.
+    fn input_region(&self, ctx: &egui::Context) -> Vec<Rect> {
+        Vec::new()
+    }
+    /// Called once the app's render state is created, eg. to set up custom pipelines or register
+    /// native textures with the renderer.
+    fn on_render_state(&mut self, render_state: &RenderState) {}
     fn on_exit(&mut self) {}
+    /// Called when the user went idle or came back, see [`Context::set_idle_timeout`]. A repaint
+    /// is requested right after, eg. to dim or hide the app.
+    fn on_idle(&mut self, idle: bool) {}
+    /// Called once the compositor showed or discarded a frame, eg. to measure the latency. Only
+    /// called if the compositor supports presentation feedback.
+    fn on_presentation(&mut self, feedback: &PresentationFeedback) {}
+    /// Called when the output that was picked with [`LayerAppOpts::output`] is removed, which
+    /// closes the surface. Once a matching output is added, a new layer surface is set up with
+    /// `on_init` again. Opacity, content type, the idle inhibitor and subsurfaces carry over.
+    fn on_output_lost(&mut self) {}
+    /// Called when an output is added after the app was created, eg. to adjust the layout or
+    /// set up companion surfaces for it.
+    fn on_output_added(&mut self, info: &OutputInfo) {}
+    /// Called when an output is removed, before the apps on it exit or lose their surface.
+    fn on_output_removed(&mut self, info: &OutputInfo) {}
+    /// Called when the desktop switches between dark and light mode, with the `portal` feature.
+    /// egui follows it by itself, unless the app set a theme preference.
+    fn on_system_theme_changed(&mut self, theme: Option<egui::Theme>) {}
+    /// Called when the desktop's theme, accent color or contrast preference changes, with the
+    /// `portal` feature. egui only follows them with [`LayerAppOpts::desktop_style`].
+    fn on_appearance_changed(&mut self, appearance: &Appearance) {}
+
+    /// Called before the app's state is saved, if [`LayerAppOpts::persist_memory`] is set. The
+    /// values are passed to the next run through [`CreationContext::storage`].
+    #[cfg(feature = "persistence")]
+    fn save(&mut self, storage: &mut Storage) {}
+}
+
+/// One app shared by several surfaces, eg. to show the same clock on every output with
+/// [`Context::new_layer_app_per_output`] while keeping a single copy of its state. Each surface
+/// still has its own egui context with that output's size and scale, and calls every hook, so
+/// eg. `on_exit` is called once for each surface.
+impl<A: App + ?Sized> App for Arc<Mutex<A>> {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut LayerFrame) {
+        self.lock().unwrap().update(ctx, frame);
+    }
+
+    fn app_id(&self) -> String {
+        self.lock().unwrap().app_id()
+    }
+
+    fn on_init(&mut self, layer: &LayerSetup) {
+        self.lock().unwrap().on_init(layer);
+    }
+
+    fn on_configure(&mut self, width: u32, height: u32) {
+        self.lock().unwrap().on_configure(width, height);
+    }
+
+    fn on_scale_changed(&mut self, scale: f32) {
+        self.lock().unwrap().on_scale_changed(scale);
+    }
+
+    fn on_focus_changed(&mut self, focused: bool) {
+        self.lock().unwrap().on_focus_changed(focused);
+    }
+
+    fn on_hover_changed(&mut self, hovered: bool) {
+        self.lock().unwrap().on_hover_changed(hovered);
+    }
+
+    fn on_platform_output(&mut self, platform_output: &egui::PlatformOutput) {
+        self.lock().unwrap().on_platform_output(platform_output);
+    }
+
+    fn input_region(&self, ctx: &egui::Context) -> Vec<Rect> {
+        self.lock().unwrap().input_region(ctx)
+    }
+
+    fn on_render_state(&mut self, render_state: &RenderState) {
+        self.lock().unwrap().on_render_state(render_state);
+    }
+
+    fn on_exit(&mut self) {
+        self.lock().unwrap().on_exit();
+    }
+
+    fn on_idle(&mut self, idle: bool) {
+        self.lock().unwrap().on_idle(idle);
+    }
+
+    fn on_presentation(&mut self, feedback: &PresentationFeedback) {
+        self.lock().unwrap().on_presentation(feedback);
+    }
+
+    fn on_output_lost(&mut self) {
+        self.lock().unwrap().on_output_lost();
+    }
+
+    fn on_output_added(&mut self, info: &OutputInfo) {
+        self.lock().unwrap().on_output_added(info);
+    }
+
+    fn on_output_removed(&mut self, info: &OutputInfo) {
+        self.lock().unwrap().on_output_removed(info);
+    }
+
+    fn on_system_theme_changed(&mut self, theme: Option<egui::Theme>) {
+        self.lock().unwrap().on_system_theme_changed(theme);
+    }
+
+    fn on_appearance_changed(&mut self, appearance: &Appearance) {
+        self.lock().unwrap().on_appearance_changed(appearance);
+    }
+
+    #[cfg(feature = "persistence")]
+    fn save(&mut self, storage: &mut Storage) {
+        self.lock().unwrap().save(storage);
+    }
+}
+
+/// The options that layer and lock apps share.
+struct AppOpts {
+    /// See [`CreationContext::app_id`].
+    app_id: String,
+    /// See [`LayerAppOpts::namespace`].
+    namespace: Option<String>,
+    input_regions: InputRegions,
+    opaque_regions: OpaqueRegions,
+    surface_format: SurfaceFormat,
+    render_scale: f32,
+    msaa_samples: u32,
+    dithering: bool,
+    popup_space: Marginf,
+    viewports: bool,
+    image_description: Option<ImageDescription>,
+    url_opener: Option<UrlOpener>,
+    default_size: Option<(u32, u32)>,
+    #[cfg(feature = "persistence")]
+    persist_memory: bool,
+    desktop_style: bool,
+    setup: Option<ContextSetup>,
+}
+
+/// The role of an app's surface.
+enum ShellSurface {
+    Layer(LayerSurface),
+    Lock(SessionLockSurface),
+    /// See [`LayerAppOpts::window_fallback`].
+    Window(WindowSurface),
+    /// See [`Context::new_child_surface`].
+    Child(Popup),
+}
+
+impl WaylandSurface for ShellSurface {
+    fn wl_surface(&self) -> &wl_surface::WlSurface {
+        match self {
+            ShellSurface::Layer(layer) => layer.wl_surface(),
+            ShellSurface::Lock(lock_surface) => lock_surface.wl_surface(),
+            ShellSurface::Window(window) => window.wl_surface(),
+            ShellSurface::Child(popup) => popup.wl_surface(),
+        }
+    }
+}
+
+/// The output a layer app was meant for, and what's needed to create its layer surface again.
+struct OutputTarget {
+    /// `None` if the selector didn't match any output yet, then the compositor picked one.
+    output: Option<wl_output::WlOutput>,
+    selector: OutputSelector,
+}
+
+/// The rectangles of an input region as `[x, y, width, height]` in surface coordinates, `None`
+/// for the whole surface.
+type InputRects = Option<Vec<[i32; 4]>>;
+
+pub struct LayerApp {
+    app: Box<dyn App>,
+    /// See [`App::app_id`].
+    app_id: String,
+    /// The layer namespace, which new layer surfaces are created with. `None` for other surfaces.
+    namespace: Option<String>,
+    wgpu_surface: wgpu::Surface<'static>,
+    popups: Vec<PopupSurface>, // drop before layer
+    /// Areas whose popup was dismissed by the compositor, they don't get a new one until they're
+    /// closed.
+    dismissed_popups: HashSet<egui::Id>,
+    /// The app's child surfaces, oldest first, see [`Context::new_child_surface`].
+    children: Vec<ObjectId>,
+    popup_space: Marginf,
+    popup_shell: Option<PopupShell>,
+    /// The surfaces of egui's deferred viewports, see [`LayerAppOpts::viewports`].
+    viewport_surfaces: Vec<ViewportSurface>,
+    viewport_shell: Option<ViewportShell>,
+    // wgpu_adapter: wgpu::Adapter,
+    // wgpu_device: wgpu::Device,
+    // wgpu_queue: wgpu::Queue,
+    egui_context: egui::Context,
+    egui_render_state: egui_wgpu::RenderState,
+    surface_config: wgpu::SurfaceConfiguration,
+    surface_configured: bool,
+    /// Shapes of the previous frame, to find out what changed.
+    previous_shapes: Vec<ClippedShape>,
+    msaa_samples: u32,
+    msaa_texture: Option<(wgpu::Texture, wgpu::TextureView)>,
+    shell_surface: ShellSurface, // drop after wgpu_surface
+    /// `None` if the compositor doesn't support fractional scaling or viewports.
+    fractional_scale: Option<WpFractionalScaleV1>,
+    /// `None` if the compositor doesn't support viewports, then the buffer is scaled with
+    /// `set_buffer_scale` instead.
+    viewport: Option<WpViewport>,
+    render_scale: f32,
+    image_description: Option<ImageDescription>,
+    color_surface: Option<WpColorManagementSurfaceV1>,
+
+    /// Set when egui asks for another frame, until it's drawn.
+    frame_requested: Arc<AtomicBool>,
+    /// Set while a frame is drawn, then frame requests are committed along with it.
+    drawing: Arc<AtomicBool>,
+    /// Whether the compositor is asked for a frame callback that didn't come yet.
+    frame_pending: bool,
+    start: Instant,
+    /// The time egui got for the last frame, in seconds since `start`.
+    time: f64,
+    /// When the input events since the last frame happened, on `CLOCK_MONOTONIC`, see
+    /// [`LayerFrame::input_times`].
+    input_times: Vec<Duration>,
+    events: Vec<egui::Event>,
+    /// Events produced outside of the event loop, eg. by clipboard transfers.
+    pending_events: Arc<Mutex<Vec<egui::Event>>>,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    last_present: Option<Instant>,
+    presentation_time: Option<PresentationTimeState>,
+    offscreen_requests: Arc<Mutex<Vec<TextureCallback>>>,
+    messages: Arc<Mutex<Vec<AppMessage>>>,
+    /// The transform of the output the surface is on, which the buffer is rendered in so the
+    /// compositor doesn't have to rotate it.
+    transform: wl_output::Transform,
+    /// The transform of the current buffer.
+    buffer_transform: Arc<Mutex<wl_output::Transform>>,
+    idle_inhibitor: Option<Arc<IdleInhibitor>>,
+    app_surface: Arc<Mutex<AppSurface>>,
+    /// Set if the app was put on a specific output, to show it again when that's added back.
+    target: Option<OutputTarget>,
+    clipboard: Clipboard,
+    cursor_icon: CursorIcon,
+    /// Whether the middle button was pressed over a text field, to paste when it's released.
+    middle_paste: bool,
+    /// The primary selection's text to paste with the next frame, and where it was clicked.
+    middle_pastes: Arc<Mutex<Vec<(Pos2, String)>>>,
+    modifiers: egui::Modifiers,
+    input_regions: InputRegions,
+    /// Set from the handle, applied with the next frame.
+    new_input_regions: Arc<Mutex<Option<InputRegions>>>,
+    /// Whether egui asked to let all pointer input through, overriding `input_regions`.
+    mouse_passthrough: bool,
+    /// The input region that was set last, so it's only sent again once it changed. `None` if
+    /// it's not known, eg. for a new surface.
+    input_rects: Option<InputRects>,
+    opaque_regions: OpaqueRegions,
+    url_opener: UrlOpener,
+    exit: Arc<AtomicBool>,
+    /// Set to unmap the layer surface, see [`LayerAppHandle::hide`].
+    hidden: Arc<AtomicBool>,
+    /// Whether the layer surface was unmapped, then it's drawn again with the next configure.
+    unmapped: bool,
+    /// Set from the handle, see [`LayerApp::commit_pending`].
+    geometry: Arc<Mutex<LayerGeometry>>,
+    /// Set from the handle, see [`LayerApp::commit_pending`].
+    properties: Arc<Mutex<SurfaceProperties>>,
+    subsurfaces: Subsurfaces,
+    /// Set once the app is dropped, see [`LayerAppHandle::is_closed`].
+    closed: Arc<AtomicBool>,
+    close_listeners: Arc<Mutex<CloseListeners>>,
+    close_timer: Arc<Mutex<Option<CloseTimer>>>,
+    first_configure: bool,
+    width: u32,
+    height: u32,
+    scale: f32,
+    shift: Option<u32>,
+    keyboard_focus: bool,
+    /// The viewport whose surface has keyboard focus, if the app has it.
+    keyboard_viewport: ViewportId,
+    system_theme: SystemTheme,
+    /// The desktop's appearance the app was last told about.
+    appearance: Appearance,
+    /// See [`LayerAppOpts::desktop_style`].
+    desktop_style: bool,
+    /// The outputs the surface is on.
+    outputs: Vec<wl_output::WlOutput>,
+    /// Info of `outputs`, for the handle.
+    output_info: Arc<Mutex<Vec<OutputInfo>>>,
+    /// Set when a frame was skipped because the outputs are off, drawing resumes once one is on.
+    powered_off: bool,
+    /// See [`LayerAppOpts::default_size`], or the size of the output it was created on.
+    default_size: Option<(u32, u32)>,
+    /// Keeps weak handles working while the app is around.
+    handle: Option<Arc<LayerAppHandle>>,
+    #[cfg(feature = "accesskit")]
+    accessibility: Accessibility,
+    /// See [`LayerAppOpts::persist_memory`].
+    #[cfg(feature = "persistence")]
+    memory_storage: Option<MemoryStorage>,
+}
+
+/// Controls an app from outside of it, eg. from another thread or an async task. It's `Send` and
+/// `Sync`, clones control the same app, and it's fine to keep using them after the app is gone.
+#[derive(Clone)]
+pub struct LayerAppHandle {
+    app_id: String,
+    egui_context: egui::Context,
+    exit: Arc<AtomicBool>,
+    hidden: Arc<AtomicBool>,
+    geometry: Arc<Mutex<LayerGeometry>>,
+    properties: Arc<Mutex<SurfaceProperties>>,
+    subsurfaces: Subsurfaces,
+    closed: Arc<AtomicBool>,
+    close_listeners: Arc<Mutex<CloseListeners>>,
+    close_timer: Arc<Mutex<Option<CloseTimer>>>,
+    new_input_regions: Arc<Mutex<Option<InputRegions>>>,
+    frame_stats: Arc<Mutex<FrameStats>>,
+    render_state: RenderState,
+    offscreen_requests: Arc<Mutex<Vec<TextureCallback>>>,
+    messages: Arc<Mutex<Vec<AppMessage>>>,
+    buffer_transform: Arc<Mutex<wl_output::Transform>>,
+    output_info: Arc<Mutex<Vec<OutputInfo>>>,
+    surface: Arc<Mutex<AppSurface>>,
+    idle_inhibitor: Option<Arc<IdleInhibitor>>,
+    /// The copy of the handle that the app keeps.
+    weak: Weak<LayerAppHandle>,
+}
+
+/// A handle that doesn't keep anything of the app around, like the GPU device, eg. for D-Bus
+/// callbacks that outlive it. See [`LayerAppHandle::downgrade`].
+#[derive(Clone)]
+pub struct WeakLayerAppHandle(Weak<LayerAppHandle>);
+
+impl WeakLayerAppHandle {
+    /// The app's handle, `None` once the app is gone.
+    pub fn upgrade(&self) -> Option<LayerAppHandle> {
+        self.0
+            .upgrade()
+            .map(|handle| LayerAppHandle::clone(&handle))
+    }
+}
+
+/// Notified when an app is dropped, see [`LayerAppHandle::on_close`].
+#[derive(Default)]
+struct CloseListeners {
+    callbacks: Vec<Box<dyn FnOnce() + Send>>,
+    wakers: Vec<Waker>,
+}
+
+/// See [`LayerAppHandle::close_after`].
+#[derive(Clone, Copy)]
+struct CloseTimer {
+    timeout: Duration,
+    deadline: Instant,
+}
+
+/// Completes once an app is gone, see [`LayerAppHandle::closed`].
+pub struct AppClosed {
+    closed: Arc<AtomicBool>,
+    close_listeners: Arc<Mutex<CloseListeners>>,
+}
+
+impl Future for AppClosed {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+        let mut listeners = self.close_listeners.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            return Poll::Ready(());
+        }
+
+        if !listeners
+            .wakers
+            .iter()
+            .any(|waker| waker.will_wake(cx.waker()))
+        {
+            listeners.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// The layer surface settings that were changed through the handle. They're kept, to apply them
+/// again when the layer surface is created again.
+#[derive(Default)]
+struct LayerGeometry {
+    layer: Option<Layer>,
+    /// Whether the layer changed, which needs a new layer surface if the compositor only
+    /// supports version 1 of the protocol.
+    new_layer: bool,
+    size: Option<(u32, u32)>,
+    anchor: Option<Anchor>,
+    margin: Option<(i32, i32, i32, i32)>,
+    exclusive_zone: Option<i32>,
+    keyboard_interactivity: Option<KeyboardInteractivity>,
+    /// The output to move to, which needs a new layer surface.
+    output: Option<OutputSelector>,
+    /// The settings made in [`App::on_init`], which are overridden by the ones above.
+    init: InitGeometry,
+    /// Whether anything changed since it was last applied.
+    changed: bool,
+}
+
+/// The layer surface settings that can be invalid, as set up in [`App::on_init`].
+#[derive(Clone, Copy, Default)]
+struct InitGeometry {
+    size: Option<(u32, u32)>,
+    anchor: Option<Anchor>,
+    margin: Option<(i32, i32, i32, i32)>,
+}
+
+impl LayerGeometry {
+    /// Change the size, anchor or margin, unless the result is invalid.
+    fn update(&mut self, update: impl FnOnce(&mut Self)) -> Result<(), GeometryError> {
+        let previous = (self.size, self.anchor, self.margin);
+        update(self);
+        let result = self.validate();
+        if result.is_err() {
+            (self.size, self.anchor, self.margin) = previous;
+        }
+        result
+    }
+
+    /// Check the settings together with the ones made in [`App::on_init`], and the protocol's
+    /// defaults for the ones that were never made: no size and no anchor.
+    fn validate(&self) -> Result<(), GeometryError> {
+        let (width, height) = self.size.or(self.init.size).unwrap_or((0, 0));
+        let anchor = self.anchor.or(self.init.anchor).unwrap_or(Anchor::empty());
+        if width == 0 && !anchor.contains(Anchor::LEFT | Anchor::RIGHT) {
+            return Err(GeometryError::ZeroWidthNotAnchored);
+        }
+        if height == 0 && !anchor.contains(Anchor::TOP | Anchor::BOTTOM) {
+            return Err(GeometryError::ZeroHeightNotAnchored);
+        }
+
+        let Some((top, right, bottom, left)) = self.margin.or(self.init.margin) else {
+            return Ok(());
+        };
+        let hidden = |edge: Anchor, margin: i32, size: u32| {
+            anchor.contains(edge) && size > 0 && i64::from(margin) <= -i64::from(size)
+        };
+        if hidden(Anchor::TOP, top, height)
+            || hidden(Anchor::RIGHT, right, width)
+            || hidden(Anchor::BOTTOM, bottom, height)
+            || hidden(Anchor::LEFT, left, width)
+        {
+            return Err(GeometryError::MarginHidesSurface);
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, layer: &LayerSurface) {
+        if let (Some(new_layer), true) = (self.layer, layer_surface_version(layer) >= 2) {
+            layer.set_layer(new_layer);
+            self.new_layer = false;
+        }
+        if let Some((width, height)) = self.size {
+            layer.set_size(width, height);
+        }
+        if let Some(anchor) = self.anchor {
+            layer.set_anchor(anchor);
+        }
+        if let Some((top, right, bottom, left)) = self.margin {
+            layer.set_margin(top, right, bottom, left);
+        }
+        if let Some(zone) = self.exclusive_zone {
+            layer.set_exclusive_zone(zone);
+        }
+        match self.keyboard_interactivity {
+            // It was added in version 4, before that it would be a protocol error
+            Some(KeyboardInteractivity::OnDemand) if layer_surface_version(layer) < 4 => {
+                layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+            }
+            Some(interactivity) => layer.set_keyboard_interactivity(interactivity),
+            None => {}
+        }
+    }
+}
+
+/// The surface settings that were changed through the handle. They're applied with the next
+/// frame, or committed on their own from the event loop, see [`LayerApp::commit_pending`].
+#[derive(Default)]
+struct SurfaceProperties {
+    opacity: Option<f32>,
+    content_type: Option<ContentType>,
+    /// Whether anything changed since it was last applied.
+    changed: bool,
+}
+
+impl SurfaceProperties {
+    fn apply(&self, surface: &AppSurface) {
+        if let (Some(opacity), Some(alpha_surface)) = (self.opacity, &surface.alpha_surface) {
+            alpha_surface.set_multiplier(wp_alpha_modifier::multiplier(opacity));
+        }
+        if let (Some(content_type), Some(content_type_surface)) =
+            (self.content_type, &surface.content_type_surface)
+        {
+            content_type_surface.set_content_type(content_type.into());
+        }
+    }
+}
+
+fn layer_surface_version(layer: &LayerSurface) -> u32 {
+    match layer.kind() {
+        SurfaceKind::Wlr(layer_surface) => layer_surface.version(),
+        _ => 1,
+    }
+}
+
+/// An app's surface and the objects extending it, which are replaced along with it when the
+/// surface is created again, see [`App::on_output_lost`] and [`Context::reconnect`].
+struct AppSurface {
+    surface: wl_surface::WlSurface,
+    /// Only the protocol object, so the layer surface isn't kept alive by the app's handles.
+    layer_surface: Option<ZwlrLayerSurfaceV1>,
+    /// The connection the surface belongs to, to flush requests made from other threads.
+    wayland_conn: Connection,
+    waker: LoopWaker,
+    /// Wakes the event loop up for the app from the repaint timer, see
+    /// [`AppSurface::wake_after`].
+    wake: RequestFrame,
+    repaint_scheduler: RepaintScheduler,
+    alpha_surface: Option<WpAlphaModifierSurfaceV1>,
+    content_type_surface: Option<WpContentTypeV1>,
+}
+
+impl AppSurface {
+    /// Update the app from the event loop once `delay` passed, see [`ContextDelegate::wake_app`].
+    fn wake_after(&self, delay: Duration) {
+        self.repaint_scheduler
+            .request_frame_after(delay, &self.wake);
+    }
+
+    /// Destroy the objects extending the surface, once it's replaced or the app is gone.
+    fn destroy(&self) {
+        if let Some(alpha_surface) = &self.alpha_surface {
+            alpha_surface.destroy();
+        }
+        if let Some(content_type_surface) = &self.content_type_surface {
+            content_type_surface.destroy();
+        }
+    }
+}
+
+impl LayerAppHandle {
+    /// Close the app from the event loop, which also ends a [`Context::blocking_dispatch`] that's
+    /// waiting for events.
+    pub fn exit(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+        self.wake();
+    }
+
+    /// See [`App::app_id`].
+    pub fn app_id(&self) -> &str {
+        &self.app_id
+    }
+
+    /// Unmap the layer surface, see [`LayerFrame::hide`]. The app keeps its state, egui memory
+    /// and GPU resources, so it's shown again right away, eg. for OSDs. Does nothing if the app
+    /// isn't on a layer surface.
+    pub fn hide(&self) {
+        if self.surface.lock().unwrap().layer_surface.is_none() {
+            return;
+        }
+        self.hidden.store(true, Ordering::Relaxed);
+        self.egui_context.request_repaint();
+    }
+
+    /// Map the layer surface again after it was hidden. It's drawn once the compositor configured
+    /// it again.
+    pub fn show(&self) {
+        if self.surface.lock().unwrap().layer_surface.is_some()
+            && self.hidden.swap(false, Ordering::Relaxed)
+        {
+            self.wake();
+        }
+    }
+
+    /// Ask for a new size, in logical pixels, see [`LayerFrame::set_size`]. Like the other
+    /// settings below it's committed right away from the event loop, and ignored if the app isn't
+    /// on a layer surface. Invalid settings are rejected, see [`GeometryError`].
+    pub fn set_size(&self, width: u32, height: u32) -> Result<(), GeometryError> {
+        self.try_update_geometry(|geometry| geometry.size = Some((width, height)))
+    }
+
+    pub fn set_anchor(&self, anchor: Anchor) -> Result<(), GeometryError> {
+        self.try_update_geometry(|geometry| geometry.anchor = Some(anchor))
+    }
+
+    pub fn set_margin(
+        &self,
+        top: i32,
+        right: i32,
+        bottom: i32,
+        left: i32,
+    ) -> Result<(), GeometryError> {
+        self.try_update_geometry(|geometry| geometry.margin = Some((top, right, bottom, left)))
+    }
+
+    pub fn set_exclusive_zone(&self, zone: i32) {
+        self.update_geometry(|geometry| geometry.exclusive_zone = Some(zone));
+    }
+
+    /// Whether the surface takes keyboard focus, eg. only while a notification center is
+    /// expanded. [`KeyboardInteractivity::OnDemand`] needs version 4 of the layer shell protocol,
+    /// older compositors get [`KeyboardInteractivity::None`] instead.
+    pub fn set_keyboard_interactivity(&self, interactivity: KeyboardInteractivity) {
+        self.update_geometry(|geometry| geometry.keyboard_interactivity = Some(interactivity));
+    }
+
+    /// Switch to other input regions, eg. to let clicks through an overlay until a hotkey is
+    /// pressed. Applied with the next frame.
+    pub fn set_input_regions(&self, input_regions: InputRegions) {
+        *self.new_input_regions.lock().unwrap() = Some(input_regions);
+        self.egui_context.request_repaint();
+    }
+
+    /// Move the app to another output, eg. the focused one. It gets a new layer surface there
+    /// that's set up with [`App::on_init`] again, like when its output is removed, but it keeps
+    /// its state. If no output matches yet, the compositor picks one until a matching one is
+    /// added. Ignored if the app isn't on a layer surface.
+    pub fn move_to_output(&self, selector: OutputSelector) {
+        self.update_geometry(|geometry| geometry.output = Some(selector));
+    }
+
+    /// Move the surface to another layer, eg. a bar to [`Layer::Overlay`] to show it over
+    /// fullscreen windows. If the compositor doesn't support changing it, the app gets a new
+    /// layer surface that's set up with [`App::on_init`] again.
+    pub fn set_layer(&self, layer: Layer) {
+        self.update_geometry(|geometry| {
+            if geometry.layer != Some(layer) {
+                geometry.layer = Some(layer);
+                geometry.new_layer = true;
+            }
+        });
+    }
+
+    /// Commit the changed settings from the event loop, see [`LoopWaker`].
+    fn wake(&self) {
+        let surface = self.surface.lock().unwrap();
+        surface.waker.wake(&surface.surface);
+    }
+
+    fn update_geometry(&self, update: impl FnOnce(&mut LayerGeometry)) {
+        let mut geometry = self.geometry.lock().unwrap();
+        update(&mut geometry);
+        geometry.changed = true;
+        drop(geometry);
+        self.wake();
+    }
+
+    /// Like [`LayerAppHandle::update_geometry`], for the settings that can be invalid.
+    fn try_update_geometry(
+        &self,
+        update: impl FnOnce(&mut LayerGeometry),
+    ) -> Result<(), GeometryError> {
+        // Windows and lock surfaces aren't checked against the layer surface's defaults
+        if self.surface.lock().unwrap().layer_surface.is_none() {
+            return Ok(());
+        }
+        let mut geometry = self.geometry.lock().unwrap();
+        geometry.update(update)?;
+        geometry.changed = true;
+        drop(geometry);
+        self.wake();
+        Ok(())
+    }
+
+    /// Draw a new frame, eg. when the data the app shows changed. Works from any thread.
+    pub fn request_repaint(&self) {
+        self.egui_context.request_repaint();
+    }
+
+    /// Draw a new frame once `delay` has passed, eg. for a clock.
+    pub fn request_repaint_after(&self, delay: Duration) {
+        self.egui_context.request_repaint_after(delay);
+    }
+
+    /// Whether the layer surface is hidden, see [`LayerAppHandle::hide`].
+    pub fn is_hidden(&self) -> bool {
+        self.hidden.load(Ordering::Relaxed)
+    }
+
+    /// The app's surface, eg. to use with protocols this crate doesn't support. It's replaced by
+    /// a new one if its output comes back after being removed, see [`App::on_output_lost`].
+    ///
+    /// This is advanced API: changing state that the crate manages itself, eg. attaching a
+    /// buffer or setting the input region, gets the surface out of sync with the app.
+    pub fn wl_surface(&self) -> wl_surface::WlSurface {
+        self.surface.lock().unwrap().surface.clone()
+    }
+
+    /// The app's layer surface, eg. for one-off protocol calls like KDE's blur. `None` if the app
+    /// is gone or isn't shown on a layer surface, and replaced like
+    /// [`LayerAppHandle::wl_surface`].
+    ///
+    /// This is advanced API like [`LayerAppHandle::wl_surface`]. The layer surface isn't
+    /// destroyed while the returned one is kept, so it shouldn't be kept past the call. Its
+    /// settings are managed by the handle, eg. [`LayerAppHandle::set_layer`] to change its layer,
+    /// and changing them here gets them out of sync.
+    pub fn layer_surface(&self) -> Option<LayerSurface> {
+        LayerSurface::from_wlr_surface(self.surface.lock().unwrap().layer_surface.as_ref()?)
+    }
+
+    /// A weak handle to the app, see [`WeakLayerAppHandle`].
+    pub fn downgrade(&self) -> WeakLayerAppHandle {
+        WeakLayerAppHandle(self.weak.clone())
+    }
+
+    /// Whether the app is gone, because it exited or the compositor closed its surface.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Call `callback` once the app is gone, eg. to start it again or quit. It's called from the
+    /// event loop, or right away if the app is already gone.
+    pub fn on_close(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut listeners = self.close_listeners.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            drop(listeners);
+            callback();
+        } else {
+            listeners.callbacks.push(Box::new(callback));
+        }
+    }
+
+    /// Close the app once `timeout` passed without input, eg. for OSDs. Calling it again starts
+    /// the timer over, eg. when the volume changed again.
+    pub fn close_after(&self, timeout: Duration) {
+        *self.close_timer.lock().unwrap() = Some(CloseTimer {
+            timeout,
+            deadline: Instant::now() + timeout,
+        });
+        self.surface.lock().unwrap().wake_after(timeout);
+    }
+
+    /// Keep the app open after all, see [`LayerAppHandle::close_after`].
+    pub fn cancel_close(&self) {
+        *self.close_timer.lock().unwrap() = None;
+    }
+
+    /// A future that completes once the app is gone, for async code.
+    pub fn closed(&self) -> AppClosed {
+        AppClosed {
+            closed: self.closed.clone(),
+            close_listeners: self.close_listeners.clone(),
+        }
+    }
+
+    /// The app's render state, to access the wgpu device and queue or register native textures
+    /// with the renderer.
+    pub fn render_state(&self) -> &RenderState {
+        &self.render_state
+    }
+
+    /// Render the next frame into a texture as well, which is passed to `callback` once it's
+    /// submitted to the GPU. The texture has the buffer's size, format and orientation, which is
+    /// rotated along with the output, and can be used to render from, copied, or registered as a
+    /// native texture with the renderer.
+    pub fn render_to_texture(&self, callback: impl FnOnce(wgpu::Texture) + Send + 'static) {
+        self.offscreen_requests
+            .lock()
+            .unwrap()
+            .push(Box::new(callback));
+        self.egui_context.request_repaint();
+    }
+
+    /// Call `f` with the app before its next frame if it's a `T`, or an `Arc<Mutex<T>>` that's
+    /// shared between surfaces, eg. to pass it data from another thread, where
+    /// [`Context::with_app_mut`] can't be used. Works from any thread. If the app is of another
+    /// type, `f` isn't called and a warning is logged.
+    pub fn send<T: App>(&self, f: impl FnOnce(&mut T) + Send + 'static) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(Box::new(|app: &mut dyn App| {
+                if with_app_as(app, f).is_none() {
+                    warn!(
+                        "Dropped a message for a {}, the app is of another type",
+                        type_name::<T>()
+                    );
+                }
+            }));
+        self.egui_context.request_repaint();
+    }
+
+    /// Take a screenshot of the next frame. The image is passed to `callback` once it's read back
+    /// from the GPU, which the frame waits for.
+    pub fn screenshot(&self, callback: impl FnOnce(egui::ColorImage) + Send + 'static) {
+        let device = self.render_state.device.clone();
+        let queue = self.render_state.queue.clone();
+        let buffer_transform = self.buffer_transform.clone();
+
+        self.render_to_texture(move |texture| {
+            // The callback runs right after rendering, so this is the frame's transform
+            let transform = *buffer_transform.lock().unwrap();
+            screenshot::read_texture(&device, &queue, texture, transform, callback);
+        });
+    }
+
+    /// Import a dmabuf as a texture that egui can show as an image. The context needs to be
+    /// created with [`Context::with_dmabuf_import`]. The buffer is copied, and has to be fully
+    /// written by its producer before, see [`import_texture`]. Free the texture with
+    /// [`egui_wgpu::Renderer::free_texture`] once it's no longer shown.
+    #[cfg(feature = "dmabuf")]
+    pub fn import_dmabuf(&self, dmabuf: Dmabuf) -> Result<egui::TextureId, DmabufError> {
+        let device = &self.render_state.device;
+        let texture = import_texture(device, &self.render_state.queue, dmabuf)?;
+        let view = texture.create_view(&Default::default());
+
+        Ok(self.render_state.renderer.write().register_native_texture(
+            device,
+            &view,
+            wgpu::FilterMode::Linear,
+        ))
+    }
+
+    /// Change the opacity of the whole surface, between 0 and 1, eg. to fade it in or out. The
+    /// compositor applies it on top of the rendered frame, so it's committed right away without
+    /// rendering it again. Returns `false` if the compositor doesn't support this.
+    pub fn set_opacity(&self, opacity: f32) -> bool {
+        if self.surface.lock().unwrap().alpha_surface.is_none() {
+            return false;
+        }
+
+        let mut properties = self.properties.lock().unwrap();
+        properties.opacity = Some(opacity);
+        properties.changed = true;
+        drop(properties);
+        self.wake();
+        true
+    }
+
+    /// Tell the compositor what kind of content the surface shows, eg. so it can enable adaptive
+    /// sync for games and videos. It's committed right away, like
+    /// [`LayerAppHandle::set_opacity`]. Returns `false` if the compositor doesn't support this.
+    pub fn set_content_type(&self, content_type: ContentType) -> bool {
+        if self.surface.lock().unwrap().content_type_surface.is_none() {
+            return false;
+        }
+
+        let mut properties = self.properties.lock().unwrap();
+        properties.content_type = Some(content_type);
+        properties.changed = true;
+        drop(properties);
+        self.wake();
+        true
+    }
+
+    /// Keep the screen from going idle while the surface is visible, eg. while playing a video.
+    /// The compositor ignores this while the surface is hidden, and it's released when the app
+    /// exits. Returns `false` if the compositor doesn't support this.
+    pub fn set_idle_inhibited(&self, inhibited: bool) -> bool {
+        let Some(idle_inhibitor) = &self.idle_inhibitor else {
+            return false;
+        };
+
+        idle_inhibitor.set_inhibited(inhibited);
+        let _ = self.surface.lock().unwrap().wayland_conn.flush();
+        true
+    }
+
+    /// The outputs the surface is on, usually one unless it spans several. Changes trigger a
+    /// repaint, eg. to adjust the layout to the output's size.
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.output_info.lock().unwrap().clone()
+    }
+
+    /// The highest refresh rate among the outputs the surface is on, in Hz.
+    pub fn refresh_rate(&self) -> Option<f32> {
+        refresh_rate(&self.output_info.lock().unwrap())
+    }
+
+    /// Timings of the last presented frame.
+    pub fn frame_stats(&self) -> FrameStats {
+        *self.frame_stats.lock().unwrap()
+    }
+}
+
+/// Timings of a frame, to find out where the time goes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+    /// Number of frames presented so far.
+    pub frames: u64,
+    /// Running the app's update.
+    pub update: Duration,
+    /// Turning egui's shapes into meshes.
+    pub tessellation: Duration,
+    /// Acquiring the surface texture, uploading textures and buffers, and submitting the render
+    /// pass to the GPU.
+    pub submit: Duration,
+    /// From the start of the frame until it was presented.
+    pub total: Duration,
+    /// Frames per second, averaged over the last few frames.
+    pub fps: f32,
+    /// From the start of the last shown frame until the compositor showed it, or zero if the
+    /// compositor doesn't support presentation feedback.
+    pub latency: Duration,
+    /// From the last input event until the frame that handles it started, or zero if the
+    /// compositor doesn't send precise input timestamps.
+    pub input_latency: Duration,
+}
+
+/// Pick a configuration that the surface supports, preferring alpha modes that allow
+/// transparency.
+fn surface_configuration(
+    capabilities: &SurfaceCapabilities,
+    format: TextureFormat,
+) -> wgpu::SurfaceConfiguration {
+    let alpha_mode = [
+        CompositeAlphaMode::PreMultiplied,
+        CompositeAlphaMode::PostMultiplied,
+        CompositeAlphaMode::Inherit,
+    ]
+    .into_iter()
+    .find(|mode| capabilities.alpha_modes.contains(mode))
+    .unwrap_or(CompositeAlphaMode::Auto);
+
+    // Wayland is inherently a mailbox system.
+    let present_mode = if capabilities
+        .present_modes
+        .contains(&wgpu::PresentMode::Mailbox)
+    {
+        wgpu::PresentMode::Mailbox
+    } else {
+        wgpu::PresentMode::Fifo
+    };
+
+    wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        view_formats: vec![format],
+        alpha_mode,
+        width: DEFAULT_WIDTH,
+        height: DEFAULT_HEIGHT,
+        desired_maximum_frame_latency: 2,
+        present_mode,
+    }
+}
+
+fn is_rotated(transform: wl_output::Transform) -> bool {
+    matches!(
+        transform,
+        wl_output::Transform::_90
+            | wl_output::Transform::_270
+            | wl_output::Transform::Flipped90
+            | wl_output::Transform::Flipped270
+    )
+}
+
+/// Map a position on a surface of the given size to the buffer, which is transformed like the
+/// output it's on.
+fn transform_pos(pos: Pos2, transform: wl_output::Transform, size: Vec2) -> Pos2 {
+    use wl_output::Transform;
+
+    let (x, y, w, h) = (pos.x, pos.y, size.x, size.y);
+    let (x, y) = match transform {
+        Transform::_90 => (y, w - x),
+        Transform::_180 => (w - x, h - y),
+        Transform::_270 => (h - y, x),
+        Transform::Flipped => (w - x, y),
+        Transform::Flipped90 => (y, x),
+        Transform::Flipped180 => (x, h - y),
+        Transform::Flipped270 => (h - y, w - x),
+        _ => (x, y),
+    };
+    Pos2::new(x, y)
+}
+
+fn transform_rect(rect: Rect, transform: wl_output::Transform, size: Vec2) -> Rect {
+    Rect::from_two_pos(
+        transform_pos(rect.min, transform, size),
+        transform_pos(rect.max, transform, size),
+    )
+}
+
+/// (Re)create a multisampled texture that matches the surface, if needed.
+fn update_msaa_texture(
+    device: &wgpu::Device,
+    surface_config: &wgpu::SurfaceConfiguration,
+    msaa_samples: u32,
+    msaa_texture: &mut Option<(wgpu::Texture, wgpu::TextureView)>,
+) {
+    if msaa_samples <= 1 {
+        return;
+    }
+
+    let size = wgpu::Extent3d {
+        width: surface_config.width,
+        height: surface_config.height,
+        depth_or_array_layers: 1,
+    };
+
+    if msaa_texture.as_ref().map(|(texture, _)| texture.size()) == Some(size) {
+        return;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("egui_msaa_texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: msaa_samples,
+        dimension: wgpu::TextureDimension::D2,
+        format: surface_config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&Default::default());
+    *msaa_texture = Some((texture, view));
+}
+
+/// Call `f` with `app` if it's a `T`, or the `T` in an `Arc<Mutex<T>>` that's shared between
+/// surfaces.
+fn with_app_as<T: App, R>(app: &mut dyn App, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+    let app = app as &mut dyn Any;
+    if let Some(shared) = app.downcast_mut::<Arc<Mutex<T>>>() {
+        return Some(f(&mut shared.lock().unwrap()));
+    }
+    app.downcast_mut().map(f)
+}
+
+/// Fill the popup space around the surface with empty panels, so the app's own panels only cover
+/// the surface.
+fn reserve_popup_space(ctx: &egui::Context, popup_space: Marginf) {
+    use egui::{Frame, SidePanel, TopBottomPanel};
+
+    if popup_space.top > 0. {
+        TopBottomPanel::top("egui_wlr_layer_popup_space_top")
+            .exact_height(popup_space.top)
+            .frame(Frame::NONE)
+            .resizable(false)
+            .show_separator_line(false)
+            .show(ctx, |_| {});
+    }
+    if popup_space.bottom > 0. {
+        TopBottomPanel::bottom("egui_wlr_layer_popup_space_bottom")
+            .exact_height(popup_space.bottom)
+            .frame(Frame::NONE)
+            .resizable(false)
+            .show_separator_line(false)
+            .show(ctx, |_| {});
+    }
+    if popup_space.left > 0. {
+        SidePanel::left("egui_wlr_layer_popup_space_left")
+            .exact_width(popup_space.left)
+            .frame(Frame::NONE)
+            .resizable(false)
+            .show_separator_line(false)
+            .show(ctx, |_| {});
+    }
+    if popup_space.right > 0. {
+        SidePanel::right("egui_wlr_layer_popup_space_right")
+            .exact_width(popup_space.right)
+            .frame(Frame::NONE)
+            .resizable(false)
+            .show_separator_line(false)
+            .show(ctx, |_| {});
+    }
+}
+
+/// Give keyboard focus to the widget under `pos` that can take it, eg. a text edit, like clicking
+/// it would.
+fn focus_widget_at(ctx: &egui::Context, pos: Pos2) {
+    let Some(layer_id) = ctx.layer_id_at(pos) else {
+        return;
+    };
+    let pos = ctx
+        .layer_transform_from_global(layer_id)
+        .map_or(pos, |transform| transform * pos);
+    // The widgets of the last frame, which is what the pointer is over
+    let id = ctx.viewport(|viewport| {
+        viewport
+            .prev_pass
+            .widgets
+            .get_layer(layer_id)
+            .filter(|widget| {
+                widget.enabled && widget.sense.is_focusable() && widget.interact_rect.contains(pos)
+            })
+            .last()
+            .map(|widget| widget.id)
+    });
+    if let Some(id) = id {
+        ctx.memory_mut(|memory| memory.request_focus(id));
+    }
+}
+
+/// Find the app that a surface belongs to, which is either its layer surface, one of its popups or
+/// one of its viewports' surfaces. Also returns the viewport the surface shows, and the offset of
+/// the surface in surface coordinates.
+#[allow(clippy::mutable_key_type)]
+fn find_app<'a>(
+    apps: &'a mut HashMap<ObjectId, LayerApp>,
+    surface: &wl_surface::WlSurface,
+) -> Option<(&'a mut LayerApp, ViewportId, Vec2)> {
+    let id = surface.id();
+    if apps.contains_key(&id) {
+        return apps
+            .get_mut(&id)
+            .map(|app| (app, ViewportId::ROOT, Vec2::ZERO));
+    }
+
+    apps.values_mut().find_map(|app| {
+        let viewport = app
+            .viewport_surfaces
+            .iter()
+            .find(|viewport| viewport.wl_surface().id() == id)
+            .map(|viewport| viewport.id);
+        if let Some(viewport) = viewport {
+            return Some((app, viewport, Vec2::ZERO));
+        }
+
+        let zoom = app.egui_context.zoom_factor();
+        let popup = app
+            .popups
+            .iter()
+            .find(|popup| popup.wl_surface().id() == id)?;
+        let offset = popup.rect.min.to_vec2() * zoom;
+        Some((app, ViewportId::ROOT, offset))
+    })
+}
+
+/// Whether anything is drawn differently than in the previous frame, to skip rendering frames
+/// that look the same.
+fn shapes_changed(previous: &[ClippedShape], current: &[ClippedShape]) -> bool {
+    // paint callbacks can draw something different without their shape changing
+    previous.len() != current.len()
+        || previous
+            .iter()
+            .zip(current)
+            .any(|(old, new)| old != new || matches!(new.shape, egui::Shape::Callback(_)))
 }
 
-pub struct LayerApp {
-    app: Box<dyn App>,
-    wgpu_surface: wgpu::Surface<'static>,
-    // wgpu_adapter: wgpu::Adapter,
-    // wgpu_device: wgpu::Device,
-    // wgpu_queue: wgpu::Queue,
-    egui_context: egui::Context,
-    egui_render_state: egui_wgpu::RenderState,
-    layer: LayerSurface, // drop after wgpu_surface
-    #[allow(dead_code)] // just needs to stay alive
-    fractional_scale: WpFractionalScaleV1,
+/// Find the areas that are covered by opaque rectangles, in points.
+fn opaque_rects(shapes: &[ClippedShape]) -> Vec<Rect> {
+    fn add(shape: &egui::Shape, clip_rect: Rect, rects: &mut Vec<Rect>) {
+        match shape {
+            egui::Shape::Vec(shapes) => {
+                for shape in shapes {
+                    add(shape, clip_rect, rects);
+                }
+            }
+            egui::Shape::Rect(rect) if rect.fill.is_opaque() && rect.brush.is_none() => {
+                // stay clear of the rounded corners and blurred edges
+                let radius = rect.corner_radius;
+                let inset = radius.nw.max(radius.ne).max(radius.sw).max(radius.se) as f32;
+                let opaque = rect.rect.shrink(inset + rect.blur_width);
+
+                if opaque.is_positive() {
+                    rects.push(opaque.intersect(clip_rect));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut rects = Vec::new();
+    for clipped in shapes {
+        add(&clipped.shape, clipped.clip_rect, &mut rects);
+    }
+    rects.retain(|rect| rect.is_positive());
+    rects
+}
 
-    frame_requested: Arc<AtomicBool>,
-    start: Instant,
-    events: Vec<egui::Event>,
-    modifiers: egui::Modifiers,
-    input_regions: InputRegions,
-    exit: Arc<AtomicBool>,
-    first_configure: bool,
-    width: u32,
-    height: u32,
-    scale: f32,
-    shift: Option<u32>,
-    keyboard_focus: bool,
+/// The logical size of an output, or its current mode's size if it's not known.
+fn output_size(info: &OutputInfo) -> Option<(u32, u32)> {
+    let (width, height) = info.logical_size.or_else(|| {
+        let mode = info.modes.iter().find(|mode| mode.current)?;
+        let (width, height) = mode.dimensions;
+        let scale = info.scale_factor.max(1);
+        Some(match is_rotated(info.transform) {
+            true => (height / scale, width / scale),
+            false => (width / scale, height / scale),
+        })
+    })?;
+    Some((width.max(1) as u32, height.max(1) as u32))
 }
 
-pub struct LayerAppHandle {
-    egui_context: egui::Context,
-    exit: Arc<AtomicBool>,
+/// The highest refresh rate of the current modes of `outputs`, in Hz.
+fn refresh_rate(outputs: &[OutputInfo]) -> Option<f32> {
+    outputs
+        .iter()
+        .flat_map(|info| info.modes.iter().filter(|mode| mode.current))
+        .map(|mode| mode.refresh_rate as f32 / 1000.)
+        .filter(|&refresh_rate| refresh_rate > 0.)
+        .max_by(f32::total_cmp)
 }
 
-impl LayerAppHandle {
-    pub fn exit(&self) {
-        self.exit.store(true, Ordering::Relaxed);
-        self.egui_context.request_repaint();
+impl Drop for LayerApp {
+    fn drop(&mut self) {
+        #[cfg(feature = "persistence")]
+        if let Some(memory_storage) = &mut self.memory_storage {
+            self.app.save(&mut memory_storage.storage);
+            memory_storage.save(&self.egui_context);
+        }
+
+        // The handle can outlive the app, so it can't hold on to the inhibitor
+        if let Some(idle_inhibitor) = &self.idle_inhibitor {
+            idle_inhibitor.release();
+        }
+        self.app_surface.lock().unwrap().destroy();
+
+        // Set while locked, so listeners that are added at the same time aren't missed
+        let listeners = {
+            let mut listeners = self.close_listeners.lock().unwrap();
+            self.closed.store(true, Ordering::Relaxed);
+            take(&mut *listeners)
+        };
+        for callback in listeners.callbacks {
+            callback();
+        }
+        for waker in listeners.wakers {
+            waker.wake();
+        }
     }
 }
 
 impl LayerApp {
+    /// Set the input region if it changed, returns whether it did.
+    fn update_input_region(&mut self, compositor: &CompositorState) -> bool {
+        // Set by egui's `MousePassthrough` viewport command
+        let input_regions = match self.mouse_passthrough {
+            true => InputRegions::None,
+            false => self.input_regions,
+        };
+        let rects = match input_regions {
+            InputRegions::Full => None,
+            InputRegions::WindowsOnly => {
+                let layers = self
+                    .egui_context
+                    .memory(|memory| {
+                        let areas = memory.areas();
+
+                        areas
+                            .visible_layer_ids()
+                            .into_iter()
+                            .filter(|layer| layer.order > egui::Order::Background)
+                            .filter(|layer| areas.is_visible(layer))
+                            .map(|layer| layer.id)
+                            .collect::<Vec<_>>()
+                    })
+                    .into_iter()
+                    .filter_map(|id| AreaState::load(&self.egui_context, id));
+
+                let rects = layers.filter_map(|layer| {
+                    let (pos, size) = layer.pivot_pos.zip(layer.size)?;
+                    Some([
+                        pos.x.floor() as i32,
+                        pos.y.floor() as i32,
+                        size.x.ceil() as i32,
+                        size.y.ceil() as i32,
+                    ])
+                });
+                Some(rects.collect())
+            }
+            InputRegions::Custom => {
+                let zoom = self.egui_context.zoom_factor();
+                let rects = self
+                    .app
+                    .input_region(&self.egui_context)
+                    .into_iter()
+                    .map(|rect| {
+                        // the region is in surface coordinates, so round outwards
+                        let rect = rect * zoom;
+                        let (min, max) = (rect.min.floor(), rect.max.ceil());
+                        [min.x as i32, min.y as i32, (max.x - min.x) as i32, (max.y - min.y) as i32]
+                    });
+                Some(rects.collect())
+            }
+            InputRegions::None => Some(Vec::new()),
+        };
+
+        if self.input_rects.as_ref() == Some(&rects) {
+            return false;
+        }
+        match &rects {
+            None => self.shell_surface.set_input_region(None),
+            Some(rects) => {
+                // The compositor copies the region, so it can be destroyed right away
+                let Ok(region) = Region::new(compositor) else {
+                    return false;
+                };
+                for &[x, y, width, height] in rects {
+                    region.add(x, y, width, height);
+                }
+                self.shell_surface
+                    .set_input_region(Some(region.wl_region()));
+            }
+        }
+        self.input_rects = Some(rects);
+        true
+    }
+
+    /// Apply the viewport commands that make sense for a layer surface, the rest is ignored.
+    fn handle_viewport_commands(
+        &mut self,
+        commands: &[egui::ViewportCommand],
+        compositor: &CompositorState,
+    ) {
+        let zoom = self.egui_context.zoom_factor();
+
+        for command in commands {
+            match command {
+                egui::ViewportCommand::Close => self.exit.store(true, Ordering::Relaxed),
+                egui::ViewportCommand::InnerSize(size) => {
+                    if let ShellSurface::Layer(layer) = &self.shell_surface {
+                        let size = (*size * zoom).round();
+                        let (width, height) = (size.x as u32, size.y as u32);
+                        let mut geometry = self.geometry.lock().unwrap();
+                        match geometry.update(|geometry| geometry.size = Some((width, height))) {
+                            Ok(()) => layer.set_size(width, height),
+                            Err(e) => warn!("Ignoring viewport size: {e}"),
+                        }
+                    }
+                }
+                egui::ViewportCommand::MousePassthrough(passthrough) => {
+                    self.mouse_passthrough = *passthrough;
+                    self.update_input_region(compositor);
+                }
+                _ => continue,
+            }
+
+            // Everything above takes effect with the next commit
+            self.egui_context.request_repaint();
+        }
+    }
+
+    /// What egui gets to know about a viewport's surface, whose size is in logical pixels.
+    fn viewport_info(
+        &self,
+        viewport: ViewportId,
+        size: (u32, u32),
+        title: Option<String>,
+    ) -> ViewportInfo {
+        let zoom = self.egui_context.zoom_factor();
+        let monitor_size = self
+            .output_info
+            .lock()
+            .unwrap()
+            .first()
+            .and_then(|info| info.logical_size)
+            .map(|(width, height)| egui::vec2(width as f32, height as f32) / zoom);
+        // Layer surfaces don't know where they are on the output
+        let rect = Rect::from_min_size(Pos2::ZERO, egui::vec2(size.0 as f32, size.1 as f32) / zoom);
+
+        ViewportInfo {
+            title,
+            native_pixels_per_point: Some(self.scale * self.render_scale),
+            monitor_size,
+            inner_rect: Some(rect),
+            outer_rect: Some(rect),
+            minimized: Some(false),
+            maximized: Some(false),
+            fullscreen: Some(false),
+            focused: Some(self.keyboard_focus && self.keyboard_viewport == viewport),
+            ..Default::default()
+        }
+    }
+
+    /// The largest texture the GPU supports, egui splits its font atlas and images to fit.
+    fn max_texture_side(&self) -> usize {
+        self.egui_render_state
+            .device
+            .limits()
+            .max_texture_dimension_2d as usize
+    }
+
+    /// Copy text and open URLs for egui.
+    fn handle_output_commands(&self, commands: &[OutputCommand]) {
+        for command in commands {
+            match command {
+                OutputCommand::CopyText(text) => self.clipboard.offer_text(text.clone()),
+                OutputCommand::OpenUrl(open_url) => (self.url_opener)(open_url),
+                OutputCommand::CopyImage(_) => {}
+            }
+        }
+    }
+
+    /// The input for the given viewport's next pass. Falls back to the root viewport's, eg. when
+    /// the viewport was closed in the meantime.
+    fn viewport_events(&mut self, viewport: ViewportId) -> &mut Vec<egui::Event> {
+        match self
+            .viewport_surfaces
+            .iter_mut()
+            .find(|surface| surface.id == viewport)
+        {
+            Some(surface) => &mut surface.events,
+            None => &mut self.events,
+        }
+    }
+
+    /// Create surfaces for the deferred viewports that are shown, and remove the ones that aren't
+    /// anymore. Immediate viewports don't show up here, as egui embeds them when there's no
+    /// renderer for them.
+    fn update_viewport_surfaces(&mut self, viewport_output: &ViewportIdMap<ViewportOutput>) {
+        let Some(viewport_shell) = &self.viewport_shell else {
+            return;
+        };
+        let zoom = self.egui_context.zoom_factor();
+
+        self.viewport_surfaces
+            .retain(|surface| viewport_output.contains_key(&surface.id));
+
+        for (id, output) in viewport_output {
+            let Some(ui_cb) = &output.viewport_ui_cb else {
+                continue;
+            };
+
+            match self
+                .viewport_surfaces
+                .iter_mut()
+                .find(|surface| surface.id == *id)
+            {
+                Some(surface) => {
+                    surface.ui_cb = ui_cb.clone();
+                    surface.set_builder(&output.builder, zoom);
+                    surface.handle_commands(&output.commands, zoom);
+                }
+                None => match viewport_shell.create(
+                    *id,
+                    output.builder.clone(),
+                    ui_cb.clone(),
+                    &self.egui_context,
+                    &self.surface_config,
+                ) {
+                    Some(surface) => {
+                        surface.handle_commands(&output.commands, zoom);
+                        self.viewport_surfaces.push(surface);
+                    }
+                    None => error!("Failed to create a surface for viewport {id:?}"),
+                },
+            }
+        }
+    }
+
+    /// Run and render the deferred viewports, each in a pass of its own. They're drawn along with
+    /// the app's surface, so they share its frame callbacks and repaints.
+    fn draw_viewport_surfaces(&mut self, compositor: &CompositorState) {
+        if self.viewport_surfaces.is_empty() {
+            return;
+        }
+
+        let mut surfaces = take(&mut self.viewport_surfaces);
+        let zoom = self.egui_context.zoom_factor();
+        let native_pixels_per_point = self.scale * self.render_scale;
+        let scale = native_pixels_per_point * zoom;
+        let mut root_commands = Vec::new();
+
+        for index in 0..surfaces.len() {
+            let surface = &mut surfaces[index];
+            let Some((width, height)) = *surface.data.size.lock().unwrap() else {
+                continue;
+            };
+            let closed = surface.data.closed.load(Ordering::Relaxed);
+
+            let mut info =
+                self.viewport_info(surface.id, (width, height), surface.builder.title.clone());
+            let focused = info.focused == Some(true);
+            if closed {
+                info.events.push(egui::ViewportEvent::Close);
+            }
+
+            let raw_input = egui::RawInput {
+                viewport_id: surface.id,
+                viewports: std::iter::once((surface.id, info)).collect(),
+                max_texture_side: Some(self.max_texture_side()),
+                focused,
+                time: Some(self.time),
+                screen_rect: Some(Rect::from_min_size(
+                    Pos2::ZERO,
+                    egui::vec2(width as f32, height as f32) / zoom,
+                )),
+                events: take(&mut surface.events),
+                modifiers: self.modifiers,
+                system_theme: self.appearance.theme,
+                ..Default::default()
+            };
+            let ui_cb = surface.ui_cb.clone();
+            let full_output = self.egui_context.run(raw_input, |ctx| ui_cb(ctx));
+            self.handle_output_commands(&full_output.platform_output.commands);
+
+            let device = &self.egui_render_state.device;
+            let queue = &self.egui_render_state.queue;
+            for (id, image_delta) in &full_output.textures_delta.set {
+                self.egui_render_state.renderer.write().update_texture(
+                    device,
+                    queue,
+                    *id,
+                    image_delta,
+                );
+            }
+
+            // A closed surface isn't shown anymore, but egui still needs to hear about it
+            if !closed {
+                let paint_jobs = self.egui_context.tessellate(full_output.shapes, scale);
+                let size = (
+                    (width as f32 * native_pixels_per_point).round() as u32,
+                    (height as f32 * native_pixels_per_point).round() as u32,
+                );
+
+                if !surface.surface_configured
+                    || size != (surface.surface_config.width, surface.surface_config.height)
+                {
+                    (surface.surface_config.width, surface.surface_config.height) = size;
+                    surface
+                        .wgpu_surface
+                        .configure(device, &surface.surface_config);
+                    surface
+                        .viewport
+                        .set_destination(width as i32, height as i32);
+                    surface.surface_configured = true;
+                }
+                update_msaa_texture(
+                    device,
+                    &surface.surface_config,
+                    self.msaa_samples,
+                    &mut surface.msaa_texture,
+                );
+
+                match surface.wgpu_surface.get_current_texture() {
+                    Ok(surface_texture) => {
+                        let view =
+                            surface_texture
+                                .texture
+                                .create_view(&wgpu::TextureViewDescriptor {
+                                    format: Some(surface.surface_config.format),
+                                    ..Default::default()
+                                });
+                        let screen_descriptor = ScreenDescriptor {
+                            size_in_pixels: [size.0, size.1],
+                            pixels_per_point: scale,
+                        };
+
+                        let mut encoder = device.create_command_encoder(&Default::default());
+                        self.egui_render_state.renderer.write().update_buffers(
+                            device,
+                            queue,
+                            &mut encoder,
+                            &paint_jobs,
+                            &screen_descriptor,
+                        );
+                        self.render_pass(
+                            &mut encoder,
+                            &view,
+                            surface.msaa_texture.as_ref(),
+                            &paint_jobs,
+                            &screen_descriptor,
+                        );
+                        queue.submit(Some(encoder.finish()));
+
+                        surface_texture.present();
+                    }
+                    // Try again next frame
+                    Err(_) => surface.surface_configured = false,
+                }
+            }
+
+            for id in &full_output.textures_delta.free {
+                self.egui_render_state.renderer.write().free_texture(id);
+            }
+
+            // Commands for any viewport come out of the last pass
+            for (id, output) in &full_output.viewport_output {
+                if *id == ViewportId::ROOT {
+                    root_commands.extend(output.commands.iter().cloned());
+                } else if let Some(other) = surfaces.iter().find(|other| other.id == *id) {
+                    other.handle_commands(&output.commands, zoom);
+                }
+            }
+        }
+
+        self.viewport_surfaces = surfaces;
+        self.handle_viewport_commands(&root_commands, compositor);
+    }
+
+    fn outputs_changed(&mut self, output_state: &OutputState) {
+        *self.output_info.lock().unwrap() = self
+            .outputs
+            .iter()
+            .filter_map(|output| output_state.info(output))
+            .collect();
+        self.egui_context.request_repaint();
+    }
+
+    fn input_received(&mut self, input_time: Option<Duration>) {
+        self.input_times.extend(input_time);
+        if let Some(timer) = &mut *self.close_timer.lock().unwrap() {
+            timer.deadline = Instant::now() + timer.timeout;
+        }
+    }
+
+    /// Whether the close timer ran out, see [`LayerAppHandle::close_after`].
+    fn close_timer_expired(&self) -> bool {
+        self.close_timer
+            .lock()
+            .unwrap()
+            .is_some_and(|timer| timer.deadline <= Instant::now())
+    }
+
+    /// Wake the event loop up once the close timer may run out, eg. again after input started
+    /// it over.
+    fn arm_close_timer(&self) {
+        if let Some(timer) = *self.close_timer.lock().unwrap() {
+            let remaining = timer.deadline.saturating_duration_since(Instant::now());
+            self.app_surface.lock().unwrap().wake_after(remaining);
+        }
+    }
+
+    /// The size when the compositor leaves it up to the app.
+    fn default_size(&self) -> (u32, u32) {
+        self.default_size
+            .or_else(|| {
+                self.output_info
+                    .lock()
+                    .unwrap()
+                    .first()
+                    .and_then(output_size)
+            })
+            .unwrap_or((DEFAULT_WIDTH, DEFAULT_HEIGHT))
+    }
+
     fn physical_width(&self) -> u32 {
-        (self.width as f32 * self.scale) as u32
+        (self.width as f32 * self.scale * self.render_scale) as u32
     }
 
     fn physical_height(&self) -> u32 {
-        (self.height as f32 * self.scale) as u32
+        (self.height as f32 * self.scale * self.render_scale) as u32
+    }
+
+    /// The size of the buffer, which is rotated along with the output.
+    fn buffer_size(&self) -> (u32, u32) {
+        let (width, height) = (self.physical_width(), self.physical_height());
+        if is_rotated(self.transform) {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// (Re)create the multisampled texture to render into, if needed.
+    fn update_msaa_texture(&mut self) {
+        update_msaa_texture(
+            &self.egui_render_state.device,
+            &self.surface_config,
+            self.msaa_samples,
+            &mut self.msaa_texture,
+        );
+    }
+
+    /// Render the paint jobs into the given texture. With MSAA, this renders into the multisampled
+    /// texture and resolves it into the given one.
+    fn render_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        msaa_texture: Option<&(wgpu::Texture, wgpu::TextureView)>,
+        paint_jobs: &[ClippedPrimitive],
+        screen_descriptor: &ScreenDescriptor,
+    ) {
+        let (view, resolve_target) = match msaa_texture {
+            Some((_, msaa_view)) => (msaa_view, Some(target)),
+            None => (target, None),
+        };
+
+        let mut render_pass = encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        // Only the resolved target is kept, the samples are cleared every frame
+                        store: if resolve_target.is_some() {
+                            wgpu::StoreOp::Discard
+                        } else {
+                            wgpu::StoreOp::Store
+                        },
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            })
+            .forget_lifetime();
+
+        self.egui_render_state.renderer.read().render(
+            &mut render_pass,
+            paint_jobs,
+            screen_descriptor,
+        );
+    }
+
+    /// Create popups for the areas that extend past the surface, and remove the ones that aren't
+    /// needed anymore.
+    fn update_popups(&mut self, zoom: f32) {
+        let (Some(popup_shell), ShellSurface::Layer(layer)) =
+            (&self.popup_shell, &self.shell_surface)
+        else {
+            return;
+        };
+
+        let surface_size = egui::vec2(self.width as f32, self.height as f32);
+        let surface_rect = Rect::from_min_size(Pos2::ZERO, surface_size / zoom);
+        let shadow = self.egui_context.style().visuals.popup_shadow.margin();
+
+        let areas = self
+            .egui_context
+            .memory(|memory| {
+                let areas = memory.areas();
+
+                areas
+                    .visible_layer_ids()
+                    .into_iter()
+                    .filter(|layer| layer.order > egui::Order::Background)
+                    .filter(|layer| areas.is_visible(layer))
+                    .map(|layer| layer.id)
+                    .collect::<Vec<_>>()
+            })
+            .into_iter()
+            .filter_map(|id| {
+                let state = AreaState::load(&self.egui_context, id)?;
+                Some((id, popup::popup_rect(state.rect() + shadow, surface_rect)?))
+            })
+            .collect::<Vec<_>>();
+
+        self.dismissed_popups
+            .retain(|id| areas.iter().any(|(area, _)| area == id));
+        // Popups can't be moved, so they're created again when their area moves
+        self.popups
+            .retain(|popup| areas.contains(&(popup.area, popup.rect)));
+
+        for (area, rect) in areas {
+            if self.dismissed_popups.contains(&area)
+                || self.popups.iter().any(|popup| popup.area == area)
+            {
+                continue;
+            }
+
+            match popup_shell.create(layer, area, rect, zoom, surface_size, &self.surface_config) {
+                Some(popup) => self.popups.push(popup),
+                None => {
+                    error!("Failed to create a popup");
+                    self.dismissed_popups.insert(area);
+                }
+            }
+        }
+    }
+
+    /// Render the popups' parts of the frame. Each popup needs its own submission, as the
+    /// renderer only holds the buffers of one set of paint jobs at a time.
+    fn draw_popups(&mut self, paint_jobs: &[ClippedPrimitive], scale: f32) {
+        let mut popups = take(&mut self.popups);
+        let device = &self.egui_render_state.device;
+        let queue = &self.egui_render_state.queue;
+
+        for popup in popups.iter_mut().filter(|popup| popup.configured) {
+            let size = (popup.rect.size() * scale)
+                .round()
+                .max(egui::Vec2::splat(1.));
+            let size = (size.x as u32, size.y as u32);
+
+            if !popup.surface_configured
+                || size != (popup.surface_config.width, popup.surface_config.height)
+            {
+                (popup.surface_config.width, popup.surface_config.height) = size;
+                popup.wgpu_surface.configure(device, &popup.surface_config);
+                popup.surface_configured = true;
+            }
+            update_msaa_texture(
+                device,
+                &popup.surface_config,
+                self.msaa_samples,
+                &mut popup.msaa_texture,
+            );
+
+            let surface_texture = match popup.wgpu_surface.get_current_texture() {
+                Ok(surface_texture) => surface_texture,
+                Err(_) => {
+                    // Try again next frame
+                    popup.surface_configured = false;
+                    continue;
+                }
+            };
+            let view = surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    format: Some(popup.surface_config.format),
+                    ..Default::default()
+                });
+
+            let paint_jobs = popup::translate(paint_jobs, popup.rect.min);
+            let screen_descriptor = ScreenDescriptor {
+                size_in_pixels: [size.0, size.1],
+                pixels_per_point: scale,
+            };
+
+            let mut encoder = device.create_command_encoder(&Default::default());
+            self.egui_render_state.renderer.write().update_buffers(
+                device,
+                queue,
+                &mut encoder,
+                &paint_jobs,
+                &screen_descriptor,
+            );
+            self.render_pass(
+                &mut encoder,
+                &view,
+                popup.msaa_texture.as_ref(),
+                &paint_jobs,
+                &screen_descriptor,
+            );
+            queue.submit(Some(encoder.finish()));
+
+            surface_texture.present();
+        }
+
+        self.popups = popups;
+    }
+
+    /// Whether the app is moved to another output, or the layer changed but the compositor can't
+    /// change it on the current layer surface.
+    fn needs_new_layer_surface(&self) -> bool {
+        let geometry = self.geometry.lock().unwrap();
+        match &self.shell_surface {
+            ShellSurface::Layer(layer) => {
+                geometry.output.is_some()
+                    || (geometry.new_layer && layer_surface_version(layer) < 2)
+            }
+            _ => false,
+        }
+    }
+
+    fn draw(
+        &mut self,
+        compositor: &CompositorState,
+        qh: &QueueHandle<ContextDelegate>,
+    ) -> Result<(), wgpu::SurfaceError> {
+        self.frame_requested.store(false, Ordering::SeqCst);
+        self.drawing.store(true, Ordering::SeqCst);
+        let result = self.draw_frame(compositor, qh);
+
+        // Nothing was committed, so a frame that was asked for in the meantime needs a commit of
+        // its own
+        if self.drawing.swap(false, Ordering::SeqCst) && self.frame_requested.load(Ordering::SeqCst)
+        {
+            let app_surface = self.app_surface.lock().unwrap();
+            app_surface.waker.wake(&app_surface.surface);
+        }
+        result
+    }
+
+    /// Ask for the next frame along with the commit that follows, if egui asked for one while
+    /// drawing. Returns whether it did.
+    fn request_next_frame(&mut self, qh: &QueueHandle<ContextDelegate>) -> bool {
+        self.drawing.store(false, Ordering::SeqCst);
+        if !self.frame_requested.load(Ordering::SeqCst) || self.frame_pending {
+            return false;
+        }
+        let surface = self.shell_surface.wl_surface();
+        surface.frame(qh, surface.clone());
+        self.frame_pending = true;
+        true
+    }
+
+    /// Commit what was asked for outside of drawing on the event loop's thread, a frame request or
+    /// the settings changed through the handle. Nothing's committed before the first configure,
+    /// which draws the first frame anyway, or while the layer surface is hidden, since that would
+    /// map it again.
+    fn commit_pending(&mut self, qh: &QueueHandle<ContextDelegate>) {
+        let surface = self.shell_surface.wl_surface().clone();
+        if self.unmapped {
+            if !self.hidden.load(Ordering::Relaxed) {
+                // Committing without a buffer works like the initial commit, it's drawn again
+                // once it's configured
+                self.apply_geometry();
+                self.apply_properties();
+                surface.commit();
+            }
+            return;
+        }
+        if self.first_configure || self.powered_off {
+            return;
+        }
+        let mut commit = self.apply_geometry();
+        commit |= self.apply_properties();
+        if self.frame_requested.load(Ordering::SeqCst) && !self.frame_pending {
+            surface.frame(qh, surface.clone());
+            self.frame_pending = true;
+            commit = true;
+        }
+        if commit {
+            surface.commit();
+        }
+    }
+
+    /// Apply the layer surface settings changed through the handle, returns whether any changed.
+    /// The compositor configures the new size if it changed.
+    fn apply_geometry(&mut self) -> bool {
+        let mut geometry = self.geometry.lock().unwrap();
+        let changed = geometry.changed;
+        if let (true, ShellSurface::Layer(layer)) = (changed, &self.shell_surface) {
+            geometry.apply(layer);
+        }
+        geometry.changed = false;
+        changed
+    }
+
+    /// Apply the surface settings changed through the handle, returns whether any changed.
+    fn apply_properties(&mut self) -> bool {
+        let mut properties = self.properties.lock().unwrap();
+        let changed = properties.changed;
+        if changed {
+            properties.apply(&self.app_surface.lock().unwrap());
+            properties.changed = false;
+        }
+        changed
     }
 
-    fn draw(&mut self, compositor: &CompositorState) {
-        self.frame_requested.store(false, Ordering::Relaxed);
+    fn draw_frame(
+        &mut self,
+        compositor: &CompositorState,
+        qh: &QueueHandle<ContextDelegate>,
+    ) -> Result<(), wgpu::SurfaceError> {
+        // Hidden apps aren't updated, and shown ones are drawn once they're configured again
+        if self.unmapped {
+            return Ok(());
+        }
+
+        let frame_start = Instant::now();
+
+        if let Some(input_regions) = self.new_input_regions.lock().unwrap().take() {
+            self.input_regions = input_regions;
+            // Draw the whole frame, the input region is updated and committed along with it
+            self.previous_shapes.clear();
+        }
+
+        // Committed along with this frame
+        let state_changed = self.apply_geometry() | self.apply_properties();
 
         let zoom = self.egui_context.zoom_factor();
-        let scale = self.scale * zoom;
+        let scale = self.scale * self.render_scale * zoom;
+
+        // Pastes go where the keyboard is
+        let pending_events = take(&mut *self.pending_events.lock().unwrap());
+        self.viewport_events(self.keyboard_viewport)
+            .extend(pending_events);
+
+        // Middle click pastes go where the pointer was. The click is passed on along with them, so
+        // the text edit puts its cursor there.
+        let middle_pastes = take(&mut *self.middle_pastes.lock().unwrap());
+        for (pos, text) in middle_pastes {
+            focus_widget_at(&self.egui_context, pos);
+            let modifiers = self.modifiers;
+            let click = |pressed| egui::Event::PointerButton {
+                pos,
+                button: PointerButton::Middle,
+                pressed,
+                modifiers,
+            };
+            self.events.extend([
+                egui::Event::PointerMoved(pos),
+                click(true),
+                click(false),
+                egui::Event::Paste(text),
+            ]);
+        }
+
+        let messages = take(&mut *self.messages.lock().unwrap());
+        for message in messages {
+            message(&mut *self.app);
+        }
+
+        let viewports = std::iter::once((
+            ViewportId::ROOT,
+            self.viewport_info(
+                ViewportId::ROOT,
+                (self.width, self.height),
+                Some(self.app_id.clone()),
+            ),
+        ))
+        .collect();
+
+        let time = self.start.elapsed().as_secs_f64();
+        let input_times = take(&mut self.input_times);
+        if let Some(first) = input_times.first() {
+            let age =
+                wp_presentation_time::clock_time(libc::CLOCK_MONOTONIC).saturating_sub(*first);
+            self.frame_stats.lock().unwrap().input_latency = age;
+        }
+        self.time = time;
+
+        // Animations step by the frame time of the fastest output the surface is on, instead of
+        // egui's default of 60 Hz
+        let refresh_rate = refresh_rate(&self.output_info.lock().unwrap());
+
+        let appearance = self.system_theme.get();
+        if appearance != self.appearance {
+            let previous = replace(&mut self.appearance, appearance);
+            if appearance.theme != previous.theme {
+                self.app.on_system_theme_changed(appearance.theme);
+            }
+            if self.desktop_style {
+                for (theme, mut visuals) in [
+                    (egui::Theme::Dark, egui::Visuals::dark()),
+                    (egui::Theme::Light, egui::Visuals::light()),
+                ] {
+                    appearance.apply(&mut visuals);
+                    self.egui_context.set_visuals_of(theme, visuals);
+                }
+            }
+            self.app.on_appearance_changed(&appearance);
+        }
+        let system_theme = appearance.theme;
+
+        // TODO: input
+        let raw_input = egui::RawInput {
+            viewport_id: ViewportId::ROOT,
+            max_texture_side: Some(self.max_texture_side()),
+            focused: self.keyboard_focus,
+            time: Some(time),
+            predicted_dt: refresh_rate.map_or(1. / 60., |refresh_rate| 1. / refresh_rate),
+            screen_rect: Some(
+                egui::Rect::from_min_size(
+                    egui::pos2(0., 0.),
+                    egui::vec2(self.width as f32 / zoom, self.height as f32 / zoom),
+                ) + self.popup_space,
+            ),
+            events: take(&mut self.events),
+            modifiers: self.modifiers,
+            viewports,
+            system_theme,
+            ..Default::default()
+        };
+
+        let size = self.buffer_size();
+        let transform_changed = self.transform != *self.buffer_transform.lock().unwrap();
+        let reconfigure = !self.surface_configured
+            || transform_changed
+            || size != (self.surface_config.width, self.surface_config.height);
+        if reconfigure {
+            (self.surface_config.width, self.surface_config.height) = size;
+            self.wgpu_surface
+                .configure(&self.egui_render_state.device, &self.surface_config);
+            self.surface_configured = true;
+        }
+        if transform_changed {
+            // Applied along with the next buffer, which has the matching size
+            self.shell_surface
+                .wl_surface()
+                .set_buffer_transform(self.transform);
+            *self.buffer_transform.lock().unwrap() = self.transform;
+        }
+        self.update_msaa_texture();
+
+        let mut frame = LayerFrame {
+            shell_surface: &self.shell_surface,
+            egui_context: &self.egui_context,
+            render_state: &self.egui_render_state,
+            output_info: &self.output_info,
+            exit: &self.exit,
+            hidden: &self.hidden,
+            geometry: &self.geometry,
+            size: (self.width, self.height),
+            scale: self.scale,
+            input_times: &input_times,
+        };
+        let full_output = self.egui_context.run(raw_input, |ctx| {
+            reserve_popup_space(ctx, self.popup_space);
+            self.app.update(ctx, &mut frame);
+        });
+        let update_end = Instant::now();
+
+        self.cursor_icon = full_output.platform_output.cursor_icon;
+
+        self.handle_output_commands(&full_output.platform_output.commands);
+        self.app.on_platform_output(&full_output.platform_output);
+        #[cfg(feature = "accesskit")]
+        if let Some(update) = &full_output.platform_output.accesskit_update {
+            self.accessibility.update(update, (self.width, self.height));
+        }
+        #[cfg(feature = "persistence")]
+        if let Some(memory_storage) = &mut self.memory_storage {
+            if memory_storage.is_due() {
+                self.app.save(&mut memory_storage.storage);
+                memory_storage.save(&self.egui_context);
+            }
+        }
+        if let Some(viewport_output) = full_output.viewport_output.get(&ViewportId::ROOT) {
+            self.handle_viewport_commands(&viewport_output.commands, compositor);
+        }
+        self.update_viewport_surfaces(&full_output.viewport_output);
+
+        if let (true, ShellSurface::Layer(layer)) =
+            (self.hidden.load(Ordering::Relaxed), &self.shell_surface)
+        {
+            let mut renderer = self.egui_render_state.renderer.write();
+            let (device, queue) = (&self.egui_render_state.device, &self.egui_render_state.queue);
+            for (id, image_delta) in &full_output.textures_delta.set {
+                renderer.update_texture(device, queue, *id, image_delta);
+            }
+            for id in &full_output.textures_delta.free {
+                renderer.free_texture(id);
+            }
 
-        let viewports = std::iter::once((
-            ViewportId::ROOT,
-            ViewportInfo {
-                native_pixels_per_point: Some(self.scale),
-                ..Default::default()
-            },
-        ))
-        .collect();
+            // Without a buffer the surface is unmapped, until it's committed again
+            if !self.unmapped {
+                layer.wl_surface().attach(None, 0, 0);
+                layer.commit();
+                self.unmapped = true;
+                self.previous_shapes.clear();
+                // They'd stay on screen otherwise, and are created again once they're shown
+                self.popups.clear();
+                self.viewport_surfaces.clear();
+            }
+            return Ok(());
+        }
 
-        // TODO: input
-        let raw_input = egui::RawInput {
-            time: Some(self.start.elapsed().as_secs_f64()),
-            screen_rect: Some(egui::Rect::from_min_size(
-                egui::pos2(0., 0.),
-                egui::vec2(self.width as f32 / zoom, self.height as f32 / zoom),
-            )),
-            events: take(&mut self.events),
-            modifiers: self.modifiers,
-            viewports,
-            ..Default::default()
-        };
+        // Committed along with this frame, even if it's skipped, eg. for a custom input region
+        // that depends on the app's state
+        let input_region_changed = self.update_input_region(compositor);
 
         // let adapter = &self.egui_render_state.adapter;
         let surface = &self.wgpu_surface;
         let device = &self.egui_render_state.device;
         let queue = &self.egui_render_state.queue;
 
-        let full_output = self.egui_context.run(raw_input, |ctx| self.app.update(ctx));
+        // Texture changes can affect any shape that uses them
+        let changed = reconfigure
+            || !full_output.textures_delta.is_empty()
+            || shapes_changed(&self.previous_shapes, &full_output.shapes);
+        self.previous_shapes = full_output.shapes.clone();
 
-        // TODO: handle full_output.platform_output
+        let offscreen_requests = take(&mut *self.offscreen_requests.lock().unwrap());
 
-        let paint_jobs = self.egui_context.tessellate(full_output.shapes, scale);
+        if !changed && offscreen_requests.is_empty() {
+            // Nothing changed, so skip rendering and presenting entirely. The next frame and the
+            // surface's state still need a commit though.
+            if self.request_next_frame(qh) || state_changed || input_region_changed {
+                self.shell_surface.wl_surface().commit();
+            }
+            self.draw_viewport_surfaces(compositor);
+            return Ok(());
+        }
 
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: TextureFormat::Bgra8Unorm,
-            view_formats: vec![TextureFormat::Bgra8Unorm],
-            alpha_mode: CompositeAlphaMode::PreMultiplied,
-            width: self.physical_width(),
-            height: self.physical_height(),
-            desired_maximum_frame_latency: 2,
-            // Wayland is inherently a mailbox system.
-            present_mode: wgpu::PresentMode::Mailbox,
-        };
+        if let OpaqueRegions::Detect = self.opaque_regions {
+            if let Ok(region) = Region::new(compositor) {
+                for rect in opaque_rects(&full_output.shapes) {
+                    // the region is in surface coordinates, so round inwards
+                    let rect = rect * zoom;
+                    let (min, max) = (rect.min.ceil(), rect.max.floor());
+                    region.add(
+                        min.x as i32,
+                        min.y as i32,
+                        (max.x - min.x) as i32,
+                        (max.y - min.y) as i32,
+                    );
+                }
+                self.shell_surface
+                    .wl_surface()
+                    .set_opaque_region(Some(region.wl_region()));
+            }
+        }
+
+        let mut paint_jobs = self.egui_context.tessellate(full_output.shapes, scale);
+        // Popups aren't rotated, so they get their own copy
+        let popup_jobs = (!self.popups.is_empty()).then(|| paint_jobs.clone());
+
+        // The surface's size in points, to rotate the meshes into the buffer's orientation
+        let points_size =
+            egui::vec2(self.physical_width() as f32 / scale, self.physical_height() as f32 / scale);
+        if self.transform != wl_output::Transform::Normal {
+            for clipped in &mut paint_jobs {
+                clipped.clip_rect = transform_rect(clipped.clip_rect, self.transform, points_size);
+
+                match &mut clipped.primitive {
+                    Primitive::Mesh(mesh) => {
+                        for vertex in &mut mesh.vertices {
+                            vertex.pos = transform_pos(vertex.pos, self.transform, points_size);
+                        }
+                    }
+                    // The callback still draws unrotated, but at least in the right place
+                    Primitive::Callback(callback) => {
+                        callback.rect = transform_rect(callback.rect, self.transform, points_size);
+                    }
+                }
+            }
+        }
+        let tessellation_end = Instant::now();
+
+        for (id, image_delta) in &full_output.textures_delta.set {
+            self.egui_render_state
+                .renderer
+                .write()
+                .update_texture(device, queue, *id, image_delta);
+        }
 
-        surface.configure(device, &surface_config);
+        let surface_config = &self.surface_config;
+
+        let surface_texture = match surface.get_current_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(e) => {
+                for id in &full_output.textures_delta.free {
+                    self.egui_render_state.renderer.write().free_texture(id);
+                }
+
+                match e {
+                    // Happens on GPU resets and some resize races
+                    wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost => {
+                        self.surface_configured = false;
+                    }
+                    wgpu::SurfaceError::Timeout => {}
+                    e => return Err(e),
+                }
 
-        let surface_texture = surface
-            .get_current_texture()
-            .expect("failed to acquire next swapchain texture");
+                // Try again next frame, redrawing everything
+                self.previous_shapes.clear();
+                self.offscreen_requests
+                    .lock()
+                    .unwrap()
+                    .extend(offscreen_requests);
+                self.egui_context.request_repaint();
+                return Ok(());
+            }
+        };
 
         let texture_view = surface_texture
             .texture
@@ -514,15 +4739,8 @@ impl LayerApp {
 
         let mut encoder = device.create_command_encoder(&Default::default());
 
-        for (id, image_delta) in &full_output.textures_delta.set {
-            self.egui_render_state
-                .renderer
-                .write()
-                .update_texture(device, queue, *id, image_delta);
-        }
-
         let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: [self.physical_width(), self.physical_height()],
+            size_in_pixels: [size.0, size.1],
             pixels_per_point: scale,
         };
 
@@ -534,93 +4752,100 @@ impl LayerApp {
             &screen_descriptor,
         );
 
-        {
-            let mut render_pass = encoder
-                .begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: None,
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &texture_view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                })
-                .forget_lifetime();
-
-            self.egui_render_state.renderer.read().render(
-                &mut render_pass,
-                &paint_jobs,
-                &screen_descriptor,
-            );
-        }
+        self.render_pass(
+            &mut encoder,
+            &texture_view,
+            self.msaa_texture.as_ref(),
+            &paint_jobs,
+            &screen_descriptor,
+        );
 
-        for x in &full_output.textures_delta.free {
-            self.egui_render_state.renderer.write().free_texture(x)
-        }
+        let offscreen_textures = offscreen_requests
+            .into_iter()
+            .map(|callback| {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("egui_offscreen_texture"),
+                    size: surface_texture.texture.size(),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: surface_config.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
 
-        match self.input_regions {
-            InputRegions::Full => self.layer.set_input_region(None),
-            InputRegions::WindowsOnly => {
-                if let Ok(region) = Region::new(compositor) {
-                    let layers = self
-                        .egui_context
-                        .memory(|memory| {
-                            let areas = memory.areas();
-
-                            areas
-                                .visible_layer_ids()
-                                .into_iter()
-                                .filter(|layer| layer.order > egui::Order::Background)
-                                .filter(|layer| areas.is_visible(layer))
-                                .map(|layer| layer.id)
-                                .collect::<Vec<_>>()
-                        })
-                        .into_iter()
-                        .filter_map(|id| AreaState::load(&self.egui_context, id));
-
-                    for layer in layers {
-                        if let (Some(pos), Some(size)) = (layer.pivot_pos, layer.size) {
-                            region.add(
-                                pos.x.floor() as i32,
-                                pos.y.floor() as i32,
-                                size.x.ceil() as i32,
-                                size.y.ceil() as i32,
-                            );
-                        }
-                    }
+                let view = texture.create_view(&Default::default());
+                self.render_pass(
+                    &mut encoder,
+                    &view,
+                    self.msaa_texture.as_ref(),
+                    &paint_jobs,
+                    &screen_descriptor,
+                );
 
-                    self.layer.set_input_region(Some(region.wl_region()));
-                }
-            }
-            InputRegions::None => {
-                if let Ok(region) = Region::new(compositor) {
-                    region.add(0, 0, 0, 0);
-                    self.layer.set_input_region(Some(region.wl_region()));
-                }
-            }
-        }
+                (texture, callback)
+            })
+            .collect::<Vec<_>>();
 
         // if self.egui_context.wants_pointer_input() {
-        //     self.layer.set_input_region(None);
+        //     self.shell_surface.set_input_region(None);
         // } else if let Ok(region) = Region::new(compositor) {
         //     region.add(0, 0, 0, 0);
-        //     self.layer.set_input_region(Some(region.wl_region()));
+        //     self.shell_surface.set_input_region(Some(region.wl_region()));
         // }
 
         // Submit the command in the queue to execute
-        queue.submit(Some(encoder.finish()));
+        self.egui_render_state.queue.submit(Some(encoder.finish()));
+        let submit_end = Instant::now();
 
+        for (texture, callback) in offscreen_textures {
+            callback(texture);
+        }
+
+        let wl_surface = self.shell_surface.wl_surface();
+        if let Some(presentation_time) = &self.presentation_time {
+            presentation_time.feedback(wl_surface, frame_start.elapsed());
+        }
+        self.request_next_frame(qh);
         surface_texture.present();
+
+        self.update_popups(zoom);
+        if let Some(popup_jobs) = popup_jobs {
+            self.draw_popups(&popup_jobs, scale);
+        }
+
+        for x in &full_output.textures_delta.free {
+            self.egui_render_state.renderer.write().free_texture(x)
+        }
+
+        self.draw_viewport_surfaces(compositor);
+
+        let present_end = Instant::now();
+        let mut stats = self.frame_stats.lock().unwrap();
+        if let Some(last_present) = self.last_present {
+            let fps = 1. / (present_end - last_present).as_secs_f32();
+            stats.fps = if stats.fps == 0. {
+                fps
+            } else {
+                stats.fps * 0.9 + fps * 0.1
+            };
+        }
+        stats.frames += 1;
+        stats.update = update_end - frame_start;
+        stats.tessellation = tessellation_end - update_end;
+        stats.submit = submit_end - tessellation_end;
+        stats.total = present_end - frame_start;
+        self.last_present = Some(present_end);
+
+        Ok(())
     }
 }
 
 impl CompositorHandler for ContextDelegate {
-    // this is only for integer scaling
+    /// Integer scales, from the surface's preferred buffer scale since `wl_compositor` v6, or
+    /// from the outputs it's on before that.
     fn scale_factor_changed(
         &mut self,
         _conn: &Connection,
@@ -628,10 +4853,9 @@ impl CompositorHandler for ContextDelegate {
         surface: &wl_surface::WlSurface,
         new_factor: i32,
     ) {
-        if let Some(app) = self.apps.get_mut(&surface.id()) {
-            if app.scale.round() != app.scale {
-                // app is already fractionally scaled?
-                // TODO: is this ok?
+        if let Some(app) = self.apps.get(&surface.id()) {
+            if app.fractional_scale.is_some() {
+                // The fractional scale is more precise, and the compositor sends it as well
                 return;
             }
 
@@ -643,51 +4867,85 @@ impl CompositorHandler for ContextDelegate {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
+        // Buffer transforms need version 2 of the surface
+        if surface.version() < 2 {
+            return;
+        }
+
+        if let Some(app) = self.apps.get_mut(&surface.id()) {
+            app.transform = new_transform;
+            app.egui_context.request_repaint();
+        }
     }
 
     fn frame(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
         let mut exit = None;
+        let mut new_layer_surface = false;
 
         if let Some(app) = self.apps.get_mut(&surface.id()) {
+            app.frame_pending = false;
+            let powered_off = self
+                .output_power
+                .as_ref()
+                .is_some_and(|output_power| output_power.all_off(&app.outputs));
+
             if app.exit.load(Ordering::Relaxed) {
                 exit = Some(surface.id());
-            } else {
-                app.draw(&self.compositor);
+            } else if app.needs_new_layer_surface() {
+                new_layer_surface = true;
+            } else if powered_off {
+                // Nobody can see it, so stop rendering. Repaints are dropped until it's resumed.
+                app.powered_off = true;
+            } else if let Err(e) = app.draw(&self.compositor, qh) {
+                self.surface_error = Some(e);
             }
         }
 
         if let Some(id) = exit {
-            if let Some(mut app) = self.apps.remove(&id) {
+            if let Some(mut app) = self.remove_app(&id) {
                 app.app.on_exit();
             }
         }
+        if new_layer_surface {
+            self.replace_layer_surface(qh, &surface.id());
+        }
     }
 
     fn surface_enter(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
+        // The integer scale of a surface on multiple outputs is the largest of theirs, which
+        // `scale_factor_changed` already gets from SCTK
+        if let Some(app) = self.apps.get_mut(&surface.id()) {
+            app.outputs.push(output.clone());
+            app.outputs_changed(&self.output_state);
+        }
     }
 
     fn surface_leave(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
+        if let Some(app) = self.apps.get_mut(&surface.id()) {
+            app.outputs.retain(|other| other != output);
+            app.outputs_changed(&self.output_state);
+        }
     }
 }
 
@@ -699,70 +4957,229 @@ impl OutputHandler for ContextDelegate {
     fn new_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
-        println!("new output");
+        debug!("new output");
+        if let Some(output_power) = &self.output_power {
+            output_power.add_output(output.clone(), qh);
+        }
+        if let Some(info) = self.output_state.info(&output) {
+            for app in self.apps.values_mut().chain(&mut self.lost_apps) {
+                app.app.on_output_added(&info);
+            }
+        }
+        self.output_apps
+            .retain(|output_app| !output_app.handle.exit.load(Ordering::Relaxed));
+        for index in 0..self.output_apps.len() {
+            self.add_output_app(qh, index, output.clone());
+        }
+        self.output_found(qh, &output);
+        self.add_lock_surface(qh, output);
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
     ) {
+        self.output_found(qh, &output);
+
+        for app in self.apps.values_mut() {
+            if app.outputs.contains(&output) {
+                app.outputs_changed(&self.output_state);
+            }
+        }
     }
 
     fn output_destroyed(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
+        output: wl_output::WlOutput,
+    ) {
+        if let Some(output_power) = &self.output_power {
+            output_power.remove_output(&output);
+        }
+        if let Some(info) = self.output_state.info(&output) {
+            for app in self.apps.values_mut().chain(&mut self.lost_apps) {
+                app.app.on_output_removed(&info);
+            }
+        }
+        self.output_lost(&output);
+
+        let mut removed = Vec::new();
+        for output_app in &self.output_apps {
+            output_app
+                .handle
+                .apps
+                .lock()
+                .unwrap()
+                .retain(|(other, handle)| {
+                    if *other != output {
+                        return true;
+                    }
+                    removed.push(handle.surface.lock().unwrap().surface.id());
+                    false
+                });
+        }
+        for id in removed {
+            if let Some(mut app) = self.remove_app(&id) {
+                app.app.on_exit();
+            }
+        }
+
+        if let Some(session_lock) = &mut self.session_lock {
+            if let Some(index) = session_lock
+                .surfaces
+                .iter()
+                .position(|(other, _)| *other == output)
+            {
+                let (_, id) = session_lock.surfaces.remove(index);
+                if let Some(mut app) = self.remove_app(&id) {
+                    app.app.on_exit();
+                }
+            }
+        }
+    }
+}
+
+impl ShmHandler for ContextDelegate {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl SessionLockHandler for ContextDelegate {
+    fn locked(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, session_lock: SessionLock) {
+        // An unlock that was requested before this couldn't be sent yet
+        if let Some(active) = &self.session_lock {
+            if active.handle.unlocked.load(Ordering::Relaxed) {
+                session_lock.unlock();
+            }
+        }
+    }
+
+    fn finished(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _session_lock: SessionLock,
+    ) {
+        debug!("Session lock finished");
+        self.end_session_lock();
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: SessionLockSurface,
+        configure: SessionLockSurfaceConfigure,
+        _serial: u32,
     ) {
+        self.configure_app(surface.wl_surface(), configure.new_size, qh);
     }
 }
 
 impl LayerShellHandler for ContextDelegate {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        // TODO
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        let id = layer.wl_surface().id();
+        let Some(app) = self.apps.get(&id) else {
+            return;
+        };
+
+        if app
+            .target
+            .as_ref()
+            .is_some_and(|target| target.output.is_some())
+        {
+            // Most likely its output is being removed, which may come back
+            self.lose_app(&id);
+        } else if let Some(mut app) = self.remove_app(&id) {
+            debug!("Layer surface was closed by the compositor");
+            app.app.on_exit();
+        }
     }
 
     fn configure(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         layer: &LayerSurface,
         configure: LayerSurfaceConfigure,
         _serial: u32,
     ) {
-        if let Some(app) = self.apps.get_mut(&layer.wl_surface().id()) {
-            if configure.new_size.0 == 0 || configure.new_size.1 == 0 {
-                app.width = DEFAULT_WIDTH;
-                app.height = DEFAULT_HEIGHT;
-            } else {
-                app.width = configure.new_size.0;
-                app.height = configure.new_size.1;
+        self.solid_layers
+            .retain(|_, solid_layer| solid_layer.strong_count() > 0);
+        if let Some(solid_layer) = self
+            .solid_layers
+            .get(&layer.wl_surface().id())
+            .and_then(Weak::upgrade)
+        {
+            solid_layer.configure(configure.new_size.0, configure.new_size.1);
+        }
+
+        // Eg. a hidden app that's moved to another output before it's shown again
+        let id = layer.wl_surface().id();
+        if self
+            .apps
+            .get(&id)
+            .is_some_and(LayerApp::needs_new_layer_surface)
+        {
+            self.replace_layer_surface(qh, &id);
+            return;
+        }
+
+        self.configure_app(layer.wl_surface(), configure.new_size, qh);
+    }
+}
+
+impl PopupHandler for ContextDelegate {
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        popup: &Popup,
+        config: PopupConfigure,
+    ) {
+        if self.apps.contains_key(&popup.wl_surface().id()) {
+            let size = (config.width.max(0) as u32, config.height.max(0) as u32);
+            self.configure_app(popup.wl_surface(), size, qh);
+            return;
+        }
+
+        if let Some((app, _, _)) = find_app(&mut self.apps, popup.wl_surface()) {
+            if let Some(popup) = app
+                .popups
+                .iter_mut()
+                .find(|other| other.wl_surface() == popup.wl_surface())
+            {
+                popup.configured = true;
             }
 
-            // let surface_format = app
-            //     .wgpu_surface
-            //     .get_supported_formats(&app.egui_render_state.adapter)[0];
+            // Render everything again, so the popup gets its first frame
+            app.previous_shapes.clear();
+            app.egui_context.request_repaint();
+        }
+    }
 
-            // let mut surface_config = wgpu::SurfaceConfiguration {
-            //     usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            //     format: TextureFormat::Bgra8UnormSrgb,
-            //     width: app.width,
-            //     height: app.height,
-            //     present_mode: wgpu::PresentMode::Fifo,
-            //     alpha_mode: CompositeAlphaMode::Auto,
-            //     desired_maximum_frame_latency
-            // };
-            // surface.configure(&device, &surface_config);
+    fn done(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, popup: &Popup) {
+        // Child surfaces are gone once they're dismissed
+        if let Some(mut app) = self.remove_app(&popup.wl_surface().id()) {
+            app.app.on_exit();
+            return;
+        }
 
-            // Initiate the first draw.
-            if app.first_configure {
-                app.first_configure = false;
-                app.draw(&self.compositor);
+        if let Some((app, _, _)) = find_app(&mut self.apps, popup.wl_surface()) {
+            if let Some(index) = app
+                .popups
+                .iter()
+                .position(|other| other.wl_surface() == popup.wl_surface())
+            {
+                let popup = app.popups.remove(index);
+                app.dismissed_popups.insert(popup.area);
             }
         }
     }
@@ -773,7 +5190,16 @@ impl SeatHandler for ContextDelegate {
         &mut self.seat_state
     }
 
-    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn new_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(toplevels) = &self.toplevels {
+            if toplevels.seat().is_none() {
+                toplevels.set_seat(Some(seat.clone()));
+            }
+        }
+        if self.idle_notification.is_none() {
+            self.watch_idle(qh, seat);
+        }
+    }
 
     fn new_capability(
         &mut self,
@@ -783,29 +5209,39 @@ impl SeatHandler for ContextDelegate {
         capability: Capability,
     ) {
         if capability == Capability::Keyboard && self.keyboard.is_none() {
-            println!("Set keyboard capability");
+            debug!("Set keyboard capability");
             let keyboard = self
                 .seat_state
                 .get_keyboard(qh, &seat, None)
                 .expect("Failed to create keyboard");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_keyboard(Some(&keyboard), qh);
+            }
             self.keyboard = Some(keyboard);
+            self.init_data_device(qh, &seat);
         }
 
         if capability == Capability::Pointer && self.pointer.is_none() {
-            println!("Set pointer capability");
+            debug!("Set pointer capability");
             let pointer = self
                 .seat_state
                 .get_pointer(qh, &seat)
                 .expect("Failed to create pointer");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_pointer(Some(&pointer), qh);
+            }
             self.pointer = Some(pointer);
         }
 
         if capability == Capability::Touch && self.touch.is_none() {
-            println!("Set touch capability");
+            debug!("Set touch capability");
             let touch = self
                 .seat_state
                 .get_touch(qh, &seat)
                 .expect("Failed to create touch");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_touch(Some(&touch), qh);
+            }
             self.touch = Some(touch);
         }
     }
@@ -813,27 +5249,55 @@ impl SeatHandler for ContextDelegate {
     fn remove_capability(
         &mut self,
         _conn: &Connection,
-        _: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         _: wl_seat::WlSeat,
         capability: Capability,
     ) {
         if capability == Capability::Keyboard && self.keyboard.is_some() {
-            println!("Unset keyboard capability");
+            debug!("Unset keyboard capability");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_keyboard(None, qh);
+            }
             self.keyboard.take().unwrap().release();
         }
 
         if capability == Capability::Pointer && self.pointer.is_some() {
-            println!("Unset pointer capability");
+            debug!("Unset pointer capability");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_pointer(None, qh);
+            }
             self.pointer.take().unwrap().release();
         }
 
         if capability == Capability::Touch && self.touch.is_some() {
-            println!("Unset touch capability");
+            debug!("Unset touch capability");
+            if let Some(input_timestamps) = &mut self.input_timestamps {
+                input_timestamps.set_touch(None, qh);
+            }
             self.touch.take().unwrap().release();
         }
     }
 
-    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+    fn remove_seat(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(toplevels) = &self.toplevels {
+            if toplevels.seat().as_ref() == Some(&seat) {
+                toplevels.set_seat(self.seat_state.seats().find(|other| *other != seat));
+            }
+        }
+        if self
+            .idle_notification
+            .as_ref()
+            .is_some_and(|(idle_seat, _)| *idle_seat == seat)
+        {
+            if let Some((_, notification)) = self.idle_notification.take() {
+                notification.destroy();
+            }
+            // The removed seat is still listed at this point
+            if let Some(seat) = self.seat_state.seats().find(|other| *other != seat) {
+                self.watch_idle(qh, seat);
+            }
+        }
+    }
 }
 
 impl KeyboardHandler for ContextDelegate {
@@ -843,13 +5307,20 @@ impl KeyboardHandler for ContextDelegate {
         _qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
         surface: &wl_surface::WlSurface,
-        _serial: u32,
+        serial: u32,
         _raw: &[u32],
         _keysyms: &[Keysym],
     ) {
-        if let Some(app) = self.apps.get_mut(&surface.id()) {
+        self.last_serial = serial;
+
+        if let Some((app, viewport, _)) = find_app(&mut self.apps, surface) {
             app.keyboard_focus = true;
-            app.events.push(egui::Event::WindowFocused(true));
+            app.keyboard_viewport = viewport;
+            #[cfg(feature = "accesskit")]
+            app.accessibility.set_focused(true);
+            app.viewport_events(viewport)
+                .push(egui::Event::WindowFocused(true));
+            app.app.on_focus_changed(true);
         }
     }
 
@@ -861,9 +5332,13 @@ impl KeyboardHandler for ContextDelegate {
         surface: &wl_surface::WlSurface,
         _serial: u32,
     ) {
-        if let Some(app) = self.apps.get_mut(&surface.id()) {
+        if let Some((app, viewport, _)) = find_app(&mut self.apps, surface) {
             app.keyboard_focus = false;
-            app.events.push(egui::Event::WindowFocused(false));
+            #[cfg(feature = "accesskit")]
+            app.accessibility.set_focused(false);
+            app.viewport_events(viewport)
+                .push(egui::Event::WindowFocused(false));
+            app.app.on_focus_changed(false);
         }
     }
 
@@ -872,9 +5347,10 @@ impl KeyboardHandler for ContextDelegate {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
-        _serial: u32,
+        serial: u32,
         event: KeyEvent,
     ) {
+        self.last_serial = serial;
         self.key_event(event, true);
     }
 
@@ -918,9 +5394,52 @@ impl PointerHandler for ContextDelegate {
         _pointer: &wl_pointer::WlPointer,
         events: &[PointerEvent],
     ) {
+        // Timestamps come before each event, so this is the last event's
+        let input_time = self.input_time();
+
         for PointerEvent { surface, position, kind } in events {
-            if let Some(app) = self.apps.get_mut(&surface.id()) {
-                let pos = egui::pos2(position.0 as f32, position.1 as f32);
+            if let PointerEventKind::Press { serial, .. } = kind {
+                self.last_serial = *serial;
+            }
+
+            if let Some((app, viewport, offset)) = find_app(&mut self.apps, surface) {
+                let pos = egui::pos2(position.0 as f32, position.1 as f32) + offset;
+
+                // Only the app's own surface counts, not its popups
+                if *surface == *app.shell_surface.wl_surface() {
+                    match kind {
+                        PointerEventKind::Enter { .. } => app.app.on_hover_changed(true),
+                        PointerEventKind::Leave { .. } => app.app.on_hover_changed(false),
+                        _ => {}
+                    }
+                }
+
+                // Middle click over a text field pastes the primary selection there, instead of
+                // being passed on. egui doesn't tell us what's under the pointer, but it does show
+                // a text cursor over text edits.
+                match kind {
+                    PointerEventKind::Press { button: BTN_MIDDLE, .. } => {
+                        app.middle_paste =
+                            viewport == ViewportId::ROOT && app.cursor_icon == CursorIcon::Text;
+                        if app.middle_paste {
+                            continue;
+                        }
+                    }
+                    PointerEventKind::Release { button: BTN_MIDDLE, .. } if app.middle_paste => {
+                        app.middle_paste = false;
+                        let middle_pastes = app.middle_pastes.clone();
+                        let egui_context = app.egui_context.clone();
+                        self.clipboard.handle().receive_primary_text(move |text| {
+                            if let Some(text) = text {
+                                middle_pastes.lock().unwrap().push((pos, text));
+                                egui_context.request_repaint();
+                            }
+                        });
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 let ev = match kind {
                     PointerEventKind::Enter { .. } => continue, // egui::Event::PointerMoved(pos),
                     PointerEventKind::Leave { .. } => egui::Event::PointerGone,
@@ -937,7 +5456,6 @@ impl PointerHandler for ContextDelegate {
                     }
                     PointerEventKind::Press { button, .. }
                     | PointerEventKind::Release { button, .. } => {
-                        use smithay_client_toolkit::seat::pointer::*;
                         egui::Event::PointerButton {
                             pos,
                             button: match *button {
@@ -953,7 +5471,8 @@ impl PointerHandler for ContextDelegate {
                     }
                 };
 
-                app.events.push(ev);
+                app.viewport_events(viewport).push(ev);
+                app.input_received(input_time);
                 app.egui_context.request_repaint();
             }
         }
@@ -966,16 +5485,21 @@ impl TouchHandler for ContextDelegate {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _touch: &wl_touch::WlTouch,
-        _serial: u32,
+        serial: u32,
         _time: u32,
         surface: wl_surface::WlSurface,
         id: i32,
         position: (f64, f64),
     ) {
-        if let Some(app) = self.apps.get_mut(&surface.id()) {
-            let pos = egui::pos2(position.0 as f32, position.1 as f32);
+        self.last_serial = serial;
+        let input_time = self.input_time();
+
+        if let Some((app, viewport, offset)) = find_app(&mut self.apps, &surface) {
+            app.input_received(input_time);
+            let pos = egui::pos2(position.0 as f32, position.1 as f32) + offset;
+            let modifiers = app.modifiers;
 
-            app.events.extend_from_slice(&[
+            app.viewport_events(viewport).extend_from_slice(&[
                 egui::Event::PointerGone,
                 egui::Event::Touch {
                     device_id: TouchDeviceId(0),
@@ -988,17 +5512,14 @@ impl TouchHandler for ContextDelegate {
                     pos,
                     button: PointerButton::Primary,
                     pressed: true,
-                    modifiers: app.modifiers,
+                    modifiers,
                 },
             ]);
 
-            self.touches.insert(
-                id,
-                TouchState {
-                    surface_id: surface.id(),
-                    last_position: pos,
-                },
-            );
+            self.touches.insert(id, TouchState {
+                surface: surface.clone(),
+                last_position: pos,
+            });
 
             app.egui_context.request_repaint();
         }
@@ -1013,9 +5534,12 @@ impl TouchHandler for ContextDelegate {
         _time: u32,
         id: i32,
     ) {
+        let input_time = self.input_time();
         if let Some(touch_state) = self.touches.get(&id) {
-            if let Some(app) = self.apps.get_mut(&touch_state.surface_id) {
-                app.events.extend_from_slice(&[
+            if let Some((app, viewport, _)) = find_app(&mut self.apps, &touch_state.surface) {
+                app.input_received(input_time);
+                let modifiers = app.modifiers;
+                app.viewport_events(viewport).extend_from_slice(&[
                     egui::Event::Touch {
                         device_id: TouchDeviceId(0),
                         id: TouchId(id as u64),
@@ -1027,7 +5551,7 @@ impl TouchHandler for ContextDelegate {
                         pos: touch_state.last_position,
                         button: PointerButton::Primary,
                         pressed: false,
-                        modifiers: app.modifiers,
+                        modifiers,
                     },
                     egui::Event::PointerGone,
                 ]);
@@ -1046,10 +5570,12 @@ impl TouchHandler for ContextDelegate {
         id: i32,
         position: (f64, f64),
     ) {
+        let input_time = self.input_time();
         if let Some(touch_state) = self.touches.get_mut(&id) {
-            if let Some(app) = self.apps.get_mut(&touch_state.surface_id) {
-                let pos = egui::pos2(position.0 as f32, position.1 as f32);
-                app.events.extend_from_slice(&[
+            if let Some((app, viewport, offset)) = find_app(&mut self.apps, &touch_state.surface) {
+                app.input_received(input_time);
+                let pos = egui::pos2(position.0 as f32, position.1 as f32) + offset;
+                app.viewport_events(viewport).extend_from_slice(&[
                     egui::Event::Touch {
                         device_id: TouchDeviceId(0),
                         id: TouchId(id as u64),
@@ -1095,8 +5621,8 @@ impl TouchHandler for ContextDelegate {
         let mut emit_pointer_gone = HashSet::new();
 
         for (id, touch_state) in take(&mut self.touches) {
-            if let Some(app) = self.apps.get_mut(&touch_state.surface_id) {
-                app.events.push(egui::Event::Touch {
+            if let Some((app, viewport, _)) = find_app(&mut self.apps, &touch_state.surface) {
+                app.viewport_events(viewport).push(egui::Event::Touch {
                     device_id: TouchDeviceId(0),
                     id: TouchId(id as u64),
                     phase: TouchPhase::Cancel,
@@ -1104,13 +5630,13 @@ impl TouchHandler for ContextDelegate {
                     force: None,
                 });
 
-                emit_pointer_gone.insert(touch_state.surface_id);
+                emit_pointer_gone.insert(touch_state.surface);
             }
         }
 
-        for surface_id in emit_pointer_gone {
-            if let Some(app) = self.apps.get_mut(&surface_id) {
-                app.events.push(egui::Event::PointerGone);
+        for surface in emit_pointer_gone {
+            if let Some((app, viewport, _)) = find_app(&mut self.apps, &surface) {
+                app.viewport_events(viewport).push(egui::Event::PointerGone);
                 app.egui_context.request_repaint();
             }
         }
@@ -1126,6 +5652,10 @@ delegate_touch!(ContextDelegate);
 delegate_pointer!(ContextDelegate);
 
 delegate_layer!(ContextDelegate);
+delegate_subcompositor!(ContextDelegate);
+delegate_session_lock!(ContextDelegate);
+delegate_shm!(ContextDelegate);
+delegate_xdg_popup!(ContextDelegate);
 
 delegate_registry!(ContextDelegate);
 
@@ -1149,6 +5679,80 @@ impl Profiler {
 
 impl Drop for Profiler {
     fn drop(&mut self) {
-        println!("Profiler {}: {:?}", self.name, self.start.elapsed());
+        trace!("Profiler {}: {:?}", self.name, self.start.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(size: Option<(u32, u32)>, anchor: Option<Anchor>) -> LayerGeometry {
+        LayerGeometry { size, anchor, ..Default::default() }
+    }
+
+    #[test]
+    fn validate_defaults() {
+        assert!(matches!(
+            geometry(None, None).validate(),
+            Err(GeometryError::ZeroWidthNotAnchored)
+        ));
+        assert!(matches!(
+            geometry(Some((300, 0)), None).validate(),
+            Err(GeometryError::ZeroHeightNotAnchored)
+        ));
+        assert!(matches!(
+            geometry(None, Some(Anchor::LEFT | Anchor::RIGHT)).validate(),
+            Err(GeometryError::ZeroHeightNotAnchored)
+        ));
+        assert!(geometry(None, Some(Anchor::all())).validate().is_ok());
+        assert!(geometry(Some((300, 80)), None).validate().is_ok());
+    }
+
+    #[test]
+    fn validate_init() {
+        let mut geometry = geometry(None, None);
+        geometry.init.size = Some((0, 80));
+        geometry.init.anchor = Some(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT);
+        assert!(geometry.validate().is_ok());
+
+        // The handle's settings override the ones from on_init
+        geometry.anchor = Some(Anchor::TOP);
+        assert!(matches!(geometry.validate(), Err(GeometryError::ZeroWidthNotAnchored)));
+    }
+
+    #[test]
+    fn validate_margin() {
+        let mut geometry = geometry(Some((300, 80)), Some(Anchor::TOP | Anchor::RIGHT));
+        geometry.margin = Some((-79, 0, 0, 0));
+        assert!(geometry.validate().is_ok());
+        geometry.margin = Some((-80, 0, 0, 0));
+        assert!(matches!(geometry.validate(), Err(GeometryError::MarginHidesSurface)));
+        // Margins on edges it isn't anchored to don't move it
+        geometry.margin = Some((0, 0, -80, -300));
+        assert!(geometry.validate().is_ok());
+    }
+
+    #[test]
+    fn update_keeps_valid_settings() {
+        let mut geometry = geometry(Some((300, 80)), Some(Anchor::TOP));
+        assert!(
+            geometry
+                .update(|geometry| geometry.size = Some((0, 80)))
+                .is_err()
+        );
+        assert_eq!(geometry.size, Some((300, 80)));
+        assert!(
+            geometry
+                .update(
+                    |geometry| geometry.anchor = Some(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT)
+                )
+                .is_ok()
+        );
+        assert!(
+            geometry
+                .update(|geometry| geometry.size = Some((0, 80)))
+                .is_ok()
+        );
     }
 }
@@ -0,0 +1,52 @@
+//! Waking the event loop up for an app, from any thread.
+
+use smithay_client_toolkit::reexports::client::{
+    Connection, Dispatch, Proxy, QueueHandle,
+    protocol::{
+        wl_callback::{self, WlCallback},
+        wl_surface::WlSurface,
+    },
+};
+
+use super::ContextDelegate;
+
+/// Wakes the context's event loop up, to commit what an app asked for on the loop's thread, eg.
+/// a frame request, so it doesn't race drawing. The compositor answers a `wl_display.sync` on the
+/// context's queue, which also ends a blocking dispatch.
+#[derive(Clone)]
+pub(crate) struct LoopWaker {
+    wayland_conn: Connection,
+    queue_handle: QueueHandle<ContextDelegate>,
+}
+
+/// The user data of the sync, the surface of the app to update.
+pub(crate) struct Wake(WlSurface);
+
+impl LoopWaker {
+    pub fn new(wayland_conn: Connection, queue_handle: QueueHandle<ContextDelegate>) -> Self {
+        Self { wayland_conn, queue_handle }
+    }
+
+    /// Update the app of `surface` from the event loop, see [`ContextDelegate::wake_app`].
+    pub fn wake(&self, surface: &WlSurface) {
+        self.wayland_conn
+            .display()
+            .sync(&self.queue_handle, Wake(surface.clone()));
+        let _ = self.wayland_conn.flush();
+    }
+}
+
+impl Dispatch<WlCallback, Wake> for ContextDelegate {
+    fn event(
+        state: &mut Self,
+        _: &WlCallback,
+        event: <WlCallback as Proxy>::Event,
+        wake: &Wake,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event {
+            state.wake_app(qh, &wake.0);
+        }
+    }
+}
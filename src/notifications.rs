@@ -0,0 +1,273 @@
+//! The server side of `org.freedesktop.Notifications`, for notification daemons, with the
+//! `notifications` feature.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::warn;
+use zbus::{
+    blocking::Connection, fdo, interface, object_server::SignalEmitter, zvariant::OwnedValue,
+};
+
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+
+/// Identifies a notification for as long as it's open. Apps use it to replace or close it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NotificationId(u32);
+
+#[derive(Debug)]
+pub struct Notification {
+    /// The same as the id of the notification it replaces, if that's still open.
+    pub id: NotificationId,
+    pub app_name: String,
+    /// An icon name from the icon theme or a `file://` URL, may be empty.
+    pub app_icon: String,
+    pub summary: String,
+    /// May contain the markup allowed by the capabilities, see
+    /// [`NotificationServerOpts::capabilities`].
+    pub body: String,
+    /// Action keys and their labels, eg. `("default", "Open")`, see
+    /// [`NotificationServer::invoke_action`].
+    pub actions: Vec<(String, String)>,
+    pub urgency: Urgency,
+    pub expiry: Expiry,
+    /// All hints, including the ones that are parsed above, eg. `category` or `image-data`.
+    pub hints: HashMap<String, OwnedValue>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+/// When a notification should be closed, see [`NotificationServer::close`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Whenever the daemon sees fit.
+    Default,
+    /// Only once the user dismisses it.
+    Never,
+    After(Duration),
+}
+
+#[derive(Debug)]
+pub enum NotificationEvent {
+    /// A notification to show, or to replace the one with the same id.
+    Notify(Notification),
+    /// The app closed a notification, it should be hidden.
+    Closed(NotificationId),
+}
+
+/// Why a notification was closed, which is passed on to the app that sent it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Expired = 1,
+    /// By the user.
+    Dismissed = 2,
+    /// By the app, which the server handles itself.
+    Closed = 3,
+    Undefined = 4,
+}
+
+/// Receives notifications from apps, see [`NotificationServer::new`].
+pub type NotificationCallback = Box<dyn FnMut(NotificationEvent) + Send>;
+
+/// What the daemon tells apps about itself.
+pub struct NotificationServerOpts {
+    pub name: String,
+    pub vendor: String,
+    pub version: String,
+    /// The optional parts of the specification that the daemon supports, eg. `"body"`,
+    /// `"body-markup"`, `"actions"` or `"icon-static"`.
+    pub capabilities: Vec<String>,
+}
+
+impl Default for NotificationServerOpts {
+    fn default() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME").to_owned(),
+            vendor: String::new(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            capabilities: vec!["body".to_owned(), "actions".to_owned()],
+        }
+    }
+}
+
+/// Handle to the notification daemon's D-Bus service, which runs for as long as a copy of it is
+/// kept.
+#[derive(Clone)]
+pub struct NotificationServer {
+    connection: Connection,
+    open: Arc<Mutex<HashSet<NotificationId>>>,
+}
+
+/// The D-Bus interface, called from zbus's thread.
+struct Notifications {
+    opts: NotificationServerOpts,
+    on_event: Mutex<NotificationCallback>,
+    open: Arc<Mutex<HashSet<NotificationId>>>,
+    next_id: u32,
+}
+
+impl NotificationServer {
+    /// Serve `org.freedesktop.Notifications` on the session bus. Fails if another daemon already
+    /// owns the name.
+    ///
+    /// `on_event` is called from zbus's thread, so it usually passes the events on to the
+    /// context's thread to create or update layer apps, eg. through a channel, or with
+    /// [`LayerAppHandle::send`](crate::LayerAppHandle::send).
+    pub fn new(
+        opts: NotificationServerOpts,
+        on_event: impl FnMut(NotificationEvent) + Send + 'static,
+    ) -> zbus::Result<Self> {
+        let open = Arc::new(Mutex::new(HashSet::new()));
+        let notifications = Notifications {
+            opts,
+            on_event: Mutex::new(Box::new(on_event)),
+            open: open.clone(),
+            next_id: 1,
+        };
+
+        // The name is taken last, so no call comes in before the interface is there
+        let connection = Connection::session()?;
+        connection.object_server().at(PATH, notifications)?;
+        connection.request_name(INTERFACE)?;
+
+        Ok(Self { connection, open })
+    }
+
+    /// Close a notification and tell its app why, eg. once it expired or the user dismissed it.
+    /// Does nothing if it's already closed.
+    pub fn close(&self, id: NotificationId, reason: CloseReason) {
+        if self.open.lock().unwrap().remove(&id) {
+            let body = (id.0, reason as u32);
+            let result = self.connection.emit_signal(
+                None::<()>,
+                PATH,
+                INTERFACE,
+                "NotificationClosed",
+                &body,
+            );
+            if let Err(e) = result {
+                warn!("Failed to tell the app that notification {} closed: {e}", id.0);
+            }
+        }
+    }
+
+    /// Tell the app that the user picked one of the notification's actions. The notification
+    /// stays open, unless it's closed as well.
+    pub fn invoke_action(&self, id: NotificationId, action_key: &str) {
+        if self.open.lock().unwrap().contains(&id) {
+            let body = (id.0, action_key);
+            let result =
+                self.connection
+                    .emit_signal(None::<()>, PATH, INTERFACE, "ActionInvoked", &body);
+            if let Err(e) = result {
+                warn!("Failed to tell the app about action {action_key}: {e}");
+            }
+        }
+    }
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl Notifications {
+    fn get_capabilities(&self) -> Vec<String> {
+        self.opts.capabilities.clone()
+    }
+
+    fn get_server_information(&self) -> (String, String, String, String) {
+        let NotificationServerOpts { name, vendor, version, .. } = &self.opts;
+        (name.clone(), vendor.clone(), version.clone(), "1.2".to_owned())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &mut self,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let mut open = self.open.lock().unwrap();
+        let id = match NotificationId(replaces_id) {
+            id if open.contains(&id) => id,
+            _ => {
+                let id = NotificationId(self.next_id);
+                // 0 means there's nothing to replace
+                self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+                id
+            }
+        };
+        open.insert(id);
+        drop(open);
+
+        let urgency = match hints.get("urgency").map(u8::try_from) {
+            Some(Ok(0)) => Urgency::Low,
+            Some(Ok(2)) => Urgency::Critical,
+            _ => Urgency::Normal,
+        };
+        let expiry = match expire_timeout {
+            0 => Expiry::Never,
+            timeout if timeout > 0 => Expiry::After(Duration::from_millis(timeout as u64)),
+            _ => Expiry::Default,
+        };
+        let actions = actions
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect();
+
+        (self.on_event.lock().unwrap())(NotificationEvent::Notify(Notification {
+            id,
+            app_name,
+            app_icon,
+            summary,
+            body,
+            actions,
+            urgency,
+            expiry,
+            hints,
+        }));
+
+        id.0
+    }
+
+    async fn close_notification(
+        &self,
+        id: u32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        let id = NotificationId(id);
+        if !self.open.lock().unwrap().remove(&id) {
+            return Err(fdo::Error::Failed("No such notification".to_owned()));
+        }
+
+        (self.on_event.lock().unwrap())(NotificationEvent::Closed(id));
+        Self::notification_closed(&emitter, id.0, CloseReason::Closed as u32).await?;
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn notification_closed(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        reason: u32,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_invoked(
+        emitter: &SignalEmitter<'_>,
+        id: u32,
+        action_key: String,
+    ) -> zbus::Result<()>;
+}
@@ -0,0 +1,535 @@
+//! Output configuration through wlr-output-management, eg. for display settings and monitor
+//! layout widgets.
+
+use std::sync::{Arc, Mutex};
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch,
+            event_created_child,
+            globals::{BindError, GlobalList},
+            protocol::wl_output::Transform,
+        },
+        protocols_wlr::output_management::v1::client::{
+            zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
+            zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+            zwlr_output_head_v1::{self, AdaptiveSyncState, ZwlrOutputHeadV1},
+            zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+            zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Identifies a head for as long as it's connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputHeadId(u64);
+
+/// Identifies a mode for as long as its head supports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutputModeId(u64);
+
+/// A connected display, which may or may not be enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputHead {
+    pub id: OutputHeadId,
+    /// The connector name, eg. `DP-1`.
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    pub serial_number: String,
+    /// In millimeters, zero if unknown.
+    pub physical_size: (i32, i32),
+    pub modes: Vec<OutputMode>,
+    pub enabled: bool,
+    /// Only set while the head is enabled, and not when it uses a custom mode.
+    pub current_mode: Option<OutputModeId>,
+    /// In the global compositor space, only meaningful while the head is enabled.
+    pub position: (i32, i32),
+    pub transform: Transform,
+    pub scale: f64,
+    pub adaptive_sync: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputMode {
+    pub id: OutputModeId,
+    /// In pixels.
+    pub size: (i32, i32),
+    /// In mHz, zero if unknown.
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+/// The configuration of a head to apply or test, see [`OutputManagement::apply`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputHeadConfig {
+    pub head: OutputHeadId,
+    pub enabled: bool,
+    /// `None` leaves it up to the compositor.
+    pub mode: Option<OutputModeId>,
+    pub position: (i32, i32),
+    pub transform: Transform,
+    pub scale: f64,
+    /// `None` leaves it as is, which is needed if the compositor doesn't support it.
+    pub adaptive_sync: Option<bool>,
+}
+
+impl From<&OutputHead> for OutputHeadConfig {
+    /// The head's current configuration.
+    fn from(head: &OutputHead) -> Self {
+        Self {
+            head: head.id,
+            enabled: head.enabled,
+            mode: head.current_mode,
+            position: head.position,
+            transform: head.transform,
+            scale: head.scale,
+            adaptive_sync: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputConfigResult {
+    Succeeded,
+    Failed,
+    /// The heads changed before the configuration was applied, it needs to be made again.
+    Cancelled,
+}
+
+/// Receives the heads whenever they change, see [`OutputManagement::on_change`].
+pub type OutputHeadsCallback = Box<dyn FnMut(&[OutputHead]) + Send>;
+
+/// Receives the result of applying or testing a configuration.
+pub type OutputConfigCallback = Box<dyn FnOnce(OutputConfigResult) + Send>;
+
+/// Handle to the output configuration of a [`Context`](crate::Context).
+///
+/// The heads are kept up to date by the event loop. Configurations are sent right away, the
+/// result is passed to the callback from the event loop.
+#[derive(Clone)]
+pub struct OutputManagement {
+    inner: Arc<Mutex<OutputManagementInner>>,
+    manager: ZwlrOutputManagerV1,
+    queue_handle: QueueHandle<ContextDelegate>,
+    wayland_conn: Connection,
+}
+
+#[derive(Default)]
+struct OutputManagementInner {
+    heads: Vec<HeadEntry>,
+    next_id: u64,
+    /// The serial of the last `done` event, configurations are made for it.
+    serial: u32,
+    callbacks: Vec<OutputHeadsCallback>,
+}
+
+struct HeadEntry {
+    handle: ZwlrOutputHeadV1,
+    /// The state as of the last `done` event, `None` before the first.
+    current: Option<OutputHead>,
+    pending: OutputHead,
+    modes: Vec<(ZwlrOutputModeV1, OutputMode)>,
+}
+
+struct ConfigurationData {
+    callback: Mutex<Option<OutputConfigCallback>>,
+}
+
+impl OutputManagement {
+    pub fn heads(&self) -> Vec<OutputHead> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .heads
+            .iter()
+            .filter_map(|entry| entry.current.clone())
+            .collect()
+    }
+
+    /// Call `callback` from the event loop whenever the heads change. Apps are repainted after
+    /// changes as well, so ones that only show [`OutputManagement::heads`] don't need this.
+    pub fn on_change(&self, callback: impl FnMut(&[OutputHead]) + Send + 'static) {
+        self.inner
+            .lock()
+            .unwrap()
+            .callbacks
+            .push(Box::new(callback));
+    }
+
+    /// Apply the configuration. Heads that aren't in `configs` keep their current configuration.
+    pub fn apply(
+        &self,
+        configs: &[OutputHeadConfig],
+        callback: impl FnOnce(OutputConfigResult) + Send + 'static,
+    ) {
+        self.configure(configs, false, Box::new(callback));
+    }
+
+    /// Like [`OutputManagement::apply`], but only checks whether the configuration would work.
+    pub fn test(
+        &self,
+        configs: &[OutputHeadConfig],
+        callback: impl FnOnce(OutputConfigResult) + Send + 'static,
+    ) {
+        self.configure(configs, true, Box::new(callback));
+    }
+
+    fn configure(&self, configs: &[OutputHeadConfig], test: bool, callback: OutputConfigCallback) {
+        let inner = self.inner.lock().unwrap();
+        let configuration = self.manager.create_configuration(
+            inner.serial,
+            &self.queue_handle,
+            ConfigurationData { callback: Mutex::new(Some(callback)) },
+        );
+
+        for entry in &inner.heads {
+            let Some(current) = &entry.current else {
+                continue;
+            };
+            let config = configs
+                .iter()
+                .find(|config| config.head == current.id)
+                .copied()
+                .unwrap_or_else(|| OutputHeadConfig::from(current));
+
+            if !config.enabled {
+                configuration.disable_head(&entry.handle);
+                continue;
+            }
+
+            let head_configuration =
+                configuration.enable_head(&entry.handle, &self.queue_handle, GlobalData);
+            let mode = config.mode.and_then(|mode| {
+                entry
+                    .modes
+                    .iter()
+                    .find(|(_, other)| other.id == mode)
+                    .map(|(handle, _)| handle)
+            });
+            if let Some(mode) = mode {
+                head_configuration.set_mode(mode);
+            }
+            head_configuration.set_position(config.position.0, config.position.1);
+            head_configuration.set_transform(config.transform);
+            head_configuration.set_scale(config.scale);
+            if let Some(adaptive_sync) = config.adaptive_sync {
+                if head_configuration.version() >= 4 {
+                    head_configuration.set_adaptive_sync(match adaptive_sync {
+                        true => AdaptiveSyncState::Enabled,
+                        false => AdaptiveSyncState::Disabled,
+                    });
+                }
+            }
+        }
+
+        if test {
+            configuration.test();
+        } else {
+            configuration.apply();
+        }
+        let _ = self.wayland_conn.flush();
+    }
+}
+
+impl OutputManagementInner {
+    fn head(&mut self, handle: &ZwlrOutputHeadV1) -> Option<&mut HeadEntry> {
+        self.heads.iter_mut().find(|entry| entry.handle == *handle)
+    }
+
+    fn mode(&mut self, handle: &ZwlrOutputModeV1) -> Option<&mut OutputMode> {
+        self.heads
+            .iter_mut()
+            .flat_map(|entry| &mut entry.modes)
+            .find(|(other, _)| other == handle)
+            .map(|(_, mode)| mode)
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+pub(crate) struct OutputManagementState {
+    handle: OutputManagement,
+}
+
+impl OutputManagementState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=4, GlobalData)?;
+        Ok(Self {
+            handle: OutputManagement {
+                inner: Arc::default(),
+                manager,
+                queue_handle: queue_handle.clone(),
+                wayland_conn,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> &OutputManagement {
+        &self.handle
+    }
+}
+
+impl ContextDelegate {
+    fn with_output_heads<T>(
+        &self,
+        f: impl FnOnce(&mut OutputManagementInner) -> Option<T>,
+    ) -> Option<T> {
+        let output_management = self.output_management.as_ref()?;
+        f(&mut output_management.handle.inner.lock().unwrap())
+    }
+
+    /// Apply the pending state of all heads, and tell everyone about it.
+    fn output_heads_done(&mut self, serial: u32) {
+        let Some(output_management) = &self.output_management else {
+            return;
+        };
+
+        let handle = &output_management.handle;
+        let (heads, mut callbacks) = {
+            let mut inner = handle.inner.lock().unwrap();
+            inner.serial = serial;
+            for entry in &mut inner.heads {
+                let mut head = entry.pending.clone();
+                head.modes = entry.modes.iter().map(|(_, mode)| *mode).collect();
+                entry.current = Some(head);
+            }
+
+            let heads = inner
+                .heads
+                .iter()
+                .filter_map(|entry| entry.current.clone())
+                .collect::<Vec<_>>();
+            // The callbacks are taken out, so they can use the handle
+            (heads, std::mem::take(&mut inner.callbacks))
+        };
+
+        for callback in &mut callbacks {
+            callback(&heads);
+        }
+        let mut inner = handle.inner.lock().unwrap();
+        callbacks.append(&mut inner.callbacks);
+        inner.callbacks = callbacks;
+        drop(inner);
+
+        for app in self.apps.values() {
+            app.egui_context.request_repaint();
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, GlobalData, ContextDelegate> for OutputManagementState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ZwlrOutputManagerV1,
+        event: <ZwlrOutputManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => {
+                state.with_output_heads(|inner| {
+                    let id = OutputHeadId(inner.next_id());
+                    inner.heads.push(HeadEntry {
+                        handle: head,
+                        current: None,
+                        pending: OutputHead {
+                            id,
+                            name: String::new(),
+                            description: String::new(),
+                            make: String::new(),
+                            model: String::new(),
+                            serial_number: String::new(),
+                            physical_size: (0, 0),
+                            modes: Vec::new(),
+                            enabled: false,
+                            current_mode: None,
+                            position: (0, 0),
+                            transform: Transform::Normal,
+                            scale: 1.,
+                            adaptive_sync: false,
+                        },
+                        modes: Vec::new(),
+                    });
+                    Some(())
+                });
+            }
+            zwlr_output_manager_v1::Event::Done { serial } => state.output_heads_done(serial),
+            zwlr_output_manager_v1::Event::Finished => {
+                state.with_output_heads(|inner| {
+                    inner.heads.clear();
+                    Some(())
+                });
+                state.output_heads_done(0);
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(ContextDelegate, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, GlobalData),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, GlobalData, ContextDelegate> for OutputManagementState {
+    fn event(
+        state: &mut ContextDelegate,
+        handle: &ZwlrOutputHeadV1,
+        event: <ZwlrOutputHeadV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        state.with_output_heads(|inner| {
+            if let zwlr_output_head_v1::Event::Mode { mode } = event {
+                let id = OutputModeId(inner.next_id());
+                let mode_info = OutputMode {
+                    id,
+                    size: (0, 0),
+                    refresh: 0,
+                    preferred: false,
+                };
+                inner.head(handle)?.modes.push((mode, mode_info));
+                return Some(());
+            }
+            if let zwlr_output_head_v1::Event::CurrentMode { mode } = event {
+                let id = inner.mode(&mode).map(|mode| mode.id);
+                inner.head(handle)?.pending.current_mode = id;
+                return Some(());
+            }
+            if let zwlr_output_head_v1::Event::Finished = event {
+                inner.heads.retain(|entry| entry.handle != *handle);
+                if handle.version() >= 3 {
+                    handle.release();
+                }
+                return Some(());
+            }
+
+            let head = &mut inner.head(handle)?.pending;
+            match event {
+                zwlr_output_head_v1::Event::Name { name } => head.name = name,
+                zwlr_output_head_v1::Event::Description { description } => {
+                    head.description = description;
+                }
+                zwlr_output_head_v1::Event::PhysicalSize { width, height } => {
+                    head.physical_size = (width, height);
+                }
+                zwlr_output_head_v1::Event::Enabled { enabled } => {
+                    head.enabled = enabled != 0;
+                    if !head.enabled {
+                        head.current_mode = None;
+                    }
+                }
+                zwlr_output_head_v1::Event::Position { x, y } => head.position = (x, y),
+                zwlr_output_head_v1::Event::Transform { transform: WEnum::Value(transform) } => {
+                    head.transform = transform;
+                }
+                zwlr_output_head_v1::Event::Scale { scale } => head.scale = scale,
+                zwlr_output_head_v1::Event::Make { make } => head.make = make,
+                zwlr_output_head_v1::Event::Model { model } => head.model = model,
+                zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
+                    head.serial_number = serial_number;
+                }
+                zwlr_output_head_v1::Event::AdaptiveSync { state } => {
+                    head.adaptive_sync = state == WEnum::Value(AdaptiveSyncState::Enabled);
+                }
+                _ => {}
+            }
+            Some(())
+        });
+    }
+
+    event_created_child!(ContextDelegate, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, GlobalData),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, GlobalData, ContextDelegate> for OutputManagementState {
+    fn event(
+        state: &mut ContextDelegate,
+        handle: &ZwlrOutputModeV1,
+        event: <ZwlrOutputModeV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        state.with_output_heads(|inner| {
+            if let zwlr_output_mode_v1::Event::Finished = event {
+                for entry in &mut inner.heads {
+                    entry.modes.retain(|(other, _)| other != handle);
+                }
+                if handle.version() >= 3 {
+                    handle.release();
+                }
+                return Some(());
+            }
+
+            let mode = inner.mode(handle)?;
+            match event {
+                zwlr_output_mode_v1::Event::Size { width, height } => mode.size = (width, height),
+                zwlr_output_mode_v1::Event::Refresh { refresh } => mode.refresh = refresh,
+                zwlr_output_mode_v1::Event::Preferred => mode.preferred = true,
+                _ => {}
+            }
+            Some(())
+        });
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ConfigurationData, ContextDelegate>
+    for OutputManagementState
+{
+    fn event(
+        _: &mut ContextDelegate,
+        configuration: &ZwlrOutputConfigurationV1,
+        event: <ZwlrOutputConfigurationV1 as Proxy>::Event,
+        data: &ConfigurationData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        let result = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => OutputConfigResult::Succeeded,
+            zwlr_output_configuration_v1::Event::Failed => OutputConfigResult::Failed,
+            zwlr_output_configuration_v1::Event::Cancelled => OutputConfigResult::Cancelled,
+            _ => return,
+        };
+
+        configuration.destroy();
+        if let Some(callback) = data.callback.lock().unwrap().take() {
+            callback(result);
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, GlobalData, ContextDelegate>
+    for OutputManagementState
+{
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwlrOutputConfigurationHeadV1,
+        _: <ZwlrOutputConfigurationHeadV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrOutputManagerV1: GlobalData] => OutputManagementState);
+delegate_dispatch!(ContextDelegate: [ZwlrOutputHeadV1: GlobalData] => OutputManagementState);
+delegate_dispatch!(ContextDelegate: [ZwlrOutputModeV1: GlobalData] => OutputManagementState);
+delegate_dispatch!(ContextDelegate: [ZwlrOutputConfigurationV1: ConfigurationData] => OutputManagementState);
+delegate_dispatch!(ContextDelegate: [ZwlrOutputConfigurationHeadV1: GlobalData] => OutputManagementState);
@@ -0,0 +1,168 @@
+//! Monitor power through wlr-output-power-management.
+
+use std::sync::{Arc, Mutex};
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_output::WlOutput,
+        },
+        protocols_wlr::output_power_management::v1::client::{
+            zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1,
+            zwlr_output_power_v1::{self, Mode, ZwlrOutputPowerV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Handle to the power of a [`Context`](crate::Context)'s outputs.
+#[derive(Clone)]
+pub struct OutputPower {
+    outputs: Arc<Mutex<Vec<OutputPowerEntry>>>,
+    wayland_conn: Connection,
+}
+
+struct OutputPowerEntry {
+    output: WlOutput,
+    power: ZwlrOutputPowerV1,
+    /// `None` until the compositor sent the mode.
+    on: Option<bool>,
+}
+
+impl OutputPower {
+    /// Whether the output is powered on, or `None` if it's unknown.
+    pub fn is_on(&self, output: &WlOutput) -> Option<bool> {
+        let outputs = self.outputs.lock().unwrap();
+        outputs
+            .iter()
+            .find(|entry| entry.output == *output)
+            .and_then(|entry| entry.on)
+    }
+
+    /// Turn the output on or off. Surfaces on outputs that are off aren't rendered.
+    pub fn set_on(&self, output: &WlOutput, on: bool) {
+        let outputs = self.outputs.lock().unwrap();
+        if let Some(entry) = outputs.iter().find(|entry| entry.output == *output) {
+            entry.power.set_mode(match on {
+                true => Mode::On,
+                false => Mode::Off,
+            });
+            let _ = self.wayland_conn.flush();
+        }
+    }
+}
+
+pub(crate) struct OutputPowerState {
+    manager: ZwlrOutputPowerManagerV1,
+    handle: OutputPower,
+}
+
+impl OutputPowerState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self {
+            manager,
+            handle: OutputPower {
+                outputs: Arc::default(),
+                wayland_conn,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> &OutputPower {
+        &self.handle
+    }
+
+    pub fn add_output(&self, output: WlOutput, queue_handle: &QueueHandle<ContextDelegate>) {
+        let power = self
+            .manager
+            .get_output_power(&output, queue_handle, GlobalData);
+        let mut outputs = self.handle.outputs.lock().unwrap();
+        outputs.push(OutputPowerEntry { output, power, on: None });
+    }
+
+    pub fn remove_output(&self, output: &WlOutput) {
+        let mut outputs = self.handle.outputs.lock().unwrap();
+        outputs.retain(|entry| {
+            if entry.output != *output {
+                return true;
+            }
+            entry.power.destroy();
+            false
+        });
+    }
+
+    /// Whether the surface is only on outputs that are off. Surfaces that aren't on any output
+    /// don't count, since they're not shown anyway.
+    pub fn all_off(&self, outputs: &[WlOutput]) -> bool {
+        !outputs.is_empty()
+            && outputs
+                .iter()
+                .all(|output| self.handle.is_on(output) == Some(false))
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, GlobalData, ContextDelegate> for OutputPowerState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwlrOutputPowerManagerV1,
+        _: <ZwlrOutputPowerManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, GlobalData, ContextDelegate> for OutputPowerState {
+    fn event(
+        state: &mut ContextDelegate,
+        power: &ZwlrOutputPowerV1,
+        event: <ZwlrOutputPowerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        let Some(output_power) = &state.output_power else {
+            return;
+        };
+
+        let output = {
+            let mut outputs = output_power.handle.outputs.lock().unwrap();
+            let Some(index) = outputs.iter().position(|entry| entry.power == *power) else {
+                return;
+            };
+
+            match event {
+                zwlr_output_power_v1::Event::Mode { mode } => {
+                    let on = mode == WEnum::Value(Mode::On);
+                    outputs[index].on = Some(on);
+                    on.then(|| outputs[index].output.clone())
+                }
+                zwlr_output_power_v1::Event::Failed => {
+                    // The output doesn't support power management (anymore)
+                    let entry = outputs.remove(index);
+                    entry.power.destroy();
+                    Some(entry.output)
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(output) = output {
+            state.output_powered_on(qh, &output);
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrOutputPowerManagerV1: GlobalData] => OutputPowerState);
+delegate_dispatch!(ContextDelegate: [ZwlrOutputPowerV1: GlobalData] => OutputPowerState);
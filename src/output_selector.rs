@@ -0,0 +1,84 @@
+//! Picking the output a layer surface is shown on.
+
+use smithay_client_toolkit::output::OutputInfo;
+
+/// Picks the output to show a layer on, see [`LayerAppOpts::output`](crate::LayerAppOpts::output).
+pub struct OutputSelector(Selector);
+
+enum Selector {
+    Name(String),
+    DescriptionContains(String),
+    Primary,
+    Largest,
+    InternalPanel,
+    Custom(Box<dyn Fn(&OutputInfo) -> bool + Send + Sync>),
+}
+
+impl OutputSelector {
+    /// Pick the outputs that `matches` returns `true` for.
+    pub fn new(matches: impl Fn(&OutputInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self(Selector::Custom(Box::new(matches)))
+    }
+
+    /// The output with the given connector name, eg. `DP-1`.
+    pub fn by_name(name: impl Into<String>) -> Self {
+        Self(Selector::Name(name.into()))
+    }
+
+    /// The outputs whose description contains `text`, eg. the monitor's model.
+    pub fn by_description_contains(text: impl Into<String>) -> Self {
+        Self(Selector::DescriptionContains(text.into()))
+    }
+
+    /// The output at the top left of the layout. Wayland has no primary output, but that's where
+    /// compositors put the first one.
+    pub fn primary() -> Self {
+        Self(Selector::Primary)
+    }
+
+    /// The output with the largest logical size.
+    pub fn largest() -> Self {
+        Self(Selector::Largest)
+    }
+
+    /// A laptop's built-in screen, going by the connector name.
+    pub fn internal_panel() -> Self {
+        Self(Selector::InternalPanel)
+    }
+
+    /// Whether `info` is picked, among all the current `outputs`.
+    pub(crate) fn matches(&self, info: &OutputInfo, outputs: &[OutputInfo]) -> bool {
+        match &self.0 {
+            Selector::Name(name) => info.name.as_ref() == Some(name),
+            Selector::DescriptionContains(text) => info
+                .description
+                .as_ref()
+                .is_some_and(|description| description.contains(text.as_str())),
+            Selector::Primary => position(info) == (0, 0),
+            Selector::Largest => outputs.iter().all(|other| area(other) <= area(info)),
+            Selector::InternalPanel => info.name.as_ref().is_some_and(|name| {
+                ["eDP", "LVDS", "DSI"]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+            }),
+            Selector::Custom(matches) => matches(info),
+        }
+    }
+}
+
+fn position(info: &OutputInfo) -> (i32, i32) {
+    info.logical_position.unwrap_or(info.location)
+}
+
+fn area(info: &OutputInfo) -> i64 {
+    let (width, height) = info
+        .logical_size
+        .or_else(|| {
+            info.modes
+                .iter()
+                .find(|mode| mode.current)
+                .map(|mode| mode.dimensions)
+        })
+        .unwrap_or_default();
+    width as i64 * height as i64
+}
@@ -0,0 +1,117 @@
+//! Keeping egui's memory and the app's own values between runs, with the `persistence` feature,
+//! see [`LayerAppOpts::persist_memory`](crate::LayerAppOpts::persist_memory).
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use smithay_client_toolkit::output::OutputInfo;
+
+/// How often the memory is saved while the app is running, like eframe does.
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Values that an app keeps between runs, eg. its settings. They're passed to the app when it's
+/// created, see [`CreationContext::storage`](crate::CreationContext::storage), and it updates
+/// them in [`App::save`](crate::App::save).
+#[derive(Debug, Default)]
+pub struct Storage {
+    values: BTreeMap<String, String>,
+}
+
+impl Storage {
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn set_string(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+}
+
+/// The files an app's egui memory is saved to, eg. the positions of its windows, along with its
+/// [`Storage`].
+pub(crate) struct MemoryStorage {
+    path: PathBuf,
+    pub storage: Storage,
+    last_save: Instant,
+}
+
+impl MemoryStorage {
+    /// The memory goes to `$XDG_DATA_HOME/<app id>/<output name>.ron`, so the same app can be laid
+    /// out differently on each output, and the storage next to it to `<output name>.storage.ron`.
+    /// `None` if there's no data directory.
+    pub fn new(app_id: &str, output: Option<&OutputInfo>) -> Option<Self> {
+        let data_dir = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".local/share")))?;
+        let output_name = output
+            .and_then(|output| output.name.as_deref())
+            .unwrap_or("default");
+
+        Some(Self {
+            path: data_dir.join(app_id).join(format!("{output_name}.ron")),
+            storage: Storage::default(),
+            last_save: Instant::now(),
+        })
+    }
+
+    /// Restore the memory and storage of the last run, if there's any.
+    pub fn load(&mut self, egui_context: &egui::Context) {
+        if let Ok(contents) = fs::read_to_string(&self.path) {
+            match ron::from_str::<egui::Memory>(&contents) {
+                Ok(memory) => egui_context.memory_mut(|current| *current = memory),
+                Err(e) => warn!("Failed to read egui memory from {}: {e}", self.path.display()),
+            }
+        }
+
+        let storage_path = self.storage_path();
+        if let Ok(contents) = fs::read_to_string(&storage_path) {
+            match ron::from_str(&contents) {
+                Ok(values) => self.storage.values = values,
+                Err(e) => warn!("Failed to read storage from {}: {e}", storage_path.display()),
+            }
+        }
+    }
+
+    /// Whether the memory and storage haven't been saved for a while.
+    pub fn is_due(&self) -> bool {
+        self.last_save.elapsed() >= SAVE_INTERVAL
+    }
+
+    pub fn save(&mut self, egui_context: &egui::Context) {
+        self.last_save = Instant::now();
+
+        let result = egui_context.memory(ron::to_string);
+        if let Err(e) = write(&self.path, result) {
+            warn!("Failed to save egui memory to {}: {e}", self.path.display());
+        }
+
+        let storage_path = self.storage_path();
+        if let Err(e) = write(&storage_path, ron::to_string(&self.storage.values)) {
+            warn!("Failed to save storage to {}: {e}", storage_path.display());
+        }
+    }
+
+    fn storage_path(&self) -> PathBuf {
+        self.path.with_extension("storage.ron")
+    }
+}
+
+fn write(path: &Path, contents: ron::Result<String>) -> Result<(), String> {
+    let contents = contents.map_err(|e| e.to_string())?;
+    // Written next to it first, so a crash while saving doesn't lose the old one
+    let temp_path = path.with_extension("tmp");
+    fs::create_dir_all(path.parent().unwrap())
+        .and_then(|()| fs::write(&temp_path, contents))
+        .and_then(|()| fs::rename(&temp_path, path))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,236 @@
+//! Popup surfaces, so egui's menus and tooltips can extend past the layer surface.
+
+use std::ptr::NonNull;
+
+use egui::{ClippedPrimitive, Id, Pos2, Rect, Vec2, epaint::Primitive};
+use smithay_client_toolkit::{
+    compositor::{CompositorState, Surface},
+    error::GlobalError,
+    globals::{GlobalData, ProvidesBoundGlobal},
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::{
+            wp::viewporter::client::wp_viewport::WpViewport,
+            xdg::shell::client::{
+                xdg_positioner::{Anchor, ConstraintAdjustment, Gravity},
+                xdg_wm_base::{self, XdgWmBase},
+            },
+        },
+    },
+    shell::{
+        wlr_layer::LayerSurface,
+        xdg::{XdgPositioner, popup::Popup},
+    },
+};
+use wgpu::rwh::{RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle};
+
+use super::{ContextDelegate, wp_viewporter::ViewporterState};
+
+/// The xdg shell, which is used for popups and the window fallback. SCTK's version also needs to
+/// handle its own windows.
+#[derive(Debug, Clone)]
+pub struct XdgShellState {
+    xdg_wm_base: XdgWmBase,
+}
+
+impl XdgShellState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let xdg_wm_base = globals.bind(queue_handle, 1..=6, GlobalData)?;
+        Ok(Self { xdg_wm_base })
+    }
+
+    pub fn xdg_wm_base(&self) -> &XdgWmBase {
+        &self.xdg_wm_base
+    }
+}
+
+impl ProvidesBoundGlobal<XdgWmBase, 5> for XdgShellState {
+    fn bound_global(&self) -> Result<XdgWmBase, GlobalError> {
+        Ok(self.xdg_wm_base.clone())
+    }
+}
+
+impl ProvidesBoundGlobal<XdgWmBase, 6> for XdgShellState {
+    fn bound_global(&self) -> Result<XdgWmBase, GlobalError> {
+        Ok(self.xdg_wm_base.clone())
+    }
+}
+
+/// Everything that's needed to create popups while drawing a frame.
+#[derive(Clone)]
+pub(crate) struct PopupShell {
+    pub xdg_shell: XdgShellState,
+    pub compositor: CompositorState,
+    pub viewporter: ViewporterState,
+    pub wgpu_instance: wgpu::Instance,
+    pub wayland_conn: Connection,
+    pub queue_handle: QueueHandle<ContextDelegate>,
+}
+
+pub(crate) struct PopupSurface {
+    pub wgpu_surface: wgpu::Surface<'static>,
+    pub surface_config: wgpu::SurfaceConfiguration,
+    /// Whether the surface is configured with the current size.
+    pub surface_configured: bool,
+    pub msaa_texture: Option<(wgpu::Texture, wgpu::TextureView)>,
+    pub viewport: WpViewport,
+    popup: Popup, // drop after wgpu_surface
+    /// The egui area that's shown on the popup.
+    pub area: Id,
+    /// The part of the area that's shown, in points relative to the layer surface.
+    pub rect: Rect,
+    /// Set once the compositor sent the first configure, before that nothing can be attached.
+    pub configured: bool,
+}
+
+impl PopupShell {
+    /// Create a popup for showing `rect` of the layer surface's egui content, right where it is.
+    /// `surface_size` is the layer surface's size.
+    pub fn create(
+        &self,
+        layer: &LayerSurface,
+        area: Id,
+        rect: Rect,
+        zoom: f32,
+        surface_size: Vec2,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> Option<PopupSurface> {
+        let positioner = XdgPositioner::new(&self.xdg_shell).ok()?;
+
+        // The anchor needs to be on the layer surface, the rest of the way is an offset
+        let min = rect.min * zoom;
+        let anchor = min
+            .clamp(Pos2::ZERO, (surface_size - Vec2::splat(1.)).max(Vec2::ZERO).to_pos2())
+            .floor();
+        let offset = (min - anchor).round();
+        let size = (rect.size() * zoom).round().max(Vec2::splat(1.));
+
+        positioner.set_size(size.x as i32, size.y as i32);
+        positioner.set_anchor_rect(anchor.x as i32, anchor.y as i32, 1, 1);
+        positioner.set_anchor(Anchor::TopLeft);
+        positioner.set_gravity(Gravity::BottomRight);
+        positioner.set_offset(offset.x as i32, offset.y as i32);
+        // egui already placed the area, and input on the popup maps back to `rect`, so the
+        // compositor mustn't move it
+        positioner.set_constraint_adjustment(ConstraintAdjustment::None);
+
+        let surface = Surface::new(&self.compositor, &self.queue_handle).ok()?;
+        let popup =
+            Popup::from_surface(None, &positioner, &self.queue_handle, surface, &self.xdg_shell)
+                .ok()?;
+        layer.get_popup(popup.xdg_popup());
+        // Like layer surfaces, the first commit has no buffer and waits for a configure
+        popup.wl_surface().commit();
+
+        let viewport = self
+            .viewporter
+            .get_viewport(popup.wl_surface(), &self.queue_handle);
+        viewport.set_destination(size.x as i32, size.y as i32);
+
+        let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+            NonNull::new(self.wayland_conn.backend().display_ptr() as *mut _)?,
+        ));
+        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(NonNull::new(
+            popup.wl_surface().id().as_ptr() as *mut _,
+        )?));
+
+        let wgpu_surface = unsafe {
+            self.wgpu_instance
+                .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+                    raw_display_handle,
+                    raw_window_handle,
+                })
+                .ok()?
+        };
+
+        Some(PopupSurface {
+            wgpu_surface,
+            surface_config: surface_config.clone(),
+            surface_configured: false,
+            msaa_texture: None,
+            viewport,
+            popup,
+            area,
+            rect,
+            configured: false,
+        })
+    }
+}
+
+impl PopupSurface {
+    pub fn wl_surface(&self) -> &WlSurface {
+        self.popup.wl_surface()
+    }
+}
+
+impl Drop for PopupSurface {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+    }
+}
+
+/// The part of an area that needs a popup, or `None` if it fits on the surface. If the area only
+/// sticks out on one side, the part that's on the surface is left out so it isn't drawn twice.
+pub(crate) fn popup_rect(area: Rect, surface: Rect) -> Option<Rect> {
+    if surface.contains_rect(area) {
+        return None;
+    }
+
+    let mut rect = area;
+    let inside_x = area.min.x >= surface.min.x && area.max.x <= surface.max.x;
+    let inside_y = area.min.y >= surface.min.y && area.max.y <= surface.max.y;
+
+    if inside_x && area.min.y >= surface.min.y {
+        rect.min.y = rect.min.y.max(surface.max.y);
+    } else if inside_x && area.max.y <= surface.max.y {
+        rect.max.y = rect.max.y.min(surface.min.y);
+    } else if inside_y && area.min.x >= surface.min.x {
+        rect.min.x = rect.min.x.max(surface.max.x);
+    } else if inside_y && area.max.x <= surface.max.x {
+        rect.max.x = rect.max.x.min(surface.min.x);
+    }
+
+    Some(rect)
+}
+
+/// Move the paint jobs so `origin` ends up in the top left corner.
+pub(crate) fn translate(paint_jobs: &[ClippedPrimitive], origin: Pos2) -> Vec<ClippedPrimitive> {
+    let offset = -origin.to_vec2();
+
+    paint_jobs
+        .iter()
+        .cloned()
+        .map(|mut clipped| {
+            clipped.clip_rect = clipped.clip_rect.translate(offset);
+            match &mut clipped.primitive {
+                Primitive::Mesh(mesh) => mesh.translate(offset),
+                Primitive::Callback(callback) => callback.rect = callback.rect.translate(offset),
+            }
+            clipped
+        })
+        .collect()
+}
+
+impl Dispatch<XdgWmBase, GlobalData, ContextDelegate> for XdgShellState {
+    fn event(
+        _: &mut ContextDelegate,
+        xdg_wm_base: &XdgWmBase,
+        event: <XdgWmBase as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            xdg_wm_base.pong(serial);
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [XdgWmBase: GlobalData] => XdgShellState);
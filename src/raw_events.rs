@@ -0,0 +1,77 @@
+//! Objects of protocols this crate doesn't support, created by apps on the context's connection.
+
+use std::sync::Arc;
+
+use smithay_client_toolkit::reexports::client::{
+    Connection, Dispatch, Proxy, QueueHandle, backend::ObjectData,
+};
+
+use super::ContextDelegate;
+
+/// User data for the objects of a protocol this crate doesn't support, which gets their events
+/// on the context's queue, eg. for downstream crates that add protocols. Bind the protocol's
+/// globals with [`Context::bind_global`](crate::Context::bind_global), and pass it to requests
+/// that create objects along with [`Context::queue_handle`](crate::Context::queue_handle).
+///
+/// Events are dispatched with a shared reference, so any state the handler keeps needs interior
+/// mutability. [`RawEvents`] is a handler that passes the events to a callback.
+pub trait ProtocolHandler<I: Proxy>: Send + Sync + 'static {
+    /// `qh` can be used to create objects in response, eg. to bind a per-output object once an
+    /// output is announced.
+    fn event(
+        &self,
+        proxy: &I,
+        event: I::Event,
+        conn: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    );
+
+    /// The user data of an object that is created by an event, for protocols that have such
+    /// events, eg. a new toplevel handle announced by a manager. Usually made with
+    /// [`QueueHandle::make_data`].
+    fn event_created_child(opcode: u16, _qh: &QueueHandle<ContextDelegate>) -> Arc<dyn ObjectData> {
+        panic!("Missing event_created_child for event opcode {opcode} of {}", I::interface().name);
+    }
+}
+
+/// User data that passes the events of an object to a callback, for protocols this crate doesn't
+/// support, see [`ProtocolHandler`].
+pub struct RawEvents<I: Proxy> {
+    callback: RawEventCallback<I>,
+}
+
+type RawEventCallback<I> = Box<dyn Fn(&I, <I as Proxy>::Event) + Send + Sync>;
+
+impl<I: Proxy> RawEvents<I> {
+    pub fn new(callback: impl Fn(&I, I::Event) + Send + Sync + 'static) -> Self {
+        Self { callback: Box::new(callback) }
+    }
+
+    /// For objects without events, or whose events don't matter.
+    pub fn ignore() -> Self {
+        Self::new(|_, _| {})
+    }
+}
+
+impl<I: Proxy + 'static> ProtocolHandler<I> for RawEvents<I> {
+    fn event(&self, proxy: &I, event: I::Event, _: &Connection, _: &QueueHandle<ContextDelegate>) {
+        (self.callback)(proxy, event);
+    }
+}
+
+impl<I: Proxy + 'static, H: ProtocolHandler<I>> Dispatch<I, H> for ContextDelegate {
+    fn event(
+        _: &mut Self,
+        proxy: &I,
+        event: I::Event,
+        data: &H,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        data.event(proxy, event, conn, qh);
+    }
+
+    fn event_created_child(opcode: u16, qh: &QueueHandle<Self>) -> Arc<dyn ObjectData> {
+        H::event_created_child(opcode, qh)
+    }
+}
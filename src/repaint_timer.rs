@@ -0,0 +1,103 @@
+//! Repaints that egui asks for after a delay, eg. for animations or tooltips.
+
+use std::{
+    mem::take,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Requests a frame for an app's surface.
+pub(crate) type RequestFrame = Arc<dyn Fn() + Send + Sync>;
+
+/// Requests the frames of all of a context's apps once their repaint is due, from a single
+/// thread. The thread stops when this is dropped.
+pub(crate) struct RepaintTimer {
+    shared: Arc<Shared>,
+}
+
+/// Schedules repaints on the [`RepaintTimer`], kept by the apps' repaint callbacks and surfaces.
+#[derive(Clone)]
+pub(crate) struct RepaintScheduler {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Wakes the thread when an earlier repaint is scheduled, or it should stop.
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// When each app's next repaint is due.
+    repaints: Vec<(Instant, RequestFrame)>,
+    stopped: bool,
+}
+
+impl RepaintTimer {
+    pub fn new() -> Self {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+        std::thread::spawn(move || thread_shared.run());
+        Self { shared }
+    }
+
+    pub fn scheduler(&self) -> RepaintScheduler {
+        RepaintScheduler { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for RepaintTimer {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.condvar.notify_one();
+    }
+}
+
+impl RepaintScheduler {
+    /// Call `request_frame` after `delay`, unless it's already due to be called before that.
+    pub fn request_frame_after(&self, delay: Duration, request_frame: &RequestFrame) {
+        let at = Instant::now() + delay;
+        let mut state = self.shared.state.lock().unwrap();
+        match state
+            .repaints
+            .iter_mut()
+            .find(|(_, other)| Arc::ptr_eq(other, request_frame))
+        {
+            Some((other_at, _)) if *other_at <= at => return,
+            Some((other_at, _)) => *other_at = at,
+            None => state.repaints.push((at, request_frame.clone())),
+        }
+        drop(state);
+        self.shared.condvar.notify_one();
+    }
+}
+
+impl Shared {
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let now = Instant::now();
+            let (due, pending): (Vec<_>, Vec<_>) = take(&mut state.repaints)
+                .into_iter()
+                .partition(|(at, _)| *at <= now);
+            state.repaints = pending;
+
+            if !due.is_empty() {
+                // Requesting a frame flushes the connection, which shouldn't hold up scheduling
+                drop(state);
+                for (_, request_frame) in due {
+                    request_frame();
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state = match state.repaints.iter().map(|(at, _)| *at).min() {
+                Some(at) => self.condvar.wait_timeout(state, at - now).unwrap().0,
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+    }
+}
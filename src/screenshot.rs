@@ -0,0 +1,152 @@
+//! Reading rendered frames back from the GPU.
+
+use egui::{Color32, ColorImage, Pos2, Rgba, Vec2};
+use log::{error, warn};
+use wayland_client::protocol::wl_output::Transform;
+use wgpu::TextureFormat;
+
+use super::{is_rotated, transform_pos};
+
+/// Copy the texture into a buffer and read it back into an image, which is passed to `callback`.
+/// This waits for the GPU to finish the copy. The texture's contents are rotated back from the
+/// given buffer transform.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: wgpu::Texture,
+    transform: Transform,
+    callback: impl FnOnce(ColorImage) + Send + 'static,
+) {
+    let format = texture.format();
+    let Some(bytes_per_pixel) = format.block_copy_size(None) else {
+        warn!("Can't read back texture format {format:?}");
+        return;
+    };
+
+    let size = texture.size();
+    let unpadded_bytes_per_row = size.width * bytes_per_pixel;
+    let bytes_per_row = unpadded_bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("egui_screenshot_buffer"),
+        size: (bytes_per_row * size.height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    let submission = queue.submit(Some(encoder.finish()));
+
+    let slice_buffer = buffer.clone();
+    buffer
+        .slice(..)
+        .map_async(wgpu::MapMode::Read, move |result| {
+            if let Err(e) = result {
+                error!("Failed to read back screenshot: {e}");
+                return;
+            }
+
+            let pixels = {
+                let data = slice_buffer.slice(..).get_mapped_range();
+                data.chunks_exact(bytes_per_row as usize)
+                    .flat_map(|row| {
+                        row[..unpadded_bytes_per_row as usize]
+                            .chunks_exact(bytes_per_pixel as usize)
+                            .map(|pixel| decode_pixel(format, pixel))
+                    })
+                    .collect()
+            };
+            slice_buffer.unmap();
+
+            callback(untransform(
+                ColorImage {
+                    size: [size.width as usize, size.height as usize],
+                    pixels,
+                },
+                transform,
+            ));
+        });
+
+    // The map callback runs from here, once the copy is done
+    device.poll(wgpu::Maintain::wait_for(submission));
+}
+
+/// Rotate an image in a buffer's orientation back to the surface's.
+fn untransform(image: ColorImage, transform: Transform) -> ColorImage {
+    if transform == Transform::Normal {
+        return image;
+    }
+
+    let [buffer_width, buffer_height] = image.size;
+    let [width, height] = if is_rotated(transform) {
+        [buffer_height, buffer_width]
+    } else {
+        [buffer_width, buffer_height]
+    };
+    let size = Vec2::new(width as f32, height as f32);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            // sample at the pixel's center, so rounding can't go out of bounds
+            let center = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let pos = transform_pos(center, transform, size);
+            pixels.push(image.pixels[pos.y as usize * buffer_width + pos.x as usize]);
+        }
+    }
+
+    ColorImage { size: [width, height], pixels }
+}
+
+/// Convert a pixel of one of the surface formats into a color. The surface contains premultiplied
+/// alpha, like egui's colors. Floating point surfaces are in linear space, and get encoded to
+/// sRGB.
+fn decode_pixel(format: TextureFormat, pixel: &[u8]) -> Color32 {
+    match format {
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => {
+            Color32::from_rgba_premultiplied(pixel[2], pixel[1], pixel[0], pixel[3])
+        }
+        TextureFormat::Rgb10a2Unorm => {
+            let bits = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            let channel = |shift: u32| ((bits >> shift) & 0x3ff) as f32 / 1023.;
+            let alpha = (bits >> 30) as f32 / 3.;
+            to_color32(channel(0), channel(10), channel(20), alpha)
+        }
+        TextureFormat::Rgba16Float => {
+            let channel = |i: usize| f16_to_f32(u16::from_le_bytes([pixel[i], pixel[i + 1]]));
+            Rgba::from_rgba_premultiplied(channel(0), channel(2), channel(4), channel(6)).into()
+        }
+        // Rgba8Unorm and Rgba8UnormSrgb, other formats aren't used for surfaces
+        _ => Color32::from_rgba_premultiplied(pixel[0], pixel[1], pixel[2], pixel[3]),
+    }
+}
+
+fn to_color32(r: f32, g: f32, b: f32, a: f32) -> Color32 {
+    let channel = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+    Color32::from_rgba_premultiplied(channel(r), channel(g), channel(b), channel(a))
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let fraction = (bits & 0x3ff) as f32 / 1024.;
+
+    match exponent {
+        0 => sign * fraction * 2f32.powi(-14),
+        0x1f if fraction == 0. => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1. + fraction) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,82 @@
+//! Creating layer apps from within other apps.
+
+use std::{cell::RefCell, rc::Rc};
+
+use super::{App, ChildSurfaceOpts, Context, CreationContext, Error, LayerAppHandle, LayerAppOpts};
+
+/// Creates layer apps from inside another app's `update`, eg. a bar opening a calendar, where the
+/// [`Context`] isn't available. They're created once the current dispatch is done. Can be cloned,
+/// but like the context it stays on the context's thread, other threads use a [`LayerAppHandle`].
+#[derive(Clone, Default)]
+pub struct Spawner {
+    requests: Rc<RefCell<Vec<SpawnRequest>>>,
+}
+
+type SpawnRequest = Box<dyn FnOnce(&mut Context)>;
+
+impl Spawner {
+    /// Queue a layer app, `on_created` gets its handle once it's created, or the error if it
+    /// couldn't be.
+    pub fn new_layer_app(
+        &self,
+        app: Box<dyn App>,
+        opts: LayerAppOpts<'static>,
+        on_created: impl FnOnce(Result<LayerAppHandle, Error>) + 'static,
+    ) {
+        self.requests.borrow_mut().push(Box::new(move |context| {
+            on_created(context.new_layer_app(app, opts));
+        }));
+    }
+
+    /// Like [`Spawner::new_layer_app`], see [`Context::new_layer_app_with`].
+    pub fn new_layer_app_with(
+        &self,
+        new_app: impl FnOnce(&CreationContext<'_>) -> Box<dyn App> + 'static,
+        opts: LayerAppOpts<'static>,
+        on_created: impl FnOnce(Result<LayerAppHandle, Error>) + 'static,
+    ) {
+        self.requests.borrow_mut().push(Box::new(move |context| {
+            on_created(context.new_layer_app_with(new_app, opts));
+        }));
+    }
+
+    /// Queue a child surface, see [`Context::new_child_surface`]. `on_created` isn't called if it
+    /// couldn't be created.
+    pub fn new_child_surface(
+        &self,
+        parent: &LayerAppHandle,
+        app: Box<dyn App>,
+        opts: ChildSurfaceOpts,
+        on_created: impl FnOnce(LayerAppHandle) + 'static,
+    ) {
+        let parent = parent.wl_surface();
+        self.requests.borrow_mut().push(Box::new(move |context| {
+            let qh = context.event_queue.handle();
+            if let Some(handle) = context.delegate.new_child_surface(&qh, &parent, app, opts) {
+                on_created(handle);
+            }
+        }));
+    }
+}
+
+impl Context {
+    /// A handle to create layer apps from inside other apps.
+    pub fn spawner(&self) -> Spawner {
+        self.spawner.clone()
+    }
+
+    /// Create the apps that were queued with the spawner.
+    pub(crate) fn spawn_queued_apps(&mut self) {
+        // Apps may queue more apps while they're created
+        loop {
+            let requests = self.spawner.requests.take();
+            if requests.is_empty() {
+                break;
+            }
+
+            for request in requests {
+                request(self);
+            }
+        }
+    }
+}
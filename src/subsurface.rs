@@ -0,0 +1,220 @@
+//! Subsurfaces, for embedding content that isn't rendered by egui.
+
+use std::{
+    ptr::NonNull,
+    sync::{Arc, Mutex, Weak},
+};
+
+use egui::Pos2;
+use smithay_client_toolkit::reexports::client::{
+    Connection, Dispatch, Proxy, QueueHandle,
+    protocol::{
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::{self, WlSubsurface},
+        wl_surface::WlSurface,
+    },
+};
+use wgpu::rwh::{
+    DisplayHandle, HandleError, HasDisplayHandle, HasWindowHandle, RawDisplayHandle,
+    RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle, WindowHandle,
+};
+
+use super::ContextDelegate;
+
+/// A surface that's placed relative to a layer app's surface, eg. for a video player or camera
+/// feed that's rendered by another library.
+///
+/// The subsurface starts out synchronized, which means that changes to it, including its
+/// position, are applied along with the app's next frame. It stays with the app when the app gets
+/// a new surface, eg. on another output, along with its position, stacking and sync mode. After
+/// [`Context::reconnect`](crate::Context::reconnect) it doesn't show anymore though, as it
+/// belongs to the old connection.
+///
+/// The subsurface owns its `wl_surface`, which is destroyed when it's dropped, so anything that
+/// renders to it has to be gone by then. A wgpu surface that's created from it borrows it, or
+/// takes it along when it's passed in an `Arc`, so it can't outlive it. Other libraries that are
+/// given [`Subsurface::wl_surface`] have to be stopped before it's dropped.
+pub struct Subsurface {
+    surface: WlSurface,
+    role: Arc<Mutex<Role>>,
+    egui_context: egui::Context,
+    wayland_conn: Connection,
+}
+
+/// The subsurface on the app's current surface, which is created again for a new one.
+struct Role {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+    parent: WlSurface,
+    position: (i32, i32),
+    stacking: Option<Stacking>,
+    sync: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Stacking {
+    Above,
+    Below,
+}
+
+impl Role {
+    /// Set the subsurface up again after it was created for a new parent.
+    fn apply(&self) {
+        let (x, y) = self.position;
+        self.subsurface.set_position(x, y);
+        match self.stacking {
+            Some(Stacking::Above) => self.subsurface.place_above(&self.parent),
+            Some(Stacking::Below) => self.subsurface.place_below(&self.parent),
+            None => {}
+        }
+        if !self.sync {
+            self.subsurface.set_desync();
+        }
+    }
+}
+
+/// The subsurfaces of an app, to move them to its new surface.
+#[derive(Clone, Default)]
+pub(crate) struct Subsurfaces(Arc<Mutex<Vec<Weak<Mutex<Role>>>>>);
+
+impl Subsurfaces {
+    /// Create a subsurface on `parent`, the app's current surface.
+    pub fn create(
+        &self,
+        subcompositor: &WlSubcompositor,
+        surface: WlSurface,
+        parent: WlSurface,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        egui_context: egui::Context,
+        wayland_conn: Connection,
+    ) -> Subsurface {
+        let subsurface = subcompositor.get_subsurface(&surface, &parent, queue_handle, ());
+        let role = Arc::new(Mutex::new(Role {
+            surface: surface.clone(),
+            subsurface,
+            parent,
+            position: (0, 0),
+            stacking: None,
+            sync: true,
+        }));
+
+        let mut subsurfaces = self.0.lock().unwrap();
+        subsurfaces.retain(|role| role.strong_count() > 0);
+        subsurfaces.push(Arc::downgrade(&role));
+
+        Subsurface {
+            surface,
+            role,
+            egui_context,
+            wayland_conn,
+        }
+    }
+
+    /// Move the subsurfaces that are still around to the app's new surface. A surface can get a
+    /// new subsurface once its old one is destroyed, so whatever renders to it keeps going.
+    pub fn reparent(
+        &self,
+        subcompositor: &WlSubcompositor,
+        parent: &WlSurface,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) {
+        let mut subsurfaces = self.0.lock().unwrap();
+        subsurfaces.retain(|role| role.strong_count() > 0);
+        for role in subsurfaces.iter().filter_map(Weak::upgrade) {
+            let mut role = role.lock().unwrap();
+            role.subsurface.destroy();
+            role.subsurface = subcompositor.get_subsurface(&role.surface, parent, queue_handle, ());
+            role.parent = parent.clone();
+            role.apply();
+        }
+    }
+
+    /// Forget the subsurfaces, once they belong to a previous connection.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Subsurface {
+    /// The surface to render to. It's only valid for as long as the subsurface is kept, see
+    /// [`Subsurface`].
+    pub fn wl_surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Move the subsurface's top left corner to the given position, in points. Call this with a
+    /// widget's position from [`App::update`](crate::App::update) to keep the subsurface in place
+    /// with it.
+    pub fn set_position(&self, position: Pos2) {
+        let position = position * self.egui_context.zoom_factor();
+        let mut role = self.role.lock().unwrap();
+        role.position = (position.x.round() as i32, position.y.round() as i32);
+        let (x, y) = role.position;
+        role.subsurface.set_position(x, y);
+    }
+
+    /// Show the subsurface above the app's egui content.
+    pub fn place_above_parent(&self) {
+        let mut role = self.role.lock().unwrap();
+        role.stacking = Some(Stacking::Above);
+        role.subsurface.place_above(&role.parent);
+    }
+
+    /// Show the subsurface beneath the app's egui content, which needs to be transparent there.
+    pub fn place_below_parent(&self) {
+        let mut role = self.role.lock().unwrap();
+        role.stacking = Some(Stacking::Below);
+        role.subsurface.place_below(&role.parent);
+    }
+
+    /// Whether changes to the subsurface wait for the app's next frame. Content that updates on
+    /// its own, like video, should usually be desynchronized.
+    pub fn set_sync(&self, sync: bool) {
+        let mut role = self.role.lock().unwrap();
+        role.sync = sync;
+        if sync {
+            role.subsurface.set_sync();
+        } else {
+            role.subsurface.set_desync();
+        }
+    }
+}
+
+impl HasWindowHandle for Subsurface {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        let surface =
+            NonNull::new(self.surface.id().as_ptr() as *mut _).ok_or(HandleError::Unavailable)?;
+        let raw = RawWindowHandle::Wayland(WaylandWindowHandle::new(surface));
+        Ok(unsafe { WindowHandle::borrow_raw(raw) })
+    }
+}
+
+impl HasDisplayHandle for Subsurface {
+    fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+        let display = NonNull::new(self.wayland_conn.backend().display_ptr() as *mut _)
+            .ok_or(HandleError::Unavailable)?;
+        let raw = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(display));
+        Ok(unsafe { DisplayHandle::borrow_raw(raw) })
+    }
+}
+
+impl Drop for Subsurface {
+    fn drop(&mut self) {
+        // The surface was created for the subsurface, nothing else owns it
+        self.role.lock().unwrap().subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+impl Dispatch<WlSubsurface, ()> for ContextDelegate {
+    fn event(
+        _: &mut Self,
+        _: &WlSubsurface,
+        _: wl_subsurface::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        // wl_subsurface has no events
+    }
+}
@@ -0,0 +1,166 @@
+//! Following the desktop's dark/light theme, accent color and contrast, with the `portal`
+//! feature.
+
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use egui::{Color32, Theme, Visuals};
+
+/// How the desktop looks, as set in its settings. Without the `portal` feature nothing is known.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Appearance {
+    /// `None` if the desktop has no preference, or it isn't known.
+    pub theme: Option<Theme>,
+    /// The color the user picked for selections and highlights, if any.
+    pub accent_color: Option<Color32>,
+    /// Whether the user asked for higher contrast.
+    pub high_contrast: bool,
+}
+
+impl Appearance {
+    /// Apply the accent color and contrast to egui's visuals, see
+    /// [`LayerAppOpts::desktop_style`](crate::LayerAppOpts::desktop_style).
+    pub fn apply(&self, visuals: &mut Visuals) {
+        if let Some(accent_color) = self.accent_color {
+            visuals.selection.bg_fill = accent_color;
+            visuals.hyperlink_color = accent_color;
+        }
+
+        if self.high_contrast {
+            let text_color = match visuals.dark_mode {
+                true => Color32::WHITE,
+                false => Color32::BLACK,
+            };
+            visuals.override_text_color = Some(text_color);
+            visuals.selection.stroke.color = text_color;
+            for widget in [
+                &mut visuals.widgets.noninteractive,
+                &mut visuals.widgets.inactive,
+                &mut visuals.widgets.hovered,
+                &mut visuals.widgets.active,
+                &mut visuals.widgets.open,
+            ] {
+                widget.fg_stroke.color = text_color;
+                widget.bg_stroke.color = text_color;
+                widget.bg_stroke.width = widget.bg_stroke.width.max(1.);
+            }
+        }
+    }
+}
+
+/// The desktop's appearance, shared with all apps.
+#[derive(Clone, Default)]
+pub(crate) struct SystemTheme {
+    appearance: Arc<Mutex<Appearance>>,
+    /// The apps' egui contexts and whether they're closed, to repaint them when the theme changes.
+    contexts: Arc<Mutex<Vec<AppContext>>>,
+}
+
+type AppContext = (egui::Context, Arc<AtomicBool>);
+
+impl SystemTheme {
+    /// Start following the settings portal's appearance settings. Without the `portal` feature,
+    /// they stay unknown.
+    pub fn new() -> Self {
+        let system_theme = Self::default();
+
+        #[cfg(feature = "portal")]
+        {
+            let system_theme = system_theme.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = system_theme.watch_portal() {
+                    log::warn!("Failed to follow the settings portal's appearance: {e}");
+                }
+            });
+        }
+
+        system_theme
+    }
+
+    pub fn get(&self) -> Appearance {
+        *self.appearance.lock().unwrap()
+    }
+
+    /// Repaint an app when the theme changes, until it's closed.
+    pub fn add_app(&self, egui_context: &egui::Context, closed: Arc<AtomicBool>) {
+        self.contexts
+            .lock()
+            .unwrap()
+            .push((egui_context.clone(), closed));
+    }
+
+    #[cfg_attr(not(feature = "portal"), allow(dead_code))]
+    fn update(&self, update: impl FnOnce(&mut Appearance)) {
+        update(&mut self.appearance.lock().unwrap());
+
+        let mut contexts = self.contexts.lock().unwrap();
+        contexts.retain(|(_, closed)| !closed.load(Ordering::Relaxed));
+        for (egui_context, _) in contexts.iter() {
+            egui_context.request_repaint();
+        }
+    }
+
+    #[cfg(feature = "portal")]
+    fn watch_portal(&self) -> zbus::Result<()> {
+        use zbus::{
+            blocking::{Connection, Proxy},
+            zvariant::OwnedValue,
+        };
+
+        let connection = Connection::session()?;
+        let settings = Proxy::new(
+            &connection,
+            "org.freedesktop.portal.Desktop",
+            "/org/freedesktop/portal/desktop",
+            "org.freedesktop.portal.Settings",
+        )?;
+
+        // Subscribe first, so no change is missed before the current values are read
+        let changes = settings
+            .receive_signal_with_args("SettingChanged", &[(0, "org.freedesktop.appearance")])?;
+        for key in ["color-scheme", "accent-color", "contrast"] {
+            // Older portals don't know every key
+            let value = settings.call("ReadOne", &("org.freedesktop.appearance", key));
+            if let Ok(value) = value {
+                self.update(|appearance| set(appearance, key, &value));
+            }
+        }
+
+        for message in changes {
+            let (_, key, value): (String, String, OwnedValue) = message.body().deserialize()?;
+            self.update(|appearance| set(appearance, &key, &value));
+        }
+
+        Ok(())
+    }
+}
+
+/// Apply a setting of the portal's `org.freedesktop.appearance` namespace.
+#[cfg(feature = "portal")]
+fn set(appearance: &mut Appearance, key: &str, value: &zbus::zvariant::OwnedValue) {
+    match key {
+        // 1 is dark, 2 is light and 0 is no preference
+        "color-scheme" => {
+            appearance.theme = match u32::try_from(value) {
+                Ok(1) => Some(Theme::Dark),
+                Ok(2) => Some(Theme::Light),
+                _ => None,
+            };
+        }
+        // Red, green and blue from 0 to 1, anything out of range means there's no accent color
+        "accent-color" => {
+            appearance.accent_color = <(f64, f64, f64)>::try_from(&**value)
+                .ok()
+                .filter(|(r, g, b)| [r, g, b].iter().all(|c| (0. ..=1.).contains(*c)))
+                .map(|(r, g, b)| {
+                    let [r, g, b] = [r, g, b].map(|c| (c * 255.).round() as u8);
+                    Color32::from_rgb(r, g, b)
+                });
+        }
+        // 1 is high contrast, 0 is no preference
+        "contrast" => appearance.high_contrast = u32::try_from(value) == Ok(1),
+        _ => {}
+    }
+}
@@ -0,0 +1,518 @@
+//! Other clients' windows through wlr-foreign-toplevel-management, eg. for taskbars and window
+//! switchers. Compositors that only have ext-foreign-toplevel-list give a read-only list.
+
+use std::{
+    mem::take,
+    sync::{Arc, Mutex},
+};
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle,
+            backend::ObjectId,
+            delegate_dispatch, event_created_child,
+            globals::{BindError, GlobalList},
+            protocol::{wl_output::WlOutput, wl_seat::WlSeat},
+        },
+        protocols::ext::foreign_toplevel_list::v1::client::{
+            ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+            ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+        },
+        protocols_wlr::foreign_toplevel::v1::client::{
+            zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+            zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Identifies a toplevel for as long as it's open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ToplevelId(u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toplevel {
+    pub id: ToplevelId,
+    pub title: String,
+    pub app_id: String,
+    /// The outputs the toplevel is shown on. Always empty with a read-only list.
+    pub outputs: Vec<WlOutput>,
+    /// Always the default with a read-only list.
+    pub state: ToplevelState,
+    /// The toplevel this one belongs to, eg. for dialogs.
+    pub parent: Option<ToplevelId>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToplevelState {
+    pub maximized: bool,
+    pub minimized: bool,
+    /// Whether the toplevel has keyboard focus.
+    pub activated: bool,
+    pub fullscreen: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToplevelEvent {
+    Added(Toplevel),
+    Changed(Toplevel),
+    Closed(ToplevelId),
+}
+
+/// Receives changes to the toplevel list, see [`Toplevels::on_change`].
+pub type ToplevelCallback = Box<dyn FnMut(&ToplevelEvent) + Send>;
+
+/// Handle to the toplevel list of a [`Context`](crate::Context).
+///
+/// The list is kept up to date by the event loop. Requests are sent right away, and do nothing if
+/// the list is read-only.
+#[derive(Clone)]
+pub struct Toplevels {
+    inner: Arc<Mutex<ToplevelsInner>>,
+    /// Whether toplevels can be activated, closed, etc.
+    controllable: bool,
+    wayland_conn: Connection,
+}
+
+#[derive(Default)]
+struct ToplevelsInner {
+    toplevels: Vec<ToplevelEntry>,
+    next_id: u64,
+    /// The seat that toplevels are activated on.
+    seat: Option<WlSeat>,
+    callbacks: Vec<ToplevelCallback>,
+}
+
+/// A toplevel's protocol object.
+enum ToplevelHandle {
+    Wlr(ZwlrForeignToplevelHandleV1),
+    /// Read-only.
+    Ext(ExtForeignToplevelHandleV1),
+}
+
+impl ToplevelHandle {
+    fn id(&self) -> ObjectId {
+        match self {
+            ToplevelHandle::Wlr(handle) => handle.id(),
+            ToplevelHandle::Ext(handle) => handle.id(),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            ToplevelHandle::Wlr(handle) => handle.destroy(),
+            ToplevelHandle::Ext(handle) => handle.destroy(),
+        }
+    }
+}
+
+struct ToplevelEntry {
+    handle: ToplevelHandle,
+    /// The state as of the last `done` event.
+    current: Toplevel,
+    pending: Toplevel,
+    /// Whether the toplevel was announced, which happens after the first `done` event.
+    added: bool,
+}
+
+impl Toplevels {
+    /// All toplevels, in the order they were opened.
+    pub fn list(&self) -> Vec<Toplevel> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .toplevels
+            .iter()
+            .filter(|entry| entry.added)
+            .map(|entry| entry.current.clone())
+            .collect()
+    }
+
+    pub fn get(&self, id: ToplevelId) -> Option<Toplevel> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .toplevels
+            .iter()
+            .find(|entry| entry.added && entry.current.id == id)
+            .map(|entry| entry.current.clone())
+    }
+
+    /// Call `callback` from the event loop whenever a toplevel is added, changed or closed. Apps
+    /// are repainted after changes as well, so ones that only show [`Toplevels::list`] don't need
+    /// this.
+    pub fn on_change(&self, callback: impl FnMut(&ToplevelEvent) + Send + 'static) {
+        self.inner
+            .lock()
+            .unwrap()
+            .callbacks
+            .push(Box::new(callback));
+    }
+
+    /// Whether toplevels can be activated, closed, etc. This is `false` if the compositor only
+    /// provides a read-only list.
+    pub fn can_control(&self) -> bool {
+        self.controllable
+    }
+
+    /// Focus the toplevel, and unminimize it.
+    pub fn activate(&self, id: ToplevelId) {
+        self.request(id, |handle, seat| {
+            if let Some(seat) = seat {
+                handle.activate(seat);
+            }
+        });
+    }
+
+    /// Ask the toplevel to close. It's up to the client whether it does, eg. after asking to save.
+    pub fn close(&self, id: ToplevelId) {
+        self.request(id, |handle, _| handle.close());
+    }
+
+    pub fn set_minimized(&self, id: ToplevelId, minimized: bool) {
+        self.request(id, |handle, _| match minimized {
+            true => handle.set_minimized(),
+            false => handle.unset_minimized(),
+        });
+    }
+
+    pub fn set_maximized(&self, id: ToplevelId, maximized: bool) {
+        self.request(id, |handle, _| match maximized {
+            true => handle.set_maximized(),
+            false => handle.unset_maximized(),
+        });
+    }
+
+    /// Make the toplevel fullscreen, on the output the compositor picks. This does nothing if the
+    /// compositor doesn't support it.
+    pub fn set_fullscreen(&self, id: ToplevelId, fullscreen: bool) {
+        self.request(id, |handle, _| {
+            if handle.version() < 2 {
+                return;
+            }
+            match fullscreen {
+                true => handle.set_fullscreen(None),
+                false => handle.unset_fullscreen(),
+            }
+        });
+    }
+
+    fn request(
+        &self,
+        id: ToplevelId,
+        request: impl FnOnce(&ZwlrForeignToplevelHandleV1, Option<&WlSeat>),
+    ) {
+        let inner = self.inner.lock().unwrap();
+        let handle = inner
+            .toplevels
+            .iter()
+            .find(|entry| entry.current.id == id)
+            .map(|entry| &entry.handle);
+
+        if let Some(ToplevelHandle::Wlr(handle)) = handle {
+            request(handle, inner.seat.as_ref());
+            let _ = self.wayland_conn.flush();
+        }
+    }
+
+    fn emit(&self, event: ToplevelEvent) {
+        // The callbacks are taken out, so they can use the handle
+        let mut callbacks = take(&mut self.inner.lock().unwrap().callbacks);
+        for callback in &mut callbacks {
+            callback(&event);
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        callbacks.append(&mut inner.callbacks);
+        inner.callbacks = callbacks;
+    }
+}
+
+impl ToplevelsInner {
+    fn add(&mut self, handle: ToplevelHandle) {
+        let id = ToplevelId(self.next_id);
+        self.next_id += 1;
+
+        let toplevel = Toplevel {
+            id,
+            title: String::new(),
+            app_id: String::new(),
+            outputs: Vec::new(),
+            state: ToplevelState::default(),
+            parent: None,
+        };
+        self.toplevels.push(ToplevelEntry {
+            handle,
+            current: toplevel.clone(),
+            pending: toplevel,
+            added: false,
+        });
+    }
+
+    /// Apply the pending state.
+    fn done(&mut self, index: usize) -> Option<ToplevelEvent> {
+        let entry = &mut self.toplevels[index];
+        entry.current = entry.pending.clone();
+
+        if entry.added {
+            Some(ToplevelEvent::Changed(entry.current.clone()))
+        } else {
+            entry.added = true;
+            Some(ToplevelEvent::Added(entry.current.clone()))
+        }
+    }
+
+    fn close(&mut self, index: usize) -> Option<ToplevelEvent> {
+        let entry = self.toplevels.remove(index);
+        entry.handle.destroy();
+        entry
+            .added
+            .then_some(ToplevelEvent::Closed(entry.current.id))
+    }
+}
+
+pub(crate) struct ToplevelsState {
+    handle: Toplevels,
+}
+
+impl ToplevelsState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> Result<Self, BindError> {
+        // wlr-foreign-toplevel-management is preferred, since toplevels can be controlled
+        let controllable = globals
+            .bind::<ZwlrForeignToplevelManagerV1, _, _>(queue_handle, 1..=3, GlobalData)
+            .is_ok();
+        if !controllable {
+            globals.bind::<ExtForeignToplevelListV1, _, _>(queue_handle, 1..=1, GlobalData)?;
+        }
+
+        Ok(Self {
+            handle: Toplevels {
+                inner: Arc::default(),
+                controllable,
+                wayland_conn,
+            },
+        })
+    }
+
+    pub fn handle(&self) -> &Toplevels {
+        &self.handle
+    }
+
+    /// Use the seat to activate toplevels.
+    pub fn set_seat(&self, seat: Option<WlSeat>) {
+        self.handle.inner.lock().unwrap().seat = seat;
+    }
+
+    pub fn seat(&self) -> Option<WlSeat> {
+        self.handle.inner.lock().unwrap().seat.clone()
+    }
+}
+
+impl ContextDelegate {
+    fn toplevel_event(&mut self, event: ToplevelEvent) {
+        let Some(toplevels) = &self.toplevels else {
+            return;
+        };
+
+        toplevels.handle.emit(event);
+        for app in self.apps.values() {
+            app.egui_context.request_repaint();
+        }
+    }
+
+    fn add_toplevel(&mut self, handle: ToplevelHandle) {
+        if let Some(toplevels) = &self.toplevels {
+            toplevels.handle.inner.lock().unwrap().add(handle);
+        }
+    }
+
+    /// Apply an event to the toplevel with the given handle.
+    fn update_toplevel(
+        &mut self,
+        handle: ObjectId,
+        update: impl FnOnce(&mut ToplevelsInner, usize) -> Option<ToplevelEvent>,
+    ) {
+        let Some(toplevels) = &self.toplevels else {
+            return;
+        };
+
+        let event = {
+            let mut inner = toplevels.handle.inner.lock().unwrap();
+            let index = inner
+                .toplevels
+                .iter()
+                .position(|entry| entry.handle.id() == handle);
+            index.and_then(|index| update(&mut inner, index))
+        };
+
+        if let Some(event) = event {
+            self.toplevel_event(event);
+        }
+    }
+
+    /// The compositor stopped sending toplevels, they're all closed.
+    fn toplevels_finished(&mut self) {
+        let Some(toplevels) = &self.toplevels else {
+            return;
+        };
+
+        let closed = take(&mut toplevels.handle.inner.lock().unwrap().toplevels);
+        for entry in closed {
+            entry.handle.destroy();
+            if entry.added {
+                self.toplevel_event(ToplevelEvent::Closed(entry.current.id));
+            }
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, GlobalData, ContextDelegate> for ToplevelsState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ZwlrForeignToplevelManagerV1,
+        event: <ZwlrForeignToplevelManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                state.add_toplevel(ToplevelHandle::Wlr(toplevel));
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => state.toplevels_finished(),
+            _ => {}
+        }
+    }
+
+    event_created_child!(ContextDelegate, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, GlobalData),
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, GlobalData, ContextDelegate> for ToplevelsState {
+    fn event(
+        state: &mut ContextDelegate,
+        handle: &ZwlrForeignToplevelHandleV1,
+        event: <ZwlrForeignToplevelHandleV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        state.update_toplevel(handle.id(), |inner, index| match event {
+            zwlr_foreign_toplevel_handle_v1::Event::Title { title } => {
+                inner.toplevels[index].pending.title = title;
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                inner.toplevels[index].pending.app_id = app_id;
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                inner.toplevels[index].pending.outputs.push(output);
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                inner.toplevels[index]
+                    .pending
+                    .outputs
+                    .retain(|other| *other != output);
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+                let mut toplevel_state = ToplevelState::default();
+                for value in state.chunks_exact(4) {
+                    let value = u32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+                    match zwlr_foreign_toplevel_handle_v1::State::try_from(value) {
+                        Ok(zwlr_foreign_toplevel_handle_v1::State::Maximized) => {
+                            toplevel_state.maximized = true;
+                        }
+                        Ok(zwlr_foreign_toplevel_handle_v1::State::Minimized) => {
+                            toplevel_state.minimized = true;
+                        }
+                        Ok(zwlr_foreign_toplevel_handle_v1::State::Activated) => {
+                            toplevel_state.activated = true;
+                        }
+                        Ok(zwlr_foreign_toplevel_handle_v1::State::Fullscreen) => {
+                            toplevel_state.fullscreen = true;
+                        }
+                        _ => {}
+                    }
+                }
+                inner.toplevels[index].pending.state = toplevel_state;
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Parent { parent } => {
+                let parent = parent.and_then(|parent| {
+                    inner
+                        .toplevels
+                        .iter()
+                        .find(|entry| entry.handle.id() == parent.id())
+                        .map(|entry| entry.current.id)
+                });
+                inner.toplevels[index].pending.parent = parent;
+                None
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => inner.done(index),
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => inner.close(index),
+            _ => None,
+        });
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, GlobalData, ContextDelegate> for ToplevelsState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ExtForeignToplevelListV1,
+        event: <ExtForeignToplevelListV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } => {
+                state.add_toplevel(ToplevelHandle::Ext(toplevel));
+            }
+            ext_foreign_toplevel_list_v1::Event::Finished => state.toplevels_finished(),
+            _ => {}
+        }
+    }
+
+    event_created_child!(ContextDelegate, ExtForeignToplevelListV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, GlobalData),
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, GlobalData, ContextDelegate> for ToplevelsState {
+    fn event(
+        state: &mut ContextDelegate,
+        handle: &ExtForeignToplevelHandleV1,
+        event: <ExtForeignToplevelHandleV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        state.update_toplevel(handle.id(), |inner, index| match event {
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => {
+                inner.toplevels[index].pending.title = title;
+                None
+            }
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                inner.toplevels[index].pending.app_id = app_id;
+                None
+            }
+            ext_foreign_toplevel_handle_v1::Event::Done => inner.done(index),
+            ext_foreign_toplevel_handle_v1::Event::Closed => inner.close(index),
+            _ => None,
+        });
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrForeignToplevelManagerV1: GlobalData] => ToplevelsState);
+delegate_dispatch!(ContextDelegate: [ZwlrForeignToplevelHandleV1: GlobalData] => ToplevelsState);
+delegate_dispatch!(ContextDelegate: [ExtForeignToplevelListV1: GlobalData] => ToplevelsState);
+delegate_dispatch!(ContextDelegate: [ExtForeignToplevelHandleV1: GlobalData] => ToplevelsState);
@@ -0,0 +1,231 @@
+//! egui's deferred viewports, each shown on a layer surface of its own, see
+//! [`LayerAppOpts::viewports`](crate::LayerAppOpts::viewports).
+
+use std::{
+    ptr::NonNull,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use egui::{DeferredViewportUiCallback, ViewportBuilder, ViewportCommand, ViewportId};
+use smithay_client_toolkit::{
+    compositor::{CompositorState, Surface},
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::viewporter::client::wp_viewport::WpViewport,
+        protocols_wlr::layer_shell::v1::client::{
+            zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
+            zwlr_layer_surface_v1::{self, Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1},
+        },
+    },
+};
+use wgpu::rwh::{RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle};
+
+use super::{ContextDelegate, wp_viewporter::ViewporterState};
+
+/// The size of viewports that don't set an inner size.
+const DEFAULT_SIZE: (u32, u32) = (400, 300);
+
+/// Everything that's needed to create viewport surfaces while drawing a frame.
+#[derive(Clone)]
+pub(crate) struct ViewportShell {
+    pub layer_shell: ZwlrLayerShellV1,
+    pub compositor: CompositorState,
+    pub viewporter: ViewporterState,
+    pub wgpu_instance: wgpu::Instance,
+    pub wayland_conn: Connection,
+    pub queue_handle: QueueHandle<ContextDelegate>,
+}
+
+pub(crate) struct ViewportSurface {
+    pub id: ViewportId,
+    pub ui_cb: Arc<DeferredViewportUiCallback>,
+    pub wgpu_surface: wgpu::Surface<'static>,
+    pub surface_config: wgpu::SurfaceConfiguration,
+    /// Whether the surface is configured with the current size.
+    pub surface_configured: bool,
+    pub msaa_texture: Option<(wgpu::Texture, wgpu::TextureView)>,
+    pub viewport: WpViewport,
+    layer_surface: ZwlrLayerSurfaceV1,
+    surface: Surface, // drop after wgpu_surface and layer_surface
+    pub data: Arc<ViewportData>,
+    /// The builder that was applied last, to notice when the app changes it.
+    pub builder: ViewportBuilder,
+    /// Input for the viewport's next pass.
+    pub events: Vec<egui::Event>,
+}
+
+pub(crate) struct ViewportData {
+    /// The size from the last configure, before the first one nothing can be attached.
+    pub size: Mutex<Option<(u32, u32)>>,
+    /// Set when the compositor closed the surface, egui gets a close event then.
+    pub closed: AtomicBool,
+    egui_context: egui::Context,
+}
+
+impl ViewportShell {
+    /// Create a layer surface for a viewport. It's placed at the builder's position on the
+    /// compositor's output of choice, or centered if there's none.
+    pub fn create(
+        &self,
+        id: ViewportId,
+        builder: ViewportBuilder,
+        ui_cb: Arc<DeferredViewportUiCallback>,
+        egui_context: &egui::Context,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> Option<ViewportSurface> {
+        let surface = Surface::new(&self.compositor, &self.queue_handle).ok()?;
+        let data = Arc::new(ViewportData {
+            size: Mutex::new(None),
+            closed: AtomicBool::new(false),
+            egui_context: egui_context.clone(),
+        });
+
+        let namespace = builder
+            .app_id
+            .clone()
+            .or_else(|| builder.title.clone())
+            .unwrap_or_else(|| "egui".to_owned());
+        let layer_surface = self.layer_shell.get_layer_surface(
+            surface.wl_surface(),
+            None,
+            Layer::Top,
+            namespace,
+            &self.queue_handle,
+            data.clone(),
+        );
+        if layer_surface.version() >= 4 {
+            layer_surface.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
+        }
+        apply_builder(&layer_surface, &builder, egui_context.zoom_factor());
+        // Like other layer surfaces, the first commit has no buffer and waits for a configure
+        surface.wl_surface().commit();
+
+        let viewport = self
+            .viewporter
+            .get_viewport(surface.wl_surface(), &self.queue_handle);
+
+        let raw_display_handle = RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
+            NonNull::new(self.wayland_conn.backend().display_ptr() as *mut _)?,
+        ));
+        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(NonNull::new(
+            surface.wl_surface().id().as_ptr() as *mut _,
+        )?));
+
+        let wgpu_surface = unsafe {
+            self.wgpu_instance
+                .create_surface_unsafe(wgpu::SurfaceTargetUnsafe::RawHandle {
+                    raw_display_handle,
+                    raw_window_handle,
+                })
+                .ok()?
+        };
+
+        Some(ViewportSurface {
+            id,
+            ui_cb,
+            wgpu_surface,
+            surface_config: surface_config.clone(),
+            surface_configured: false,
+            msaa_texture: None,
+            viewport,
+            layer_surface,
+            surface,
+            data,
+            builder,
+            events: Vec::new(),
+        })
+    }
+}
+
+impl ViewportSurface {
+    pub fn wl_surface(&self) -> &WlSurface {
+        self.surface.wl_surface()
+    }
+
+    /// Apply the builder of the latest frame, if the app changed it.
+    pub fn set_builder(&mut self, builder: &ViewportBuilder, zoom: f32) {
+        if *builder != self.builder {
+            apply_builder(&self.layer_surface, builder, zoom);
+            self.builder = builder.clone();
+        }
+    }
+
+    /// Apply the viewport commands that make sense for a layer surface, the rest is ignored.
+    pub fn handle_commands(&self, commands: &[ViewportCommand], zoom: f32) {
+        for command in commands {
+            match command {
+                // Let egui know, so the app can stop showing the viewport
+                ViewportCommand::Close => self.data.closed.store(true, Ordering::Relaxed),
+                ViewportCommand::InnerSize(size) => {
+                    let size = (*size * zoom).round();
+                    self.layer_surface.set_size(size.x as u32, size.y as u32);
+                }
+                ViewportCommand::OuterPosition(position) => {
+                    set_position(&self.layer_surface, *position, zoom);
+                }
+                _ => continue,
+            }
+
+            // Everything above takes effect with the next commit
+            self.data.egui_context.request_repaint();
+        }
+    }
+}
+
+impl Drop for ViewportSurface {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        self.layer_surface.destroy();
+    }
+}
+
+/// Set the layer surface's size and position from a viewport builder.
+fn apply_builder(layer_surface: &ZwlrLayerSurfaceV1, builder: &ViewportBuilder, zoom: f32) {
+    let (width, height) = builder
+        .inner_size
+        .map(|size| (size * zoom).round())
+        .map_or(DEFAULT_SIZE, |size| (size.x as u32, size.y as u32));
+    layer_surface.set_size(width, height);
+
+    match builder.position {
+        Some(position) => set_position(layer_surface, position, zoom),
+        None => layer_surface.set_anchor(Anchor::empty()),
+    }
+}
+
+/// Place the layer surface at `position`, in points from the top left of the output.
+fn set_position(layer_surface: &ZwlrLayerSurfaceV1, position: egui::Pos2, zoom: f32) {
+    let position = (position * zoom).round();
+    layer_surface.set_anchor(Anchor::Top | Anchor::Left);
+    layer_surface.set_margin(position.y as i32, 0, 0, position.x as i32);
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, Arc<ViewportData>, ContextDelegate> for ViewportShell {
+    fn event(
+        _: &mut ContextDelegate,
+        layer_surface: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        data: &Arc<ViewportData>,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, width, height } => {
+                layer_surface.ack_configure(serial);
+                *data.size.lock().unwrap() = Some((width.max(1), height.max(1)));
+            }
+            zwlr_layer_surface_v1::Event::Closed => data.closed.store(true, Ordering::Relaxed),
+            _ => return,
+        }
+
+        data.egui_context.request_repaint();
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrLayerSurfaceV1: Arc<ViewportData>] => ViewportShell);
@@ -0,0 +1,175 @@
+//! Pointer input injection through wlr-virtual-pointer, eg. for on-screen trackpads or dwell
+//! clickers.
+
+use std::time::Instant;
+
+use egui::{PointerButton, Pos2, Vec2};
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::{
+                wl_pointer::{Axis, AxisSource, ButtonState},
+                wl_seat::WlSeat,
+            },
+        },
+        protocols_wlr::virtual_pointer::v1::client::{
+            zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1,
+            zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
+        },
+    },
+    seat::pointer::{BTN_BACK, BTN_FORWARD, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT},
+};
+
+use super::ContextDelegate;
+
+/// A pointer that moves and clicks like a real one. Every call is sent as a separate pointer
+/// frame. The pointer is removed once this is dropped.
+pub struct VirtualPointer {
+    pointer: ZwlrVirtualPointerV1,
+    start: Instant,
+    wayland_conn: Connection,
+}
+
+impl VirtualPointer {
+    /// Move the pointer relative to where it is, in logical pixels.
+    pub fn move_by(&self, delta: Vec2) {
+        self.pointer
+            .motion(self.time(), delta.x as f64, delta.y as f64);
+        self.frame();
+    }
+
+    /// Move the pointer to a position within `extent`, which the compositor maps to the whole
+    /// output layout, eg. the size of a trackpad widget.
+    pub fn move_to(&self, position: Pos2, extent: Vec2) {
+        let max = (extent - Vec2::splat(1.)).max(Vec2::ZERO);
+        let position = position.clamp(Pos2::ZERO, max.to_pos2());
+        self.pointer.motion_absolute(
+            self.time(),
+            position.x.round() as u32,
+            position.y.round() as u32,
+            extent.x.round() as u32,
+            extent.y.round() as u32,
+        );
+        self.frame();
+    }
+
+    pub fn button(&self, button: PointerButton, pressed: bool) {
+        let state = match pressed {
+            true => ButtonState::Pressed,
+            false => ButtonState::Released,
+        };
+        self.pointer.button(self.time(), button_code(button), state);
+        self.frame();
+    }
+
+    /// Press and release the button.
+    pub fn click(&self, button: PointerButton) {
+        self.button(button, true);
+        self.button(button, false);
+    }
+
+    /// Scroll by `delta` in logical pixels, positive values scroll down and right like a wheel.
+    pub fn scroll(&self, delta: Vec2) {
+        let time = self.time();
+        self.pointer.axis_source(AxisSource::Continuous);
+        if delta.y != 0. {
+            self.pointer
+                .axis(time, Axis::VerticalScroll, delta.y as f64);
+        }
+        if delta.x != 0. {
+            self.pointer
+                .axis(time, Axis::HorizontalScroll, delta.x as f64);
+        }
+        self.frame();
+    }
+
+    fn frame(&self) {
+        self.pointer.frame();
+        let _ = self.wayland_conn.flush();
+    }
+
+    /// Milliseconds since the pointer was created, as event timestamps.
+    fn time(&self) -> u32 {
+        self.start.elapsed().as_millis() as u32
+    }
+}
+
+impl Drop for VirtualPointer {
+    fn drop(&mut self) {
+        self.pointer.destroy();
+        let _ = self.wayland_conn.flush();
+    }
+}
+
+fn button_code(button: PointerButton) -> u32 {
+    match button {
+        PointerButton::Primary => BTN_LEFT,
+        PointerButton::Secondary => BTN_RIGHT,
+        PointerButton::Middle => BTN_MIDDLE,
+        PointerButton::Extra1 => BTN_BACK,
+        PointerButton::Extra2 => BTN_FORWARD,
+    }
+}
+
+/// Virtual pointer manager, to create virtual pointers on a seat.
+#[derive(Debug)]
+pub struct VirtualPointerState {
+    manager: ZwlrVirtualPointerManagerV1,
+}
+
+impl VirtualPointerState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=2, GlobalData)?;
+        Ok(Self { manager })
+    }
+
+    pub fn create_pointer(
+        &self,
+        seat: Option<&WlSeat>,
+        queue_handle: &QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> VirtualPointer {
+        let pointer = self
+            .manager
+            .create_virtual_pointer(seat, queue_handle, GlobalData);
+        VirtualPointer {
+            pointer,
+            start: Instant::now(),
+            wayland_conn,
+        }
+    }
+}
+
+impl Dispatch<ZwlrVirtualPointerManagerV1, GlobalData, ContextDelegate> for VirtualPointerState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwlrVirtualPointerManagerV1,
+        _: <ZwlrVirtualPointerManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<ZwlrVirtualPointerV1, GlobalData, ContextDelegate> for VirtualPointerState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwlrVirtualPointerV1,
+        _: <ZwlrVirtualPointerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwlrVirtualPointerManagerV1: GlobalData] => VirtualPointerState);
+delegate_dispatch!(ContextDelegate: [ZwlrVirtualPointerV1: GlobalData] => VirtualPointerState);
@@ -0,0 +1,116 @@
+//! Ordinary windows, as a fallback for compositors without layer shell.
+
+use std::sync::{Arc, Mutex};
+
+use smithay_client_toolkit::reexports::{
+    client::{
+        Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+        protocol::wl_surface::WlSurface,
+    },
+    protocols::xdg::shell::client::{
+        xdg_surface::{self, XdgSurface},
+        xdg_toplevel::{self, XdgToplevel},
+    },
+};
+
+use super::{ContextDelegate, popup::XdgShellState};
+
+/// The size of windows that the compositor lets the app choose the size for.
+const DEFAULT_SIZE: (u32, u32) = (800, 600);
+
+/// An xdg toplevel that shows a layer app in a window.
+pub struct WindowSurface {
+    wl_surface: WlSurface,
+    xdg_surface: XdgSurface,
+    xdg_toplevel: XdgToplevel,
+}
+
+pub struct WindowData {
+    wl_surface: WlSurface,
+    /// The size of the pending configure.
+    size: Mutex<(u32, u32)>,
+}
+
+impl WindowSurface {
+    /// Turn the surface into a window. Like layer surfaces, it needs an initial commit without a
+    /// buffer, after which the compositor sends the first configure.
+    pub fn new(
+        xdg_shell: &XdgShellState,
+        wl_surface: WlSurface,
+        title: &str,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Self {
+        let data = Arc::new(WindowData {
+            wl_surface: wl_surface.clone(),
+            size: Mutex::new(DEFAULT_SIZE),
+        });
+        let xdg_surface =
+            xdg_shell
+                .xdg_wm_base()
+                .get_xdg_surface(&wl_surface, queue_handle, data.clone());
+        let xdg_toplevel = xdg_surface.get_toplevel(queue_handle, data);
+        xdg_toplevel.set_title(title.to_owned());
+
+        Self {
+            wl_surface,
+            xdg_surface,
+            xdg_toplevel,
+        }
+    }
+
+    pub fn set_app_id(&self, app_id: &str) {
+        self.xdg_toplevel.set_app_id(app_id.to_owned());
+    }
+
+    pub fn wl_surface(&self) -> &WlSurface {
+        &self.wl_surface
+    }
+}
+
+impl Drop for WindowSurface {
+    fn drop(&mut self) {
+        self.xdg_toplevel.destroy();
+        self.xdg_surface.destroy();
+        self.wl_surface.destroy();
+    }
+}
+
+impl Dispatch<XdgSurface, Arc<WindowData>, ContextDelegate> for XdgShellState {
+    fn event(
+        state: &mut ContextDelegate,
+        xdg_surface: &XdgSurface,
+        event: <XdgSurface as Proxy>::Event,
+        data: &Arc<WindowData>,
+        _: &Connection,
+        qh: &QueueHandle<ContextDelegate>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+            let size = *data.size.lock().unwrap();
+            state.configure_app(&data.wl_surface, size, qh);
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, Arc<WindowData>, ContextDelegate> for XdgShellState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &XdgToplevel,
+        event: <XdgToplevel as Proxy>::Event,
+        data: &Arc<WindowData>,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            // Zero means the app can choose, so it keeps the size it has
+            xdg_toplevel::Event::Configure { width, height, .. } if width > 0 && height > 0 => {
+                *data.size.lock().unwrap() = (width as u32, height as u32);
+            }
+            xdg_toplevel::Event::Close => state.close_window(&data.wl_surface),
+            _ => {}
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [XdgSurface: Arc<WindowData>] => XdgShellState);
+delegate_dispatch!(ContextDelegate: [XdgToplevel: Arc<WindowData>] => XdgShellState);
@@ -0,0 +1,74 @@
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::alpha_modifier::v1::client::{
+            wp_alpha_modifier_surface_v1::WpAlphaModifierSurfaceV1,
+            wp_alpha_modifier_v1::WpAlphaModifierV1,
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Alpha modifier, to change a surface's opacity without rendering it again.
+#[derive(Debug)]
+pub struct AlphaModifierState {
+    alpha_modifier: WpAlphaModifierV1,
+}
+
+impl AlphaModifierState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let alpha_modifier = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self { alpha_modifier })
+    }
+
+    pub fn get_surface(
+        &self,
+        surface: &WlSurface,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> WpAlphaModifierSurfaceV1 {
+        self.alpha_modifier
+            .get_surface(surface, queue_handle, GlobalData)
+    }
+}
+
+/// Convert an opacity between 0 and 1 to the protocol's multiplier.
+pub fn multiplier(opacity: f32) -> u32 {
+    (opacity.clamp(0., 1.) as f64 * u32::MAX as f64).round() as u32
+}
+
+impl Dispatch<WpAlphaModifierV1, GlobalData, ContextDelegate> for AlphaModifierState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpAlphaModifierV1,
+        _: <WpAlphaModifierV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<WpAlphaModifierSurfaceV1, GlobalData, ContextDelegate> for AlphaModifierState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpAlphaModifierSurfaceV1,
+        _: <WpAlphaModifierSurfaceV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [WpAlphaModifierV1: GlobalData] => AlphaModifierState);
+delegate_dispatch!(ContextDelegate: [WpAlphaModifierSurfaceV1: GlobalData] => AlphaModifierState);
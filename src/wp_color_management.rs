@@ -0,0 +1,219 @@
+//! Tagging surfaces with their color space, through the color management protocol.
+
+use log::warn;
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::color_management::v1::client::{
+            wp_color_management_surface_v1::WpColorManagementSurfaceV1,
+            wp_color_manager_v1::{
+                Event as ColorManagerEvent, Feature, Primaries, RenderIntent, TransferFunction,
+                WpColorManagerV1,
+            },
+            wp_image_description_creator_params_v1::WpImageDescriptionCreatorParamsV1,
+            wp_image_description_v1::{Event as ImageDescriptionEvent, WpImageDescriptionV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// The color space that a surface's contents are in.
+///
+/// egui itself renders sRGB content; the other options are for apps that render their own
+/// content, eg. through paint callbacks. Note that some Vulkan drivers already tag
+/// [`SurfaceFormat::Rgba16Float`](crate::SurfaceFormat::Rgba16Float) surfaces themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDescription {
+    /// sRGB primaries and transfer function.
+    Srgb,
+    /// sRGB primaries with an extended linear transfer function (Windows-scRGB), values above 1.0
+    /// are brighter than SDR white.
+    ScRgb,
+    /// BT.2020 primaries with the PQ transfer function, ie. HDR10.
+    Bt2100Pq,
+}
+
+#[derive(Debug)]
+pub struct ColorManagerState {
+    manager: WpColorManagerV1,
+    features: Vec<Feature>,
+    transfer_functions: Vec<TransferFunction>,
+    primaries: Vec<Primaries>,
+    perceptual: bool,
+}
+
+pub struct ImageDescriptionData {
+    surface: WlSurface,
+}
+
+impl ColorManagerState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+
+        Ok(Self {
+            manager,
+            features: Vec::new(),
+            transfer_functions: Vec::new(),
+            primaries: Vec::new(),
+            perceptual: false,
+        })
+    }
+
+    fn supports(&self, description: ImageDescription) -> bool {
+        let parametric = |primaries, transfer_function| {
+            self.features.contains(&Feature::Parametric)
+                && self.primaries.contains(&primaries)
+                && self.transfer_functions.contains(&transfer_function)
+        };
+
+        self.perceptual
+            && match description {
+                ImageDescription::Srgb => parametric(Primaries::Srgb, TransferFunction::Gamma22),
+                ImageDescription::ScRgb => self.features.contains(&Feature::WindowsScrgb),
+                ImageDescription::Bt2100Pq => {
+                    parametric(Primaries::Bt2020, TransferFunction::St2084Pq)
+                }
+            }
+    }
+
+    /// Tag the surface with the given image description. The description is set once the
+    /// compositor has it ready, and takes effect on the next commit.
+    ///
+    /// Returns `None` if the compositor doesn't support the description.
+    pub fn set_image_description(
+        &self,
+        surface: &WlSurface,
+        description: ImageDescription,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Option<WpColorManagementSurfaceV1> {
+        if !self.supports(description) {
+            return None;
+        }
+
+        let data = ImageDescriptionData { surface: surface.clone() };
+
+        match description {
+            ImageDescription::Srgb => {
+                let creator = self
+                    .manager
+                    .create_parametric_creator(queue_handle, GlobalData);
+                creator.set_primaries_named(Primaries::Srgb);
+                creator.set_tf_named(TransferFunction::Gamma22);
+                creator.create(queue_handle, data);
+            }
+            ImageDescription::ScRgb => {
+                self.manager.create_windows_scrgb(queue_handle, data);
+            }
+            ImageDescription::Bt2100Pq => {
+                let creator = self
+                    .manager
+                    .create_parametric_creator(queue_handle, GlobalData);
+                creator.set_primaries_named(Primaries::Bt2020);
+                creator.set_tf_named(TransferFunction::St2084Pq);
+                creator.create(queue_handle, data);
+            }
+        }
+
+        Some(self.manager.get_surface(surface, queue_handle, GlobalData))
+    }
+}
+
+impl Dispatch<WpColorManagerV1, GlobalData, ContextDelegate> for ColorManagerState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &WpColorManagerV1,
+        event: <WpColorManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        let Some(color_manager) = &mut state.color_manager else {
+            return;
+        };
+
+        match event {
+            ColorManagerEvent::SupportedIntent { render_intent: WEnum::Value(intent) } => {
+                color_manager.perceptual |= intent == RenderIntent::Perceptual;
+            }
+            ColorManagerEvent::SupportedFeature { feature: WEnum::Value(feature) } => {
+                color_manager.features.push(feature);
+            }
+            ColorManagerEvent::SupportedTfNamed { tf: WEnum::Value(tf) } => {
+                color_manager.transfer_functions.push(tf);
+            }
+            ColorManagerEvent::SupportedPrimariesNamed { primaries: WEnum::Value(primaries) } => {
+                color_manager.primaries.push(primaries);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpImageDescriptionCreatorParamsV1, GlobalData, ContextDelegate>
+    for ColorManagerState
+{
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpImageDescriptionCreatorParamsV1,
+        _: <WpImageDescriptionCreatorParamsV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<WpImageDescriptionV1, ImageDescriptionData, ContextDelegate> for ColorManagerState {
+    fn event(
+        state: &mut ContextDelegate,
+        description: &WpImageDescriptionV1,
+        event: <WpImageDescriptionV1 as Proxy>::Event,
+        data: &ImageDescriptionData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        match event {
+            ImageDescriptionEvent::Ready { .. } => {
+                if let Some(app) = state.apps.get_mut(&data.surface.id()) {
+                    if let Some(color_surface) = &app.color_surface {
+                        color_surface.set_image_description(description, RenderIntent::Perceptual);
+                    }
+                }
+                description.destroy();
+            }
+            ImageDescriptionEvent::Failed { msg, .. } => {
+                warn!("Failed to create image description: {msg}");
+                description.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpColorManagementSurfaceV1, GlobalData, ContextDelegate> for ColorManagerState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpColorManagementSurfaceV1,
+        _: <WpColorManagementSurfaceV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [WpColorManagerV1: GlobalData] => ColorManagerState);
+delegate_dispatch!(ContextDelegate: [WpImageDescriptionCreatorParamsV1: GlobalData] => ColorManagerState);
+delegate_dispatch!(ContextDelegate: [WpImageDescriptionV1: ImageDescriptionData] => ColorManagerState);
+delegate_dispatch!(ContextDelegate: [WpColorManagementSurfaceV1: GlobalData] => ColorManagerState);
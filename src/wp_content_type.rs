@@ -0,0 +1,91 @@
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::content_type::v1::client::{
+            wp_content_type_manager_v1::WpContentTypeManagerV1,
+            wp_content_type_v1::{self, WpContentTypeV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Content type manager, to tell the compositor what kind of content a surface shows.
+#[derive(Debug)]
+pub struct ContentTypeState {
+    manager: WpContentTypeManagerV1,
+}
+
+impl ContentTypeState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self { manager })
+    }
+
+    pub fn get_surface_content_type(
+        &self,
+        surface: &WlSurface,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> WpContentTypeV1 {
+        self.manager
+            .get_surface_content_type(surface, queue_handle, GlobalData)
+    }
+}
+
+/// What kind of content a surface shows, so the compositor can eg. pick a scaling filter or
+/// enable adaptive sync for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ContentType {
+    #[default]
+    None,
+    Photo,
+    Video,
+    Game,
+}
+
+impl From<ContentType> for wp_content_type_v1::Type {
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            ContentType::None => wp_content_type_v1::Type::None,
+            ContentType::Photo => wp_content_type_v1::Type::Photo,
+            ContentType::Video => wp_content_type_v1::Type::Video,
+            ContentType::Game => wp_content_type_v1::Type::Game,
+        }
+    }
+}
+
+impl Dispatch<WpContentTypeManagerV1, GlobalData, ContextDelegate> for ContentTypeState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpContentTypeManagerV1,
+        _: <WpContentTypeManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<WpContentTypeV1, GlobalData, ContextDelegate> for ContentTypeState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpContentTypeV1,
+        _: <WpContentTypeV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [WpContentTypeManagerV1: GlobalData] => ContentTypeState);
+delegate_dispatch!(ContextDelegate: [WpContentTypeV1: GlobalData] => ContentTypeState);
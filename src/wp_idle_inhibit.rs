@@ -0,0 +1,136 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::idle_inhibit::zv1::client::{
+            zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+            zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Idle inhibit manager, to keep the screen awake while a surface is visible.
+#[derive(Debug, Clone)]
+pub struct IdleInhibitState {
+    manager: ZwpIdleInhibitManagerV1,
+}
+
+impl IdleInhibitState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self { manager })
+    }
+}
+
+/// Toggles an inhibitor on an app's surface. The compositor only honors it while the surface is
+/// visible, so hiding the surface releases it as well.
+pub(crate) struct IdleInhibitor {
+    /// `None` after reconnecting to a compositor that doesn't support idle inhibition.
+    idle_inhibit: Mutex<Option<IdleInhibitState>>,
+    surface: Mutex<WlSurface>,
+    queue_handle: Mutex<QueueHandle<ContextDelegate>>,
+    inhibitor: Mutex<Option<ZwpIdleInhibitorV1>>,
+    /// Set once the app exited, after which its surface is gone.
+    released: AtomicBool,
+}
+
+impl IdleInhibitor {
+    pub fn new(
+        idle_inhibit: IdleInhibitState,
+        surface: WlSurface,
+        queue_handle: QueueHandle<ContextDelegate>,
+    ) -> Self {
+        Self {
+            idle_inhibit: Mutex::new(Some(idle_inhibit)),
+            surface: Mutex::new(surface),
+            queue_handle: Mutex::new(queue_handle),
+            inhibitor: Mutex::new(None),
+            released: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_inhibited(&self, inhibited: bool) {
+        let mut inhibitor = self.inhibitor.lock().unwrap();
+
+        if !inhibited {
+            if let Some(inhibitor) = inhibitor.take() {
+                inhibitor.destroy();
+            }
+        } else if inhibitor.is_none() && !self.released.load(Ordering::Relaxed) {
+            if let Some(idle_inhibit) = &*self.idle_inhibit.lock().unwrap() {
+                *inhibitor = Some(idle_inhibit.manager.create_inhibitor(
+                    &self.surface.lock().unwrap(),
+                    &self.queue_handle.lock().unwrap(),
+                    GlobalData,
+                ));
+            }
+        }
+    }
+
+    /// Use the manager of a new connection, the inhibitor is created again along with the app's
+    /// new surface, see [`IdleInhibitor::set_surface`].
+    pub fn reconnect(
+        &self,
+        idle_inhibit: Option<IdleInhibitState>,
+        queue_handle: QueueHandle<ContextDelegate>,
+    ) {
+        *self.idle_inhibit.lock().unwrap() = idle_inhibit;
+        *self.queue_handle.lock().unwrap() = queue_handle;
+    }
+
+    /// Move the inhibitor to the app's new surface, after its output came back.
+    pub fn set_surface(&self, surface: WlSurface) {
+        let inhibited = self.inhibitor.lock().unwrap().is_some();
+        self.set_inhibited(false);
+        *self.surface.lock().unwrap() = surface;
+        self.set_inhibited(inhibited);
+    }
+
+    /// Destroy the inhibitor for good, before the app's surface is destroyed.
+    pub fn release(&self) {
+        self.released.store(true, Ordering::Relaxed);
+        self.set_inhibited(false);
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, GlobalData, ContextDelegate> for IdleInhibitState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwpIdleInhibitManagerV1,
+        _: <ZwpIdleInhibitManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<ZwpIdleInhibitorV1, GlobalData, ContextDelegate> for IdleInhibitState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwpIdleInhibitorV1,
+        _: <ZwpIdleInhibitorV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwpIdleInhibitManagerV1: GlobalData] => IdleInhibitState);
+delegate_dispatch!(ContextDelegate: [ZwpIdleInhibitorV1: GlobalData] => IdleInhibitState);
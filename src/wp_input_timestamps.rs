@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::{wl_keyboard::WlKeyboard, wl_pointer::WlPointer, wl_touch::WlTouch},
+        },
+        protocols::wp::input_timestamps::zv1::client::{
+            zwp_input_timestamps_manager_v1::ZwpInputTimestampsManagerV1,
+            zwp_input_timestamps_v1::{self, ZwpInputTimestampsV1},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// Input timestamps, for nanosecond precision on input events. They're exposed through
+/// [`LayerFrame::input_times`](crate::LayerFrame::input_times) and the input latency stat, egui
+/// itself doesn't get them.
+#[derive(Debug)]
+pub struct InputTimestampsState {
+    manager: ZwpInputTimestampsManagerV1,
+    keyboard: Option<ZwpInputTimestampsV1>,
+    pointer: Option<ZwpInputTimestampsV1>,
+    touch: Option<ZwpInputTimestampsV1>,
+    /// The timestamp of the next input event, on `CLOCK_MONOTONIC`.
+    latest: Option<Duration>,
+}
+
+impl InputTimestampsState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self {
+            manager,
+            keyboard: None,
+            pointer: None,
+            touch: None,
+            latest: None,
+        })
+    }
+
+    pub fn set_keyboard(
+        &mut self,
+        keyboard: Option<&WlKeyboard>,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) {
+        let timestamps = keyboard.map(|keyboard| {
+            self.manager
+                .get_keyboard_timestamps(keyboard, queue_handle, GlobalData)
+        });
+        if let Some(old) = std::mem::replace(&mut self.keyboard, timestamps) {
+            old.destroy();
+        }
+    }
+
+    pub fn set_pointer(
+        &mut self,
+        pointer: Option<&WlPointer>,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) {
+        let timestamps = pointer.map(|pointer| {
+            self.manager
+                .get_pointer_timestamps(pointer, queue_handle, GlobalData)
+        });
+        if let Some(old) = std::mem::replace(&mut self.pointer, timestamps) {
+            old.destroy();
+        }
+    }
+
+    pub fn set_touch(
+        &mut self,
+        touch: Option<&WlTouch>,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) {
+        let timestamps = touch.map(|touch| {
+            self.manager
+                .get_touch_timestamps(touch, queue_handle, GlobalData)
+        });
+        if let Some(old) = std::mem::replace(&mut self.touch, timestamps) {
+            old.destroy();
+        }
+    }
+
+    /// The timestamp of the input event that's being handled.
+    pub fn take(&mut self) -> Option<Duration> {
+        self.latest.take()
+    }
+}
+
+impl Dispatch<ZwpInputTimestampsManagerV1, GlobalData, ContextDelegate> for InputTimestampsState {
+    fn event(
+        _: &mut ContextDelegate,
+        _: &ZwpInputTimestampsManagerV1,
+        _: <ZwpInputTimestampsManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+impl Dispatch<ZwpInputTimestampsV1, GlobalData, ContextDelegate> for InputTimestampsState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &ZwpInputTimestampsV1,
+        event: <ZwpInputTimestampsV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        if let zwp_input_timestamps_v1::Event::Timestamp { tv_sec_hi, tv_sec_lo, tv_nsec } = event {
+            if let Some(input_timestamps) = &mut state.input_timestamps {
+                let secs = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+                input_timestamps.latest = Some(Duration::new(secs, tv_nsec));
+            }
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [ZwpInputTimestampsManagerV1: GlobalData] => InputTimestampsState);
+delegate_dispatch!(ContextDelegate: [ZwpInputTimestampsV1: GlobalData] => InputTimestampsState);
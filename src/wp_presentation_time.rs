@@ -0,0 +1,168 @@
+//! Presentation feedback, to find out when and how frames were shown.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, WEnum, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_surface::WlSurface,
+        },
+        protocols::wp::presentation_time::client::{
+            wp_presentation::{self, WpPresentation},
+            wp_presentation_feedback::{self, Kind, WpPresentationFeedback},
+        },
+    },
+};
+
+use super::ContextDelegate;
+
+/// How a frame was shown, see [`App::on_presentation`](crate::App::on_presentation).
+#[derive(Debug, Clone, Copy)]
+pub enum PresentationFeedback {
+    Presented(Presentation),
+    /// The frame was never shown, eg. because a newer one replaced it.
+    Discarded,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Presentation {
+    /// From the start of the frame until it was shown.
+    pub latency: Duration,
+    /// Time until the next refresh, or `None` if the output doesn't have a fixed refresh rate.
+    pub refresh: Option<Duration>,
+    /// The output's vertical retrace counter, if it has one.
+    pub sequence: Option<u64>,
+    /// Whether the frame was shown in sync with the output's refresh, without tearing.
+    pub vsync: bool,
+    /// Whether the timestamp comes from the display hardware.
+    pub hw_clock: bool,
+    /// Whether the display hardware signalled that the frame was shown.
+    pub hw_completion: bool,
+    /// Whether the buffer was scanned out directly, without compositing.
+    pub zero_copy: bool,
+}
+
+/// Presentation time, to request feedback for an app's frames.
+#[derive(Clone)]
+pub struct PresentationTimeState {
+    presentation: WpPresentation,
+    /// The clock the compositor's timestamps are in, `CLOCK_MONOTONIC` until it says otherwise.
+    clock_id: Arc<AtomicU32>,
+    queue_handle: QueueHandle<ContextDelegate>,
+}
+
+impl PresentationTimeState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let presentation = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self {
+            presentation,
+            clock_id: Arc::new(AtomicU32::new(libc::CLOCK_MONOTONIC as u32)),
+            queue_handle: queue_handle.clone(),
+        })
+    }
+
+    /// Get feedback for the surface's next commit. `frame_time` is how long ago the frame
+    /// started.
+    pub fn feedback(&self, surface: &WlSurface, frame_time: Duration) {
+        let data = FeedbackData {
+            surface: surface.clone(),
+            frame_start: clock_time(self.clock_id.load(Ordering::Relaxed) as libc::clockid_t)
+                .saturating_sub(frame_time),
+        };
+        self.presentation
+            .feedback(surface, &self.queue_handle, data);
+    }
+}
+
+/// The current time on a clock like `CLOCK_MONOTONIC`, which compositors use for timestamps.
+pub fn clock_time(clock_id: libc::clockid_t) -> Duration {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `time` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(clock_id, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+pub struct FeedbackData {
+    surface: WlSurface,
+    /// When the frame started, on the compositor's clock.
+    frame_start: Duration,
+}
+
+impl Dispatch<WpPresentation, GlobalData, ContextDelegate> for PresentationTimeState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &WpPresentation,
+        event: <WpPresentation as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            if let Some(presentation_time) = &state.presentation_time {
+                presentation_time.clock_id.store(clk_id, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, FeedbackData, ContextDelegate> for PresentationTimeState {
+    fn event(
+        state: &mut ContextDelegate,
+        _: &WpPresentationFeedback,
+        event: <WpPresentationFeedback as Proxy>::Event,
+        data: &FeedbackData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        let feedback = match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => {
+                let presented =
+                    Duration::new(((tv_sec_hi as u64) << 32) | tv_sec_lo as u64, tv_nsec);
+                let flags = match flags {
+                    WEnum::Value(flags) => flags,
+                    WEnum::Unknown(bits) => Kind::from_bits_truncate(bits),
+                };
+                let sequence = ((seq_hi as u64) << 32) | seq_lo as u64;
+
+                PresentationFeedback::Presented(Presentation {
+                    latency: presented.saturating_sub(data.frame_start),
+                    refresh: (refresh != 0).then(|| Duration::from_nanos(refresh as u64)),
+                    sequence: (sequence != 0).then_some(sequence),
+                    vsync: flags.contains(Kind::Vsync),
+                    hw_clock: flags.contains(Kind::HwClock),
+                    hw_completion: flags.contains(Kind::HwCompletion),
+                    zero_copy: flags.contains(Kind::ZeroCopy),
+                })
+            }
+            wp_presentation_feedback::Event::Discarded => PresentationFeedback::Discarded,
+            _ => return,
+        };
+
+        state.presentation_feedback(&data.surface, feedback);
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [WpPresentation: GlobalData] => PresentationTimeState);
+delegate_dispatch!(ContextDelegate: [WpPresentationFeedback: FeedbackData] => PresentationTimeState);
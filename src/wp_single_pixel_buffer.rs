@@ -0,0 +1,182 @@
+//! Layer surfaces that are filled with a single color, through the single pixel buffer protocol.
+
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use egui::Color32;
+use smithay_client_toolkit::{
+    globals::GlobalData,
+    reexports::{
+        client::{
+            Connection, Dispatch, Proxy, QueueHandle, delegate_dispatch,
+            globals::{BindError, GlobalList},
+            protocol::wl_buffer::{Event as BufferEvent, WlBuffer},
+        },
+        protocols::wp::{
+            single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1,
+            viewporter::client::wp_viewport::WpViewport,
+        },
+    },
+    shell::{WaylandSurface, wlr_layer::LayerSurface},
+};
+
+use super::ContextDelegate;
+
+#[derive(Debug, Clone)]
+pub struct SinglePixelBufferState {
+    manager: WpSinglePixelBufferManagerV1,
+}
+
+#[derive(Default)]
+pub struct BufferData {
+    released: AtomicBool,
+    retired: AtomicBool,
+}
+
+impl SinglePixelBufferState {
+    pub fn bind(
+        globals: &GlobalList,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> Result<Self, BindError> {
+        let manager = globals.bind(queue_handle, 1..=1, GlobalData)?;
+        Ok(Self { manager })
+    }
+
+    fn create_buffer(
+        &self,
+        color: Color32,
+        queue_handle: &QueueHandle<ContextDelegate>,
+    ) -> WlBuffer {
+        // The channels are premultiplied, like egui's colors
+        let channel = |value: u8| value as u32 * 0x01010101;
+        let [r, g, b, a] = color.to_array();
+
+        self.manager.create_u32_rgba_buffer(
+            channel(r),
+            channel(g),
+            channel(b),
+            channel(a),
+            queue_handle,
+            BufferData::default(),
+        )
+    }
+}
+
+/// A layer surface that's filled with a single color, eg. to dim the screen. The compositor
+/// stretches a single pixel over the whole surface, so it doesn't need any GPU memory or
+/// rendering. Egui content can be shown on top of it with a regular app.
+///
+/// The layer surface is closed when this is dropped.
+pub struct SolidLayer {
+    layer: LayerSurface,
+    viewport: WpViewport,
+    buffers: SinglePixelBufferState,
+    buffer: Mutex<WlBuffer>,
+    queue_handle: QueueHandle<ContextDelegate>,
+    wayland_conn: Connection,
+}
+
+impl SolidLayer {
+    pub(crate) fn new(
+        layer: LayerSurface,
+        viewport: WpViewport,
+        buffers: SinglePixelBufferState,
+        color: Color32,
+        queue_handle: QueueHandle<ContextDelegate>,
+        wayland_conn: Connection,
+    ) -> Arc<Self> {
+        let buffer = Mutex::new(buffers.create_buffer(color, &queue_handle));
+
+        Arc::new(Self {
+            layer,
+            viewport,
+            buffers,
+            buffer,
+            queue_handle,
+            wayland_conn,
+        })
+    }
+
+    pub fn layer(&self) -> &LayerSurface {
+        &self.layer
+    }
+
+    /// Change the color, which is shown right away.
+    pub fn set_color(&self, color: Color32) {
+        let buffer = self.buffers.create_buffer(color, &self.queue_handle);
+        let old_buffer = std::mem::replace(&mut *self.buffer.lock().unwrap(), buffer.clone());
+        retire(&old_buffer);
+
+        self.attach(&buffer);
+        let _ = self.wayland_conn.flush();
+    }
+
+    pub(crate) fn configure(&self, width: u32, height: u32) {
+        self.viewport.set_destination(width as i32, height as i32);
+        self.attach(&self.buffer.lock().unwrap());
+    }
+
+    fn attach(&self, buffer: &WlBuffer) {
+        let surface = self.layer.wl_surface();
+        surface.attach(Some(buffer), 0, 0);
+        surface.damage(0, 0, i32::MAX, i32::MAX);
+        surface.commit();
+    }
+}
+
+impl Drop for SolidLayer {
+    fn drop(&mut self) {
+        self.viewport.destroy();
+        retire(&self.buffer.lock().unwrap());
+    }
+}
+
+/// Destroy the buffer once the compositor no longer uses it.
+fn retire(buffer: &WlBuffer) {
+    let Some(data) = buffer.data::<BufferData>() else {
+        return;
+    };
+
+    data.retired.store(true, Ordering::Relaxed);
+    if data.released.load(Ordering::Relaxed) {
+        buffer.destroy();
+    }
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, GlobalData, ContextDelegate>
+    for SinglePixelBufferState
+{
+    fn event(
+        _: &mut ContextDelegate,
+        _: &WpSinglePixelBufferManagerV1,
+        _: <WpSinglePixelBufferManagerV1 as Proxy>::Event,
+        _: &GlobalData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        // No events.
+    }
+}
+
+impl Dispatch<WlBuffer, BufferData, ContextDelegate> for SinglePixelBufferState {
+    fn event(
+        _: &mut ContextDelegate,
+        buffer: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        data: &BufferData,
+        _: &Connection,
+        _: &QueueHandle<ContextDelegate>,
+    ) {
+        if let BufferEvent::Release = event {
+            data.released.store(true, Ordering::Relaxed);
+            if data.retired.load(Ordering::Relaxed) {
+                buffer.destroy();
+            }
+        }
+    }
+}
+
+delegate_dispatch!(ContextDelegate: [WpSinglePixelBufferManagerV1: GlobalData] => SinglePixelBufferState);
+delegate_dispatch!(ContextDelegate: [WlBuffer: BufferData] => SinglePixelBufferState);
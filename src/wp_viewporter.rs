@@ -10,7 +10,7 @@ use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_vie
 use super::ContextDelegate;
 
 /// Viewporter.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ViewporterState {
     viewporter: WpViewporter,
 }